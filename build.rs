@@ -105,6 +105,16 @@ fn main() {
         .status()
         .unwrap();
 
+    Command::new("scripts/generate_syscalls.py")
+        .arg(path.join("syscall_arg_count_arch_x64_generated.rs"))
+        .status()
+        .unwrap();
+
+    Command::new("scripts/generate_syscalls.py")
+        .arg(path.join("syscall_arg_count_arch_x86_generated.rs"))
+        .status()
+        .unwrap();
+
     Command::new("scripts/generate_syscalls.py")
         .arg(path.join("syscall_helper_functions_generated.rs"))
         .status()
@@ -118,6 +128,7 @@ fn main() {
     println!("cargo:rerun-if-changed=scripts/generate_syscalls.py");
     println!("cargo:rerun-if-changed=scripts/assembly_templates.py");
     println!("cargo:rerun-if-changed=scripts/syscalls.py");
+    println!("cargo:rerun-if-changed=scripts/syscall_arg_counts.py");
 
     let perf_event_bindings = Builder::default()
         .parse_callbacks(Box::new(CargoCallbacks))
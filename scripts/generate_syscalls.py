@@ -6,6 +6,7 @@ from io import StringIO
 import os
 import string
 import sys
+import syscall_arg_counts
 import syscalls
 
 def arch_syscall_number(arch, syscall):
@@ -82,6 +83,25 @@ def write_syscallname_arch(f, arch):
     f.write("    }\n")
     f.write("}\n")
     f.write("\n")
+    f.write("/// (number, name) of every syscall available on this architecture\n")
+    f.write("pub const SYSCALL_NAMES: &[(i32, &str)] = &[\n")
+    for name, _ in syscalls.for_arch(arch):
+        f.write("    (%s, \"%s\"),\n" % (name.upper(), name))
+    f.write("];\n")
+    f.write("\n")
+
+def write_syscall_arg_count_arch(f, arch):
+    f.write("// This file has been autogenerated. DO NOT MODIFY!\n")
+    f.write("pub fn syscall_arg_count_arch(syscall: i32) -> Option<u8> {\n")
+    f.write("    match syscall {\n");
+    for name, _ in syscalls.for_arch(arch):
+        count = syscall_arg_counts.arg_count(arch, name)
+        if count is not None:
+            f.write("        %s => Some(%d),\n" % (name.upper(), count))
+    f.write("        _ => None,\n")
+    f.write("    }\n")
+    f.write("}\n")
+    f.write("\n")
 
 def write_syscall_record_cases(f):
     def write_recorder_for_arg(syscall, arg):
@@ -189,6 +209,8 @@ generators_for = {
     'syscall_consts_for_tests_x64_generated': lambda f: write_syscall_consts_for_tests(f, 'x64'),
     'syscall_name_arch_x86_generated': lambda f: write_syscallname_arch(f, 'x86'),
     'syscall_name_arch_x64_generated': lambda f: write_syscallname_arch(f, 'x64'),
+    'syscall_arg_count_arch_x86_generated': lambda f: write_syscall_arg_count_arch(f, 'x86'),
+    'syscall_arg_count_arch_x64_generated': lambda f: write_syscall_arg_count_arch(f, 'x64'),
     'syscall_record_case_generated': write_syscall_record_cases,
     'syscall_helper_functions_generated': write_syscall_helper_functions,
 }
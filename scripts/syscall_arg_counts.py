@@ -0,0 +1,124 @@
+"""Number of arguments taken by each syscall, from the SYSCALL_DEFINEn()
+definitions in the Linux kernel sources.
+
+Syscalls missing from these tables (e.g. obsolete or unimplemented ones) are
+reported as having an unknown argument count.
+"""
+
+ARG_COUNTS = {
+    'restart_syscall': 0, 'exit': 1, 'fork': 0, 'read': 3, 'write': 3, 'open': 3,
+    'close': 1, 'waitpid': 3, 'creat': 2, 'link': 2, 'unlink': 1, 'execve': 3,
+    'chdir': 1, 'time': 1, 'mknod': 3, 'chmod': 2, 'lchown': 3, 'lseek': 3,
+    'getpid': 0, 'mount': 5, 'umount': 1, 'setuid': 1, 'getuid': 0, 'stime': 1,
+    'ptrace': 4, 'alarm': 1, 'pause': 0, 'utime': 2, 'access': 2, 'nice': 1,
+    'sync': 0, 'kill': 2, 'rename': 2, 'mkdir': 2, 'rmdir': 1, 'dup': 1,
+    'pipe': 1, 'times': 1, 'brk': 1, 'setgid': 1, 'getgid': 0, 'signal': 2,
+    'geteuid': 0, 'getegid': 0, 'acct': 1, 'umount2': 2, 'ioctl': 3, 'fcntl': 3,
+    'setpgid': 2, 'olduname': 1, 'oldolduname': 1, 'umask': 1, 'chroot': 1,
+    'ustat': 2, 'dup2': 2, 'getppid': 0, 'getpgrp': 0, 'setsid': 0,
+    'sigaction': 3, 'sgetmask': 0, 'ssetmask': 1, 'setreuid': 2, 'setregid': 2,
+    'sigsuspend': 3, 'sigpending': 1, 'sethostname': 2, 'setrlimit': 2,
+    'getrlimit': 2, 'ugetrlimit': 2, 'getrusage': 2, 'gettimeofday': 2,
+    'settimeofday': 2, 'getgroups': 2, 'setgroups': 2, 'select': 5,
+    'symlink': 2, 'readlink': 3, 'uselib': 1, 'swapon': 2, 'reboot': 4,
+    'readdir': 3, 'mmap': 6, 'munmap': 2, 'truncate': 2, 'ftruncate': 2,
+    'fchmod': 2, 'fchown': 3, 'getpriority': 2, 'setpriority': 3, 'statfs': 2,
+    'fstatfs': 2, 'ioperm': 3, 'socketcall': 2, 'syslog': 3, 'setitimer': 3,
+    'getitimer': 2, 'stat': 2, 'lstat': 2, 'fstat': 2, 'iopl': 1, 'vhangup': 0,
+    'wait4': 4, 'swapoff': 1, 'sysinfo': 1, 'ipc': 6, 'fsync': 1,
+    'sigreturn': 0, 'clone': 5, 'setdomainname': 2, 'uname': 1,
+    'modify_ldt': 3, 'adjtimex': 1, 'mprotect': 3, 'sigprocmask': 3,
+    'init_module': 3, 'delete_module': 2, 'quotactl': 4, 'getpgid': 1,
+    'fchdir': 1, 'sysfs': 3, 'personality': 1, 'setfsuid': 1, 'setfsgid': 1,
+    '_llseek': 5, 'getdents': 3, '_newselect': 5, 'flock': 2, 'msync': 3,
+    'readv': 3, 'writev': 3, 'getsid': 1, 'fdatasync': 1, '_sysctl': 1,
+    'mlock': 2, 'munlock': 2, 'mlockall': 1, 'munlockall': 0,
+    'sched_setparam': 2, 'sched_getparam': 2, 'sched_setscheduler': 3,
+    'sched_getscheduler': 1, 'sched_yield': 0, 'sched_get_priority_max': 1,
+    'sched_get_priority_min': 1, 'sched_rr_get_interval': 2, 'nanosleep': 2,
+    'mremap': 5, 'setresuid': 3, 'getresuid': 3, 'poll': 3, 'setresgid': 3,
+    'getresgid': 3, 'prctl': 5, 'rt_sigreturn': 0, 'rt_sigaction': 4,
+    'rt_sigprocmask': 4, 'rt_sigpending': 2, 'rt_sigtimedwait': 4,
+    'rt_sigqueueinfo': 3, 'rt_sigsuspend': 2, 'pread64': 4, 'pwrite64': 4,
+    'chown': 3, 'getcwd': 2, 'capget': 2, 'capset': 2, 'sigaltstack': 2,
+    'sendfile': 4, 'vfork': 0, 'mmap2': 6, 'truncate64': 2, 'ftruncate64': 2,
+    'stat64': 2, 'lstat64': 2, 'fstat64': 2, 'lchown32': 3, 'getuid32': 0,
+    'getgid32': 0, 'geteuid32': 0, 'getegid32': 0, 'setreuid32': 2,
+    'setregid32': 2, 'getgroups32': 2, 'setgroups32': 2, 'fchown32': 3,
+    'setresuid32': 3, 'getresuid32': 3, 'setresgid32': 3, 'getresgid32': 3,
+    'chown32': 3, 'setuid32': 1, 'setgid32': 1, 'setfsuid32': 1,
+    'setfsgid32': 1, 'pivot_root': 2, 'mincore': 3, 'madvise': 3,
+    'getdents64': 3, 'fcntl64': 3, 'gettid': 0, 'readahead': 3,
+    'setxattr': 5, 'lsetxattr': 5, 'fsetxattr': 5, 'getxattr': 4,
+    'lgetxattr': 4, 'fgetxattr': 4, 'listxattr': 3, 'llistxattr': 3,
+    'flistxattr': 3, 'removexattr': 2, 'lremovexattr': 2, 'fremovexattr': 2,
+    'tkill': 2, 'sendfile64': 4, 'futex': 6, 'sched_setaffinity': 3,
+    'sched_getaffinity': 3, 'set_thread_area': 1, 'get_thread_area': 1,
+    'io_setup': 2, 'io_destroy': 1, 'io_getevents': 5, 'io_submit': 3,
+    'io_cancel': 3, 'fadvise64': 4, 'exit_group': 1, 'lookup_dcookie': 3,
+    'epoll_create': 1, 'epoll_ctl': 4, 'epoll_wait': 4,
+    'remap_file_pages': 5, 'set_tid_address': 1, 'timer_create': 3,
+    'timer_settime': 4, 'timer_gettime': 2, 'timer_getoverrun': 1,
+    'timer_delete': 1, 'clock_settime': 2, 'clock_gettime': 2,
+    'clock_getres': 2, 'clock_nanosleep': 4, 'statfs64': 3, 'fstatfs64': 3,
+    'tgkill': 3, 'utimes': 2, 'fadvise64_64': 4, 'mbind': 6,
+    'get_mempolicy': 5, 'set_mempolicy': 3, 'mq_open': 4, 'mq_unlink': 1,
+    'mq_timedsend': 5, 'mq_timedreceive': 5, 'mq_notify': 2,
+    'mq_getsetattr': 3, 'kexec_load': 4, 'waitid': 5, 'add_key': 5,
+    'request_key': 4, 'keyctl': 5, 'ioprio_set': 3, 'ioprio_get': 2,
+    'inotify_init': 0, 'inotify_add_watch': 3, 'inotify_rm_watch': 2,
+    'migrate_pages': 4, 'openat': 4, 'mkdirat': 3, 'mknodat': 4,
+    'fchownat': 5, 'futimesat': 3, 'fstatat64': 4, 'newfstatat': 4,
+    'unlinkat': 3, 'renameat': 4, 'linkat': 5, 'symlinkat': 3,
+    'readlinkat': 4, 'fchmodat': 3, 'faccessat': 3, 'pselect6': 6,
+    'ppoll': 5, 'unshare': 1, 'set_robust_list': 2, 'get_robust_list': 3,
+    'splice': 6, 'sync_file_range': 4, 'tee': 4, 'vmsplice': 4,
+    'move_pages': 6, 'getcpu': 3, 'epoll_pwait': 6, 'utimensat': 4,
+    'signalfd': 3, 'timerfd_create': 2, 'eventfd': 1, 'fallocate': 4,
+    'timerfd_settime': 4, 'timerfd_gettime': 2, 'signalfd4': 4,
+    'eventfd2': 2, 'epoll_create1': 1, 'dup3': 3, 'pipe2': 2,
+    'inotify_init1': 1, 'preadv': 5, 'pwritev': 5, 'rt_tgsigqueueinfo': 4,
+    'perf_event_open': 5, 'recvmmsg': 5, 'fanotify_init': 2,
+    'fanotify_mark': 5, 'prlimit64': 4, 'name_to_handle_at': 5,
+    'open_by_handle_at': 3, 'clock_adjtime': 2, 'syncfs': 1,
+    'sendmmsg': 4, 'setns': 2, 'process_vm_readv': 6,
+    'process_vm_writev': 6, 'kcmp': 5, 'finit_module': 3,
+    'sched_setattr': 3, 'sched_getattr': 4, 'renameat2': 5, 'seccomp': 3,
+    'getrandom': 3, 'memfd_create': 2, 'arch_prctl': 2, 'bpf': 3,
+    'execveat': 5, 'userfaultfd': 1, 'membarrier': 3, 'mlock2': 3,
+    'copy_file_range': 6, 'preadv2': 6, 'pwritev2': 6, 'pkey_mprotect': 4,
+    'pkey_alloc': 2, 'pkey_free': 1, 'statx': 5, 'io_pgetevents': 6,
+    'rseq': 4, 'clock_gettime64': 2, 'clock_settime64': 2,
+    'clock_adjtime64': 2, 'clock_getres_time64': 2,
+    'clock_nanosleep_time64': 4, 'timer_gettime64': 2,
+    'timer_settime64': 4, 'timerfd_gettime64': 2, 'timerfd_settime64': 4,
+    'utimensat_time64': 4, 'pselect6_time64': 6, 'ppoll_time64': 5,
+    'io_pgetevents_time64': 6, 'recvmmsg_time64': 5,
+    'mq_timedsend_time64': 5, 'mq_timedreceive_time64': 5,
+    'semtimedop_time64': 4, 'rt_sigtimedwait_time64': 4,
+    'futex_time64': 6, 'sched_rr_get_interval_time64': 2,
+    'pidfd_send_signal': 4, 'io_uring_setup': 2, 'io_uring_enter': 6,
+    'io_uring_register': 4, 'open_tree': 3, 'move_mount': 5, 'fsopen': 2,
+    'fsconfig': 5, 'fsmount': 3, 'fspick': 3, 'socket': 3, 'connect': 3,
+    'accept': 3, 'sendto': 6, 'recvfrom': 6, 'sendmsg': 3, 'recvmsg': 3,
+    'shutdown': 2, 'bind': 3, 'listen': 2, 'getsockname': 3,
+    'getpeername': 3, 'socketpair': 4, 'setsockopt': 5, 'getsockopt': 5,
+    'accept4': 4, 'shmget': 3, 'shmat': 3, 'shmctl': 3, 'semget': 3,
+    'semop': 3, 'semctl': 4, 'shmdt': 1, 'msgget': 2, 'msgsnd': 4,
+    'msgrcv': 5, 'msgctl': 3, 'semtimedop': 4,
+}
+
+# On x86 some syscalls take their arguments in a struct in memory and 64-bit
+# arguments are split over two registers.
+X86_OVERRIDES = {
+    'mmap': 1, 'select': 1, 'pread64': 5, 'pwrite64': 5, 'truncate64': 3,
+    'ftruncate64': 3, 'readahead': 4, 'fadvise64': 5, 'fadvise64_64': 6,
+    'lookup_dcookie': 4, 'sync_file_range': 6, 'fallocate': 6,
+}
+
+def arg_count(arch, name):
+    """Returns the number of arguments of syscall `name` on `arch` or None
+    if unknown."""
+    if arch == 'x86' and name in X86_OVERRIDES:
+        return X86_OVERRIDES[name]
+    return ARG_COUNTS.get(name)
@@ -1,6 +1,7 @@
 #![allow(non_camel_case_types)]
 
 use crate::{
+    gdb_register::GdbRegister,
     kernel_abi::{
         x64,
         x86,
@@ -11,6 +12,8 @@ use crate::{
         SelectCallingSemantics,
         SupportedArch,
     },
+    kernel_metadata::{syscall_name_to_number, syscall_number_to_name},
+    registers::gdb_register_by_name,
     remote_ptr::{RemotePtr, Void},
 };
 use std::{
@@ -663,6 +666,15 @@ pub trait Architecture: 'static + Default {
 
     fn arch() -> SupportedArch;
 
+    /// DIFF NOTE: Not present in rr. See `Registers::read_by_name()`
+    fn gdb_register_by_name(name: &str) -> Option<GdbRegister>;
+
+    /// DIFF NOTE: Not present in rr. See `kernel_metadata::syscall_number_to_name()`
+    fn syscall_number_to_name(n: i64) -> Option<&'static str>;
+
+    /// DIFF NOTE: Not present in rr. See `kernel_metadata::syscall_name_to_number()`
+    fn syscall_name_to_number(name: &str) -> Option<i64>;
+
     fn set_iovec(msgdata: &mut Self::iovec, iov_base: RemotePtr<Void>, iov_len: usize);
 
     fn as_signed_short(ss: i16) -> Self::signed_short;
@@ -1250,6 +1262,18 @@ impl Architecture for X86Arch {
         SupportedArch::X86
     }
 
+    fn gdb_register_by_name(name: &str) -> Option<GdbRegister> {
+        gdb_register_by_name(SupportedArch::X86, name).map(|(regno, _)| regno)
+    }
+
+    fn syscall_number_to_name(n: i64) -> Option<&'static str> {
+        syscall_number_to_name(SupportedArch::X86, n)
+    }
+
+    fn syscall_name_to_number(name: &str) -> Option<i64> {
+        syscall_name_to_number(SupportedArch::X86, name)
+    }
+
     fn set_iovec(msgdata: &mut Self::iovec, iov_base: RemotePtr<u8>, iov_len: usize) {
         msgdata.iov_base = iov_base.into();
         msgdata.iov_len = iov_len.try_into().unwrap();
@@ -1879,6 +1903,18 @@ impl Architecture for X64Arch {
         SupportedArch::X64
     }
 
+    fn gdb_register_by_name(name: &str) -> Option<GdbRegister> {
+        gdb_register_by_name(SupportedArch::X64, name).map(|(regno, _)| regno)
+    }
+
+    fn syscall_number_to_name(n: i64) -> Option<&'static str> {
+        syscall_number_to_name(SupportedArch::X64, n)
+    }
+
+    fn syscall_name_to_number(name: &str) -> Option<i64> {
+        syscall_name_to_number(SupportedArch::X64, name)
+    }
+
     fn set_iovec(msgdata: &mut Self::iovec, iov_base: RemotePtr<u8>, iov_len: usize) {
         msgdata.iov_base = iov_base.into();
         msgdata.iov_len = iov_len as _;
@@ -627,7 +627,7 @@ impl<'a> AutoRemoteSyscalls<'a> {
         for (i, arg) in args.iter().enumerate() {
             // Syscall argument are indexed from 1 onwards and not 0.
             // e.g. arg 1, arg 2, arg 3 etc.
-            callregs.set_arg(i + 1, *arg);
+            callregs.set_arg_unchecked(i + 1, *arg);
         }
         self.syscall_base(syscallno, &mut callregs)
     }
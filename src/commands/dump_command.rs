@@ -4,6 +4,7 @@ use crate::{
         rd_options::{RdOptions, RdSubCommand},
         RdCommand,
     },
+    error::exit_on_error,
     event::EventType,
     kernel_metadata::syscall_name,
     log::notifying_abort,
@@ -69,7 +70,7 @@ impl DumpCommand {
     }
 
     pub fn dump(&self, f: &mut dyn Write) -> io::Result<()> {
-        let mut trace = TraceReader::new(self.trace_dir.as_ref());
+        let mut trace = exit_on_error(TraceReader::open_with_migration(self.trace_dir.as_ref()));
 
         if self.raw_dump {
             write!(
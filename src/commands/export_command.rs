@@ -0,0 +1,45 @@
+use super::exit_result::ExitResult;
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    error::exit_on_error,
+    trace::trace_reader::TraceReader,
+};
+use std::{
+    io,
+    io::{stdout, BufWriter, Write},
+    path::PathBuf,
+};
+
+/// DIFF NOTE: Not present in rr
+pub struct ExportCommand {
+    trace_dir: Option<PathBuf>,
+}
+
+impl ExportCommand {
+    pub fn new(options: &RdOptions) -> ExportCommand {
+        match options.cmd.clone() {
+            // jsonl is the only format so `format` needs no further handling
+            RdSubCommand::Export { trace_dir, .. } => ExportCommand { trace_dir },
+            _ => panic!("Unexpected RdSubCommand variant. Not an `Export` variant!"),
+        }
+    }
+
+    fn export(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut trace = exit_on_error(TraceReader::open_with_migration(self.trace_dir.as_ref()));
+        let mut out = BufWriter::new(out);
+        trace.export_jsonl(&mut out)?;
+        out.flush()
+    }
+}
+
+impl RdCommand for ExportCommand {
+    fn run(&mut self) -> ExitResult<()> {
+        match self.export(&mut stdout()) {
+            Ok(()) => ExitResult::Ok(()),
+            Err(e) => ExitResult::err_from(e, 1),
+        }
+    }
+}
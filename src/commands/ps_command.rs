@@ -4,6 +4,7 @@ use crate::{
         rd_options::{RdOptions, RdSubCommand},
         RdCommand,
     },
+    error::exit_on_error,
     trace::{
         trace_reader::TraceReader,
         trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
@@ -45,7 +46,7 @@ type TidPidMap = HashMap<pid_t, pid_t>;
 
 impl PsCommand {
     fn ps(&mut self, out: &mut dyn Write) -> io::Result<()> {
-        let mut trace = TraceReader::new(self.trace_dir.as_ref());
+        let mut trace = exit_on_error(TraceReader::open_with_migration(self.trace_dir.as_ref()));
         write!(out, "PID\tPPID\tEXIT\tCMD\n")?;
 
         let mut events: Vec<TraceTaskEvent> = Vec::new();
@@ -73,6 +73,13 @@ pub struct RdOptions {
     )]
     pub check_cached_mmaps: bool,
 
+    #[structopt(
+        long = "canonicalize-registers",
+        help = "Ignore reserved EFLAGS bits and the upper bits of segment registers when \
+        comparing recorded and replayed registers."
+    )]
+    pub canonicalize_registers: bool,
+
     #[structopt(
         short = "E",
         long = "fatal-errors",
@@ -461,6 +468,15 @@ pub enum RdSubCommand {
         #[structopt(long = "copy-preload-src")]
         copy_preload_src: bool,
 
+        /// Compress the trace with zstd at the given level instead of brotli
+        #[structopt(long = "zstd")]
+        zstd_level: Option<i32>,
+
+        /// Write a frame index entry every N frames to speed up seeking during
+        /// replay. 0 disables the index
+        #[structopt(long = "index-interval")]
+        frame_index_interval: Option<u64>,
+
         /// Program being recorded
         exe: OsString,
 
@@ -525,6 +541,26 @@ pub enum RdSubCommand {
         /// Which directory is the trace data in? If omitted the latest trace dir is used
         trace_dir: Option<PathBuf>,
     },
+
+    /// Export the frames, mmaps and task events of a trace for analysis with
+    /// external tools.
+    #[structopt(name = "export")]
+    Export {
+        /// Output format. Only `jsonl` (one JSON object per line) is supported
+        #[structopt(long = "format", default_value = "jsonl", possible_values = &["jsonl"])]
+        format: String,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Print the event count, task count, peak memory usage and CPU time of a
+    /// recording.
+    #[structopt(name = "stats")]
+    Stats {
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
 }
 
 fn parse_env_name_val(maybe_name_val: &OsStr) -> Result<(OsString, OsString), OsString> {
@@ -18,6 +18,7 @@ use crate::{
     sig,
     sig::Sig,
     ticks::Ticks,
+    trace::{compressed_writer::Compression, trace_stream::DEFAULT_FRAME_INDEX_INTERVAL},
     util::{check_for_leaks, page_size, running_under_rd, write_all, BindCPU},
     wait_status::{WaitStatus, WaitType},
 };
@@ -98,6 +99,12 @@ pub struct RecordCommand {
     /// The signal to use for syscallbuf desched events
     pub syscallbuf_desched_sig: Sig,
 
+    /// How the trace substreams are compressed
+    pub compression: Compression,
+
+    /// Write a frame index entry every this many frames. 0 disables the index.
+    pub frame_index_interval: u64,
+
     // The exe and exe_args
     pub args: Vec<OsString>,
 }
@@ -142,6 +149,8 @@ impl RecordCommand {
                 setuid_sudo,
                 trace_id,
                 copy_preload_src,
+                zstd_level,
+                frame_index_interval,
             } => RecordCommand {
                 extra_env: env.unwrap_or(Vec::new()),
                 max_ticks: num_cpu_ticks.unwrap_or(TicksHowMany::DefaultMaxTicks as u64),
@@ -207,6 +216,11 @@ impl RecordCommand {
                 trace_id: Box::new(trace_id.unwrap_or(TraceUuid::generate_new())),
                 copy_preload_src,
                 syscallbuf_desched_sig: syscall_buffer_sig.unwrap_or(sig::SIGPWR),
+                compression: match zstd_level {
+                    Some(level) => Compression::Zstd(level),
+                    None => Compression::Brotli,
+                },
+                frame_index_interval: frame_index_interval.unwrap_or(DEFAULT_FRAME_INDEX_INTERVAL),
                 args: {
                     let mut args = Vec::new();
                     args.push(exe);
@@ -0,0 +1,56 @@
+use super::exit_result::ExitResult;
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    error::exit_on_error,
+    session::session_inner::SessionStatistics,
+    trace::trace_reader::TraceReader,
+};
+use std::{
+    io,
+    io::{stdout, Write},
+    path::PathBuf,
+};
+
+/// DIFF NOTE: Not present in rr
+pub struct StatsCommand {
+    trace_dir: Option<PathBuf>,
+}
+
+impl StatsCommand {
+    pub fn new(options: &RdOptions) -> StatsCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Stats { trace_dir } => StatsCommand { trace_dir },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Stats` variant!"),
+        }
+    }
+
+    fn stats(&self, out: &mut dyn Write) -> io::Result<()> {
+        let trace = exit_on_error(TraceReader::open_with_migration(self.trace_dir.as_ref()));
+        let stats = match SessionStatistics::from_metadata(trace.metadata()) {
+            Some(stats) => stats,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No statistics in trace metadata. Was the trace recorded by an older rd?",
+                ))
+            }
+        };
+        writeln!(out, "events: {}", stats.event_count)?;
+        writeln!(out, "tasks: {}", stats.task_count)?;
+        writeln!(out, "peak_rss_bytes: {}", stats.peak_rss_bytes)?;
+        writeln!(out, "elapsed_wall_ns: {}", stats.elapsed_wall_ns)?;
+        writeln!(out, "elapsed_cpu_ns: {}", stats.elapsed_cpu_ns)
+    }
+}
+
+impl RdCommand for StatsCommand {
+    fn run(&mut self) -> ExitResult<()> {
+        match self.stats(&mut stdout()) {
+            Ok(()) => ExitResult::Ok(()),
+            Err(e) => ExitResult::err_from(e, 1),
+        }
+    }
+}
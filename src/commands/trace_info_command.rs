@@ -4,6 +4,7 @@ use crate::{
         rd_options::{RdOptions, RdSubCommand},
         RdCommand,
     },
+    error::exit_on_error,
     perf_counters::TicksSemantics,
     session::{
         replay_session::{Flags, ReplaySession, ReplayStatus},
@@ -42,7 +43,7 @@ struct TraceHeader {
 
 impl RdCommand for TraceInfoCommand {
     fn run(&mut self) -> ExitResult<()> {
-        let trace = TraceReader::new(self.trace_dir.as_ref());
+        let trace = exit_on_error(TraceReader::open_with_migration(self.trace_dir.as_ref()));
 
         let uuid_bytes = trace.uuid().bytes;
         let xcr0 = trace.xcr0();
@@ -0,0 +1,100 @@
+use bit_field::BitField;
+
+/// Number of x86 debug registers: DR0-DR7. DR4 and DR5 are reserved.
+pub const NUM_DEBUG_REGISTERS: usize = 8;
+/// DR0-DR3 hold breakpoint/watchpoint addresses
+pub const NUM_DEBUG_ADDRESS_REGISTERS: usize = 4;
+
+/// The R/W field of DR7 for a breakpoint
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DrCondition {
+    Exec = 0x00,
+    Write = 0x01,
+    /// Only when CR4.DE is set
+    Io = 0x02,
+    ReadWrite = 0x03,
+}
+
+/// The LEN field of DR7 for a breakpoint
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DrLength {
+    Bytes1 = 0x00,
+    Bytes2 = 0x01,
+    Bytes8 = 0x02,
+    Bytes4 = 0x03,
+}
+
+/// The values of the x86 debug registers DR0-DR7 of a task.
+/// See `TaskInner::read_debug_registers()`
+/// DIFF NOTE: Not present in rr
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DebugRegisters([usize; NUM_DEBUG_REGISTERS]);
+
+impl DebugRegisters {
+    pub fn new(values: [usize; NUM_DEBUG_REGISTERS]) -> DebugRegisters {
+        DebugRegisters(values)
+    }
+
+    /// The value of DR`n`
+    pub fn get(&self, n: u8) -> usize {
+        self.0[n as usize]
+    }
+
+    /// Set DR`n` to `value`
+    pub fn set(&mut self, n: u8, value: usize) {
+        self.0[n as usize] = value;
+    }
+
+    /// Is breakpoint `n` (i.e. DR`n`, `n` < 4) locally or globally enabled in DR7?
+    pub fn breakpoint_enabled(&self, n: u8) -> bool {
+        debug_assert!((n as usize) < NUM_DEBUG_ADDRESS_REGISTERS);
+        let i = 2 * n as usize;
+        self.0[7].get_bits(i..i + 2) != 0
+    }
+
+    /// Program DR`n` (`n` < 4) with `addr` and locally enable it in DR7
+    pub fn set_breakpoint(&mut self, n: u8, addr: usize, condition: DrCondition, length: DrLength) {
+        debug_assert!((n as usize) < NUM_DEBUG_ADDRESS_REGISTERS);
+        self.0[n as usize] = addr;
+        let i = 2 * n as usize;
+        let dr7 = &mut self.0[7];
+        dr7.set_bit(i, true);
+        dr7.set_bit(i + 1, false);
+        dr7.set_bits(16 + 4 * n as usize..18 + 4 * n as usize, condition as usize);
+        dr7.set_bits(18 + 4 * n as usize..20 + 4 * n as usize, length as usize);
+    }
+
+    /// Disable breakpoint `n` (`n` < 4) in DR7 and clear DR`n`
+    pub fn clear_breakpoint(&mut self, n: u8) {
+        debug_assert!((n as usize) < NUM_DEBUG_ADDRESS_REGISTERS);
+        self.0[n as usize] = 0;
+        let i = 2 * n as usize;
+        let dr7 = &mut self.0[7];
+        dr7.set_bits(i..i + 2, 0);
+        dr7.set_bits(16 + 4 * n as usize..20 + 4 * n as usize, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_breakpoint_test() {
+        let mut d = DebugRegisters::default();
+        assert!(!d.breakpoint_enabled(1));
+
+        d.set_breakpoint(1, 0x1000, DrCondition::Write, DrLength::Bytes4);
+        assert!(d.breakpoint_enabled(1));
+        assert!(!d.breakpoint_enabled(0));
+        assert_eq!(0x1000, d.get(1));
+        // L1, R/W1 = 01, LEN1 = 11
+        assert_eq!((1 << 2) | (0x1 << 20) | (0x3 << 22), d.get(7));
+
+        d.clear_breakpoint(1);
+        assert!(!d.breakpoint_enabled(1));
+        assert_eq!(DebugRegisters::default(), d);
+    }
+}
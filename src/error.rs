@@ -0,0 +1,126 @@
+//! DIFF NOTE: Not present in rr. rr (and most of rd) reports unrecoverable problems via
+//! `fatal!()`, which aborts the process. `RdError` is for code paths that are being migrated to
+//! return errors to their callers instead. Binaries can still turn an `RdError` into an exit
+//! with `exit_on_error()`.
+
+use crate::{
+    bindings::sysexits::EX_DATAERR,
+    kernel_abi::SupportedArch,
+    kernel_metadata::errno_name,
+    registers::RegisterDiff,
+};
+use libc::pid_t;
+use std::{error::Error, ffi::OsString, fmt, io, process::exit};
+
+pub type RdResult<T> = Result<T, RdError>;
+
+#[derive(Debug)]
+pub enum RdError {
+    /// The trace at `path` could not be used.
+    TraceCorrupted { path: OsString, reason: String },
+    /// The trace has version `found` but this rd needs `required` and the trace can't be
+    /// migrated. See `trace_migration`
+    IncompatibleTraceVersion { found: u32, required: u32 },
+    /// Registers differ from what was recorded.
+    RegisterMismatch(Vec<RegisterDiff>),
+    UnsupportedArch(SupportedArch),
+    PtraceFailed {
+        pid: pid_t,
+        op: &'static str,
+        errno: i32,
+    },
+    IoError(io::Error),
+}
+
+impl RdError {
+    pub fn trace_corrupted<P: Into<OsString>, R: Into<String>>(path: P, reason: R) -> RdError {
+        RdError::TraceCorrupted {
+            path: path.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for RdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RdError::TraceCorrupted { path, reason } => write!(f, "Trace {:?}: {}", path, reason),
+            RdError::IncompatibleTraceVersion { found, required } => write!(
+                f,
+                "Recorded trace has an incompatible version {}; expected {}. Did you record it \
+                 with a different version of rd? If so, you'll need to replay it with that \
+                 version. Otherwise, your trace is likely corrupted.",
+                found, required
+            ),
+            RdError::RegisterMismatch(diffs) => {
+                write!(f, "{} register(s) differ:", diffs.len())?;
+                for d in diffs {
+                    write!(f, " {} 0x{:x} != 0x{:x};", d.name, d.recorded, d.live)?;
+                }
+                Ok(())
+            }
+            RdError::UnsupportedArch(arch) => write!(f, "Unsupported architecture {:?}", arch),
+            RdError::PtraceFailed { pid, op, errno } => write!(
+                f,
+                "ptrace({}) on {} failed: {}",
+                op,
+                pid,
+                errno_name(*errno)
+            ),
+            RdError::IoError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for RdError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RdError::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RdError {
+    fn from(e: io::Error) -> Self {
+        RdError::IoError(e)
+    }
+}
+
+/// Unwrap `res` or print the error and exit. For use by commands, not library code.
+pub fn exit_on_error<T>(res: RdResult<T>) -> T {
+    match res {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("\nrd: {}\n", e);
+            exit(EX_DATAERR as i32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_test() {
+        let e = RdError::trace_corrupted("/tmp/trace", "Invalid UUID length");
+        assert_eq!("Trace \"/tmp/trace\": Invalid UUID length", e.to_string());
+
+        let e = RdError::RegisterMismatch(vec![RegisterDiff {
+            name: "rax",
+            recorded: 1,
+            live: 2,
+            mask: !0,
+        }]);
+        assert_eq!("1 register(s) differ: rax 0x1 != 0x2;", e.to_string());
+    }
+
+    #[test]
+    fn from_io_error_test() {
+        let e: RdError = io::Error::new(io::ErrorKind::NotFound, "gone").into();
+        assert!(matches!(e, RdError::IoError(_)));
+        assert!(e.source().is_some());
+        assert_eq!("gone", e.to_string());
+    }
+}
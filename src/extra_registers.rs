@@ -8,6 +8,8 @@ use crate::{
 };
 use std::{convert::TryInto, fmt::Write, io, mem::size_of, ptr::copy_nonoverlapping};
 
+const X87_FEATURE_BIT: usize = 0;
+const SSE_FEATURE_BIT: usize = 1;
 const AVX_FEATURE_BIT: usize = 2;
 
 const XSAVE_HEADER_OFFSET: usize = 512;
@@ -16,6 +18,8 @@ const XSAVE_HEADER_END: usize = XSAVE_HEADER_OFFSET + XSAVE_HEADER_SIZE;
 /// This is always at 576 since AVX is always the first optional feature,
 /// if present.
 const AVX_XSAVE_OFFSET: usize = 576;
+/// YMM0H-YMM15H
+const AVX_XSAVE_SIZE: usize = 256;
 
 /// This is the byte offset at which the ST0-7 register data begins
 /// with an xsave (or fxsave) block.
@@ -49,6 +53,13 @@ const FXSAVE_387_CTRL_OFFSETS: [usize; 8] = [
 
 const XINUSE_OFFSET: usize = 512;
 
+/// Size of the legacy FXSAVE area at the start of an (f)xsave block.
+const FXSAVE_SIZE: usize = 512;
+/// Byte offset of MXCSR within (f)xsave.
+const MXCSR_OFFSET: usize = 24;
+/// Each STx register holds 10 bytes of actual data. See `ST_REG_SPACE`.
+const ST_REG_SIZE: usize = 10;
+
 /// On a x86 64-bit kernel, these structures are initialized by an XSAVE64 or
 /// FXSAVE64.
 /// On a x86 32-bit kernel, they are initialized by an XSAVE or FXSAVE.
@@ -312,6 +323,73 @@ impl ExtraRegisters {
         Some(reg_data.size)
     }
 
+    /// Like `Registers::write_register()`, except attempts to write the value
+    /// of an "extra register" (floating point / vector).
+    /// Returns false if the register can't be written.
+    /// DIFF NOTE: Not present in rr
+    pub fn write_register(&mut self, value: &[u8], regno: GdbRegister) -> bool {
+        if self.format_ != Format::XSave {
+            return false;
+        }
+
+        let reg_data = xsave_register_data(self.arch_, regno);
+        let off = match reg_data.offset {
+            Some(off) => off,
+            None => return false,
+        };
+        if value.len() != reg_data.size || off + reg_data.size > self.data_.len() {
+            return false;
+        }
+
+        if let Some(feature_bit) = reg_data.xsave_feature_bit {
+            let features = xsave_features(&self.data_);
+            if features & (1 << feature_bit) == 0 {
+                // The component is in its initial (all zero) state. Its area in
+                // the XSAVE data may contain garbage so clear it before marking
+                // the component as in use.
+                if feature_bit != AVX_FEATURE_BIT
+                    || self.data_.len() < AVX_XSAVE_OFFSET + AVX_XSAVE_SIZE
+                {
+                    return false;
+                }
+                for b in &mut self.data_[AVX_XSAVE_OFFSET..AVX_XSAVE_OFFSET + AVX_XSAVE_SIZE] {
+                    *b = 0;
+                }
+                let features = features | (1 << feature_bit);
+                self.data_[XSAVE_HEADER_OFFSET..XSAVE_HEADER_OFFSET + 8]
+                    .copy_from_slice(&features.to_le_bytes());
+            }
+        }
+
+        self.data_[off..off + reg_data.size].copy_from_slice(value);
+        true
+    }
+
+    /// The XMM and YMM (upper half) registers whose values differ between
+    /// `self` and `other`.
+    /// DIFF NOTE: Not present in rr
+    pub fn simd_register_mismatches(&self, other: &ExtraRegisters) -> Vec<GdbRegister> {
+        debug_assert_eq!(self.arch_, other.arch_);
+        let (xmm0, ymm0h, count) = match self.arch_ {
+            X86 => (DREG_XMM0, DREG_YMM0H, 8u32),
+            X64 => (DREG_64_XMM0, DREG_64_YMM0H, 16u32),
+        };
+        let mut mismatches = Vec::new();
+        for &base in &[xmm0, ymm0h] {
+            for i in 0..count {
+                let regno = (base + i).unwrap();
+                let mut buf1 = [0u8; 16];
+                let mut buf2 = [0u8; 16];
+                let size1 = self.read_register(&mut buf1, regno);
+                let size2 = other.read_register(&mut buf2, regno);
+                if size1 != size2 || buf1 != buf2 {
+                    mismatches.push(regno);
+                }
+            }
+        }
+        mismatches
+    }
+
     /// Get a user_fpregs_struct for a particular Arch from these ExtraRegisters.
     pub fn get_user_fpregs_struct(&self, arch: SupportedArch) -> Vec<u8> {
         debug_assert_eq!(self.format_, Format::XSave);
@@ -416,6 +494,30 @@ impl ExtraRegisters {
         }
     }
 
+    /// The FPU/SSE part of these registers. Returns `None` if there is no
+    /// (f)xsave data.
+    /// DIFF NOTE: Not present in rr
+    pub fn fpu(&self) -> Option<FpuRegisters> {
+        if self.format_ != Format::XSave {
+            return None;
+        }
+        FpuRegisters::from_fxsave(self.arch_, &self.data_)
+    }
+
+    /// Replace the FPU/SSE part of these registers. Returns false if there is
+    /// no (f)xsave data or the archs differ.
+    /// DIFF NOTE: Not present in rr
+    pub fn set_fpu(&mut self, fpu: &FpuRegisters) -> bool {
+        if self.format_ != Format::XSave
+            || self.arch_ != fpu.arch_
+            || self.data_.len() < FXSAVE_SIZE
+        {
+            return false;
+        }
+        self.data_[0..FXSAVE_SIZE].copy_from_slice(&fpu.data_);
+        true
+    }
+
     pub fn write_register_file_compact(&self, f: &mut dyn io::Write) -> io::Result<()> {
         match self.arch_ {
             X86 => {
@@ -482,6 +584,148 @@ impl ExtraRegisters {
     }
 }
 
+/// The x87 FPU and SSE state i.e. the FXSAVE area, which has the same layout
+/// as a x64 user_fpregs_struct and a x86 user_fpxregs_struct.
+/// DIFF NOTE: Not present in rr
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FpuRegisters {
+    arch_: SupportedArch,
+    data_: Vec<u8>,
+}
+
+/// See `FpuRegisters::compare()`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FpuRegisterDiff {
+    pub regno: GdbRegister,
+    pub value1: Vec<u8>,
+    pub value2: Vec<u8>,
+}
+
+impl FpuRegisters {
+    /// Returns `None` if `data` is shorter than the FXSAVE area
+    pub fn from_fxsave(arch: SupportedArch, data: &[u8]) -> Option<FpuRegisters> {
+        if data.len() < FXSAVE_SIZE {
+            return None;
+        }
+        Some(FpuRegisters {
+            arch_: arch,
+            data_: data[0..FXSAVE_SIZE].to_vec(),
+        })
+    }
+
+    pub fn arch(&self) -> SupportedArch {
+        self.arch_
+    }
+
+    pub fn data_bytes(&self) -> &[u8] {
+        &self.data_
+    }
+
+    /// FPU control word
+    pub fn fcw(&self) -> u16 {
+        u16::from_le_bytes(self.data_[0..2].try_into().unwrap())
+    }
+
+    /// FPU status word
+    pub fn fsw(&self) -> u16 {
+        u16::from_le_bytes(self.data_[2..4].try_into().unwrap())
+    }
+
+    /// Abridged FPU tag word
+    pub fn ftw(&self) -> u8 {
+        self.data_[4]
+    }
+
+    pub fn mxcsr(&self) -> u32 {
+        u32::from_le_bytes(
+            self.data_[MXCSR_OFFSET..MXCSR_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    /// ST(i), `i` < 8
+    pub fn st(&self, i: usize) -> &[u8] {
+        let off = ST_REGS_OFFSET + i * ST_REG_SPACE;
+        &self.data_[off..off + ST_REG_SIZE]
+    }
+
+    /// XMMi, `i` < `xmm_register_count()`
+    pub fn xmm(&self, i: usize) -> &[u8] {
+        let off = XMM_REGS_OFFSET + i * XMM_REG_SPACE;
+        &self.data_[off..off + XMM_REG_SPACE]
+    }
+
+    pub fn xmm_register_count(&self) -> usize {
+        match self.arch_ {
+            X86 => 8,
+            X64 => 16,
+        }
+    }
+
+    /// Compare ST(0)-ST(7), the XMM registers, MXCSR and FCW/FSW/FTW.
+    pub fn compare(&self, other: &FpuRegisters) -> Vec<FpuRegisterDiff> {
+        self.compare_features(other, 1 << X87_FEATURE_BIT | 1 << SSE_FEATURE_BIT)
+    }
+
+    /// Like `compare()` but only compares the state components whose XCR0 bit
+    /// is set in `features`: x87 (FCW/FSW/FTW and ST(0)-ST(7)) and SSE (MXCSR
+    /// and the XMM registers).
+    /// DIFF NOTE: Not present in rr
+    pub fn compare_features(&self, other: &FpuRegisters, features: u64) -> Vec<FpuRegisterDiff> {
+        debug_assert_eq!(self.arch_, other.arch_);
+        let (fctrl, fstat, ftag, mxcsr, st0, xmm0) = match self.arch_ {
+            X86 => (
+                DREG_FCTRL, DREG_FSTAT, DREG_FTAG, DREG_MXCSR, DREG_ST0, DREG_XMM0,
+            ),
+            X64 => (
+                DREG_64_FCTRL,
+                DREG_64_FSTAT,
+                DREG_64_FTAG,
+                DREG_64_MXCSR,
+                DREG_64_ST0,
+                DREG_64_XMM0,
+            ),
+        };
+        // (regno, offset, size)
+        let mut fields = Vec::new();
+        if features & 1 << X87_FEATURE_BIT != 0 {
+            fields.extend_from_slice(&[(fctrl, 0, 2), (fstat, 2, 2), (ftag, 4, 1)]);
+            for i in 0..8 {
+                fields.push((
+                    (st0 + i as u32).unwrap(),
+                    ST_REGS_OFFSET + i * ST_REG_SPACE,
+                    ST_REG_SIZE,
+                ));
+            }
+        }
+        if features & 1 << SSE_FEATURE_BIT != 0 {
+            fields.push((mxcsr, MXCSR_OFFSET, 4));
+            for i in 0..self.xmm_register_count() {
+                fields.push((
+                    (xmm0 + i as u32).unwrap(),
+                    XMM_REGS_OFFSET + i * XMM_REG_SPACE,
+                    XMM_REG_SPACE,
+                ));
+            }
+        }
+
+        let mut diffs = Vec::new();
+        for (regno, off, size) in fields {
+            let value1 = &self.data_[off..off + size];
+            let value2 = &other.data_[off..off + size];
+            if value1 != value2 {
+                diffs.push(FpuRegisterDiff {
+                    regno,
+                    value1: value1.to_vec(),
+                    value2: value2.to_vec(),
+                });
+            }
+        }
+        diffs
+    }
+}
+
 fn features_used(data: &[u8], layout: &XSaveLayout) -> u64 {
     let mut features: u64 = u64::from_le_bytes(
         data[XSAVE_HEADER_OFFSET..XSAVE_HEADER_OFFSET + 8]
@@ -778,3 +1022,83 @@ fn write_reg(
     }
     write!(f, "{}:0x{}", name, out_str)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xsave_extra_registers(arch: SupportedArch) -> ExtraRegisters {
+        ExtraRegisters {
+            format_: Format::XSave,
+            arch_: arch,
+            data_: vec![0; AVX_XSAVE_OFFSET + AVX_XSAVE_SIZE],
+        }
+    }
+
+    #[test]
+    fn write_simd_register_test() {
+        let mut e = xsave_extra_registers(X64);
+        let xmm: Vec<u8> = (1..=16).collect();
+        assert!(e.write_register(&xmm, DREG_64_XMM3));
+        let mut buf = [0u8; 16];
+        assert_eq!(Some(16), e.read_register(&mut buf, DREG_64_XMM3));
+        assert_eq!(&xmm[..], &buf[..]);
+
+        // Writing a YMM upper half marks AVX state as in use
+        assert_eq!(0, xsave_features(&e.data_) & (1 << AVX_FEATURE_BIT));
+        let ymmh = [0xffu8; 16];
+        assert!(e.write_register(&ymmh, DREG_64_YMM15H));
+        assert_ne!(0, xsave_features(&e.data_) & (1 << AVX_FEATURE_BIT));
+        assert_eq!(Some(16), e.read_register(&mut buf, DREG_64_YMM15H));
+        assert_eq!(ymmh, buf);
+
+        assert!(!e.write_register(&xmm[0..8], DREG_64_XMM0));
+        assert!(!ExtraRegisters::new(X64).write_register(&xmm, DREG_64_XMM0));
+    }
+
+    #[test]
+    fn simd_register_mismatches_test() {
+        let mut a = xsave_extra_registers(X86);
+        let b = xsave_extra_registers(X86);
+        assert!(a.simd_register_mismatches(&b).is_empty());
+
+        assert!(a.write_register(&[7u8; 16], DREG_XMM5));
+        assert!(a.write_register(&[9u8; 16], DREG_YMM1H));
+        assert_eq!(vec![DREG_XMM5, DREG_YMM1H], a.simd_register_mismatches(&b));
+    }
+
+    #[test]
+    fn fpu_compare_test() {
+        let mut e = xsave_extra_registers(X64);
+        let fpu = e.fpu().unwrap();
+        assert_eq!(16, fpu.xmm_register_count());
+        assert!(fpu.compare(&fpu).is_empty());
+
+        let mut data = fpu.data_bytes().to_vec();
+        data[0..2].copy_from_slice(&0x37fu16.to_le_bytes());
+        data[MXCSR_OFFSET..MXCSR_OFFSET + 4].copy_from_slice(&0x1f80u32.to_le_bytes());
+        data[XMM_REGS_OFFSET + 15 * XMM_REG_SPACE] = 1;
+        let fpu2 = FpuRegisters::from_fxsave(X64, &data).unwrap();
+        assert_eq!(0x37f, fpu2.fcw());
+        assert_eq!(0x1f80, fpu2.mxcsr());
+        let regnos: Vec<GdbRegister> = fpu.compare(&fpu2).iter().map(|d| d.regno).collect();
+        assert_eq!(vec![DREG_64_FCTRL, DREG_64_MXCSR, DREG_64_XMM15], regnos);
+        let regnos: Vec<GdbRegister> = fpu
+            .compare_features(&fpu2, 1 << X87_FEATURE_BIT)
+            .iter()
+            .map(|d| d.regno)
+            .collect();
+        assert_eq!(vec![DREG_64_FCTRL], regnos);
+        let regnos: Vec<GdbRegister> = fpu
+            .compare_features(&fpu2, 1 << SSE_FEATURE_BIT)
+            .iter()
+            .map(|d| d.regno)
+            .collect();
+        assert_eq!(vec![DREG_64_MXCSR, DREG_64_XMM15], regnos);
+
+        assert!(e.set_fpu(&fpu2));
+        assert_eq!(Some(fpu2), e.fpu());
+        assert!(ExtraRegisters::new(X64).fpu().is_none());
+        assert!(FpuRegisters::from_fxsave(X86, &data[0..100]).is_none());
+    }
+}
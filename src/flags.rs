@@ -54,6 +54,10 @@ pub struct Flags {
     pub forced_uarch: Option<String>,
     /// User override for the path to page files and other resources.
     pub resource_path: Option<PathBuf>,
+    /// Compare the canonical form of registers (see `Registers::canonical_form()`)
+    /// when checking for replay divergence.
+    /// DIFF NOTE: Not present in rr
+    pub canonicalize_before_compare: bool,
 }
 
 impl Flags {
@@ -63,7 +67,13 @@ impl Flags {
 }
 
 pub fn init_flags() -> Flags {
-    let options = RdOptions::from_args();
+    // Under `cargo test` the command line holds the options of the test
+    // harness, not ours.
+    let options = if cfg!(test) {
+        RdOptions::from_iter(&["rd", "buildid"])
+    } else {
+        RdOptions::from_args()
+    };
 
     Flags {
         checksum: options.checksum.unwrap_or(Checksum::ChecksumNone),
@@ -79,5 +89,6 @@ pub fn init_flags() -> Flags {
         disable_ptrace_exit_events: options.disable_ptrace_exit_events,
         forced_uarch: options.microarch,
         resource_path: options.resource_path,
+        canonicalize_before_compare: options.canonicalize_registers,
     }
 }
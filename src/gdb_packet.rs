@@ -0,0 +1,228 @@
+//! Framing of the GDB remote serial protocol.
+//!
+//! DIFF NOTE: Not present in rr. rr parses packets in place inside GdbConnection's input buffer
+//! (see `GdbConnection::read_packet()` and friends). Here the framing is a standalone, pure
+//! parser/serializer so it can be unit tested without a socket.
+//!
+//! A packet on the wire is one of:
+//! - `$<data>#<checksum>` where checksum is the sum of the bytes of `<data>` modulo 256 as
+//!   two hex digits
+//! - `+` / `-`: ACK / NAK of the last packet
+//! - `\x03`: interrupt request
+//!
+//! Run length encoding (`x*n` means "`x` followed by `n - 29` more copies of `x`") is expanded
+//! by `parse()`. The `}` escapes used by binary packets like `X` are NOT removed here because
+//! whether they apply depends on the packet type. Like in rr, that is left to the packet handler.
+
+use std::{error::Error, fmt};
+
+const INTERRUPT_CHAR: u8 = 0x03;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GdbPacket {
+    Ack,
+    Nak,
+    Interrupt,
+    /// The payload of a `$...#xx` packet, with run length encoding expanded
+    Data(Vec<u8>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GdbParseError {
+    /// No complete packet in the buffer yet. Read more data and try again.
+    Incomplete,
+    /// The first `len` bytes of the buffer are a packet whose checksum doesn't match its
+    /// payload. These should be dropped and a `-` sent back.
+    BadChecksum {
+        len: usize,
+        expected: u8,
+        actual: u8,
+    },
+    /// The first `len` bytes of the buffer can't be parsed and should be dropped.
+    Malformed { len: usize, reason: &'static str },
+}
+
+impl fmt::Display for GdbParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GdbParseError::Incomplete => write!(f, "Incomplete packet"),
+            GdbParseError::BadChecksum {
+                expected, actual, ..
+            } => write!(
+                f,
+                "Bad checksum: packet says {:02x}, payload sums to {:02x}",
+                expected, actual
+            ),
+            GdbParseError::Malformed { reason, .. } => write!(f, "Malformed packet: {}", reason),
+        }
+    }
+}
+
+impl Error for GdbParseError {}
+
+impl GdbPacket {
+    /// Parse the packet at the start of `buf`. On success also returns the number of bytes
+    /// of `buf` that were consumed.
+    pub fn parse(buf: &[u8]) -> Result<(GdbPacket, usize), GdbParseError> {
+        match buf.first() {
+            None => Err(GdbParseError::Incomplete),
+            Some(b'+') => Ok((GdbPacket::Ack, 1)),
+            Some(b'-') => Ok((GdbPacket::Nak, 1)),
+            Some(&INTERRUPT_CHAR) => Ok((GdbPacket::Interrupt, 1)),
+            Some(b'$') => {
+                let hash_pos = match buf.iter().position(|&c| c == b'#') {
+                    Some(pos) => pos,
+                    None => return Err(GdbParseError::Incomplete),
+                };
+                // '$' + data + '#' + 2 checksum digits
+                let len = hash_pos + 3;
+                if buf.len() < len {
+                    return Err(GdbParseError::Incomplete);
+                }
+                let raw = &buf[1..hash_pos];
+                let expected = match parse_hex_byte(&buf[hash_pos + 1..len]) {
+                    Some(cs) => cs,
+                    None => {
+                        return Err(GdbParseError::Malformed {
+                            len,
+                            reason: "checksum is not two hex digits",
+                        })
+                    }
+                };
+                let actual = checksum(raw);
+                if expected != actual {
+                    return Err(GdbParseError::BadChecksum {
+                        len,
+                        expected,
+                        actual,
+                    });
+                }
+                match run_length_decode(raw) {
+                    Some(data) => Ok((GdbPacket::Data(data), len)),
+                    None => Err(GdbParseError::Malformed {
+                        len,
+                        reason: "invalid run length encoding",
+                    }),
+                }
+            }
+            Some(_) => Err(GdbParseError::Malformed {
+                len: 1,
+                reason: "expected '$', '+', '-' or 0x03",
+            }),
+        }
+    }
+
+    /// Serialize the packet for sending. Data is sent as is i.e. without run length
+    /// encoding. Binary payloads must already have `$`, `#`, `}` and `*` escaped.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            GdbPacket::Ack => vec![b'+'],
+            GdbPacket::Nak => vec![b'-'],
+            GdbPacket::Interrupt => vec![INTERRUPT_CHAR],
+            GdbPacket::Data(data) => {
+                let mut out = Vec::with_capacity(data.len() + 4);
+                out.push(b'$');
+                out.extend_from_slice(data);
+                out.extend_from_slice(format!("#{:02x}", checksum(data)).as_bytes());
+                out
+            }
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, &c| acc.wrapping_add(c))
+}
+
+fn parse_hex_byte(digits: &[u8]) -> Option<u8> {
+    u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+/// Returns None if a `*` is not preceded by a byte to repeat or not followed by a valid count.
+fn run_length_decode(raw: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut it = raw.iter();
+    while let Some(&c) = it.next() {
+        if c == b'*' {
+            let repeat = *out.last()?;
+            let count = it.next()?.checked_sub(29)?;
+            out.extend(std::iter::repeat(repeat).take(count as usize));
+        } else {
+            out.push(c);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(
+            Ok((GdbPacket::Data(b"qSupported".to_vec()), 14)),
+            GdbPacket::parse(b"$qSupported#37+")
+        );
+        assert_eq!(Ok((GdbPacket::Ack, 1)), GdbPacket::parse(b"+$g#67"));
+        assert_eq!(Ok((GdbPacket::Nak, 1)), GdbPacket::parse(b"-"));
+        assert_eq!(Ok((GdbPacket::Interrupt, 1)), GdbPacket::parse(b"\x03"));
+        assert_eq!(Ok((GdbPacket::Data(vec![]), 4)), GdbPacket::parse(b"$#00"));
+    }
+
+    #[test]
+    fn encode_test() {
+        assert_eq!(b"$g#67".to_vec(), GdbPacket::Data(b"g".to_vec()).encode());
+        assert_eq!(b"$OK#9a".to_vec(), GdbPacket::Data(b"OK".to_vec()).encode());
+        assert_eq!(b"+".to_vec(), GdbPacket::Ack.encode());
+        assert_eq!(vec![0x03], GdbPacket::Interrupt.encode());
+
+        let packet = GdbPacket::Data(b"m4015bc,2".to_vec());
+        assert_eq!(Ok((packet.clone(), 13)), GdbPacket::parse(&packet.encode()));
+    }
+
+    #[test]
+    fn bad_checksum_test() {
+        assert_eq!(
+            Err(GdbParseError::BadChecksum {
+                len: 5,
+                expected: 0x68,
+                actual: 0x67
+            }),
+            GdbPacket::parse(b"$g#68")
+        );
+        assert!(matches!(
+            GdbPacket::parse(b"$g#zz"),
+            Err(GdbParseError::Malformed { len: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn truncated_test() {
+        assert_eq!(Err(GdbParseError::Incomplete), GdbPacket::parse(b""));
+        assert_eq!(Err(GdbParseError::Incomplete), GdbPacket::parse(b"$qSupp"));
+        assert_eq!(Err(GdbParseError::Incomplete), GdbPacket::parse(b"$g#6"));
+        assert!(matches!(
+            GdbPacket::parse(b"x$g#67"),
+            Err(GdbParseError::Malformed { len: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn run_length_test() {
+        // "0* " is '0' followed by 32 - 29 = 3 more '0's. This is how gdbserver
+        // compresses runs of zero bytes in a 'g' reply.
+        let raw = b"0* 1122";
+        let mut wire = b"$".to_vec();
+        wire.extend_from_slice(raw);
+        wire.extend_from_slice(format!("#{:02x}", checksum(raw)).as_bytes());
+        assert_eq!(
+            Ok((GdbPacket::Data(b"00001122".to_vec()), wire.len())),
+            GdbPacket::parse(&wire)
+        );
+
+        assert_eq!(None, run_length_decode(b"*!"));
+        assert_eq!(None, run_length_decode(b"0*"));
+        assert_eq!(None, run_length_decode(b"0*\x10"));
+    }
+}
@@ -1,11 +1,16 @@
 use crate::{
     extra_registers::ExtraRegisters,
     gdb_connection::GdbRegisterValue,
-    gdb_register::GdbRegister,
-    registers::Registers,
+    gdb_packet::GdbPacket,
+    gdb_register::*,
+    kernel_abi::SupportedArch,
+    registers::{regs_info_for_arch, Registers},
+    session::task::Task,
     trace::trace_frame::FrameTime,
 };
 use libc::pid_t;
+use nix::sys::mman::ProtFlags;
+use std::fmt::Write;
 
 #[derive(Clone)]
 pub struct Target {
@@ -44,4 +49,258 @@ impl GdbServer {
     ) -> GdbRegisterValue {
         unimplemented!()
     }
+
+    /// Reply to a `g` packet with the general purpose registers of `t`, hex
+    /// encoded in GDB register order.
+    /// DIFF NOTE: Not present in rr. rr collects a GdbRegisterValue for every
+    /// register and passes them to GdbConnection::reply_get_regs().
+    pub fn handle_read_registers_packet(t: &dyn Task) -> GdbPacket {
+        let mut data = Vec::new();
+        // Writing to a Vec can't fail
+        t.regs_ref()
+            .write_registers_for_gdb_stub(&mut data)
+            .unwrap();
+        GdbPacket::Data(data)
+    }
+
+    /// Handle a `G` packet. `payload` is everything after the 'G'.
+    /// DIFF NOTE: Not present in rr
+    pub fn handle_write_registers_packet(t: &mut dyn Task, payload: &[u8]) -> GdbPacket {
+        let mut regs = t.regs_ref().clone();
+        if !regs.read_registers_from_gdb_stub(payload) {
+            return GdbPacket::Data(b"E01".to_vec());
+        }
+        t.set_regs(&regs);
+        GdbPacket::Data(b"OK".to_vec())
+    }
+
+    /// The target description for `qXfer:features:read:target.xml`, generated
+    /// from the register table used by `Registers` so the two can't disagree.
+    /// The x87 and SSE registers live in `ExtraRegisters` and are described with gdb's
+    /// standard numbering and types, since gdb's i386 tdesc validation requires them.
+    /// DIFF NOTE: Not present in rr. rr serves hand written XML files.
+    pub fn target_xml(arch: SupportedArch) -> String {
+        let mut core = String::new();
+        let mut segments = String::new();
+        let mut linux = String::new();
+        for (regno, rv) in regs_info_for_arch(arch) {
+            if rv.nbytes == 0 {
+                continue;
+            }
+            let (name, feature) = match rv.name {
+                // x86 segment registers are named after their user_regs_struct fields
+                "xcs" | "xss" | "xds" | "xes" | "xfs" | "xgs" => (&rv.name[1..], &mut core),
+                "orig_eax" | "orig_rax" => (rv.name, &mut linux),
+                "fs_base" | "gs_base" => (rv.name, &mut segments),
+                name => (name, &mut core),
+            };
+            let reg_type = match name {
+                "eflags" => "i386_eflags",
+                "eip" | "rip" => "code_ptr",
+                "esp" | "rsp" | "ebp" | "rbp" => "data_ptr",
+                _ => {
+                    if rv.nbytes == 8 {
+                        "int64"
+                    } else {
+                        "int32"
+                    }
+                }
+            };
+            write_reg(feature, name, rv.nbytes * 8, reg_type, regno.as_usize());
+        }
+
+        let (st0, fctrl, xmm0, num_xmm, mxcsr) = match arch {
+            SupportedArch::X86 => (DREG_ST0, DREG_FCTRL, DREG_XMM0, 8, DREG_MXCSR),
+            SupportedArch::X64 => (DREG_64_ST0, DREG_64_FCTRL, DREG_64_XMM0, 16, DREG_64_MXCSR),
+        };
+        for i in 0..8 {
+            let name = format!("st{}", i);
+            write_reg(&mut core, &name, 80, "i387_ext", st0.as_usize() + i);
+        }
+        for (i, name) in X87_CONTROL_REGS.iter().enumerate() {
+            write_reg(&mut core, name, 32, "int", fctrl.as_usize() + i);
+        }
+        let mut sse = String::from(I386_SSE_TYPES);
+        for i in 0..num_xmm {
+            let name = format!("xmm{}", i);
+            write_reg(&mut sse, &name, 128, "vec128", xmm0.as_usize() + i);
+        }
+        write_reg(&mut sse, "mxcsr", 32, "i386_mxcsr", mxcsr.as_usize());
+
+        let mut xml = String::from(
+            "<?xml version=\"1.0\"?>\n\
+             <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+             <target version=\"1.0\">\n",
+        );
+        let architecture = match arch {
+            SupportedArch::X86 => "i386",
+            SupportedArch::X64 => "i386:x86-64",
+        };
+        writeln!(xml, "  <architecture>{}</architecture>", architecture).unwrap();
+        xml += "  <osabi>GNU/Linux</osabi>\n";
+        xml += "  <feature name=\"org.gnu.gdb.i386.core\">\n";
+        xml += I386_EFLAGS_TYPE;
+        xml += &core;
+        xml += "  </feature>\n";
+        for (feature_name, regs) in &[
+            ("org.gnu.gdb.i386.sse", sse),
+            ("org.gnu.gdb.i386.segments", segments),
+            ("org.gnu.gdb.i386.linux", linux),
+        ] {
+            if !regs.is_empty() {
+                writeln!(xml, "  <feature name=\"{}\">", feature_name).unwrap();
+                xml += regs;
+                xml += "  </feature>\n";
+            }
+        }
+        xml += "</target>\n";
+        xml
+    }
+
+    /// The memory map for `qXfer:memory-map:read`, built from the address space
+    /// of `t`. gdb treats addresses outside of the map as inaccessible.
+    ///
+    /// Every accessible mapping is reported as "ram", like rr does. gdb refuses to set
+    /// software breakpoints in "rom" (so every breakpoint in .text would use up a debug
+    /// register) and tries to erase and program "flash".
+    /// DIFF NOTE: Not present in rr
+    pub fn memory_map_xml(t: &dyn Task) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\"?>\n\
+             <!DOCTYPE memory-map PUBLIC \"+//IDN gnu.org//DTD GDB Memory Map V1.0//EN\" \
+             \"http://sourceware.org/gdb/gdb-memory-map.dtd\">\n\
+             <memory-map>\n",
+        );
+        for (_, m) in &t.vm().maps() {
+            if !is_accessible(m.map.prot()) {
+                continue;
+            }
+            // Writing to a String can't fail
+            writeln!(
+                xml,
+                "  <memory type=\"ram\" start=\"{:#x}\" length=\"{:#x}\"/>",
+                m.map.start().as_usize(),
+                m.map.size()
+            )
+            .unwrap();
+        }
+        xml += "</memory-map>\n";
+        xml
+    }
+}
+
+/// Append a `<reg>` element to the feature being built in `feature`
+fn write_reg(feature: &mut String, name: &str, bitsize: usize, reg_type: &str, regnum: usize) {
+    // Writing to a String can't fail
+    writeln!(
+        feature,
+        "    <reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>",
+        name, bitsize, reg_type, regnum
+    )
+    .unwrap();
+}
+
+/// Whether the tracee can access a mapping with protection `prot` in any way
+fn is_accessible(prot: ProtFlags) -> bool {
+    prot.intersects(ProtFlags::PROT_READ | ProtFlags::PROT_WRITE | ProtFlags::PROT_EXEC)
+}
+
+/// Same as the definition in gdb's 32bit-core.xml and 64bit-core.xml
+const I386_EFLAGS_TYPE: &str = "    <flags id=\"i386_eflags\" size=\"4\">
+      <field name=\"CF\" start=\"0\" end=\"0\"/>
+      <field name=\"\" start=\"1\" end=\"1\"/>
+      <field name=\"PF\" start=\"2\" end=\"2\"/>
+      <field name=\"AF\" start=\"4\" end=\"4\"/>
+      <field name=\"ZF\" start=\"6\" end=\"6\"/>
+      <field name=\"SF\" start=\"7\" end=\"7\"/>
+      <field name=\"TF\" start=\"8\" end=\"8\"/>
+      <field name=\"IF\" start=\"9\" end=\"9\"/>
+      <field name=\"DF\" start=\"10\" end=\"10\"/>
+      <field name=\"OF\" start=\"11\" end=\"11\"/>
+      <field name=\"NT\" start=\"14\" end=\"14\"/>
+      <field name=\"RF\" start=\"16\" end=\"16\"/>
+      <field name=\"VM\" start=\"17\" end=\"17\"/>
+      <field name=\"AC\" start=\"18\" end=\"18\"/>
+      <field name=\"VIF\" start=\"19\" end=\"19\"/>
+      <field name=\"VIP\" start=\"20\" end=\"20\"/>
+      <field name=\"ID\" start=\"21\" end=\"21\"/>
+    </flags>
+";
+
+/// Follow st0-st7 in gdb's register numbering
+const X87_CONTROL_REGS: [&str; 8] = [
+    "fctrl", "fstat", "ftag", "fiseg", "fioff", "foseg", "fooff", "fop",
+];
+
+/// Same as the definitions in gdb's 32bit-sse.xml and 64bit-sse.xml
+const I386_SSE_TYPES: &str = "    <vector id=\"v4f\" type=\"ieee_single\" count=\"4\"/>
+    <vector id=\"v2d\" type=\"ieee_double\" count=\"2\"/>
+    <vector id=\"v16i8\" type=\"int8\" count=\"16\"/>
+    <vector id=\"v8i16\" type=\"int16\" count=\"8\"/>
+    <vector id=\"v4i32\" type=\"int32\" count=\"4\"/>
+    <vector id=\"v2i64\" type=\"int64\" count=\"2\"/>
+    <union id=\"vec128\">
+      <field name=\"v4_float\" type=\"v4f\"/>
+      <field name=\"v2_double\" type=\"v2d\"/>
+      <field name=\"v16_int8\" type=\"v16i8\"/>
+      <field name=\"v8_int16\" type=\"v8i16\"/>
+      <field name=\"v4_int32\" type=\"v4i32\"/>
+      <field name=\"v2_int64\" type=\"v2i64\"/>
+      <field name=\"uint128\" type=\"uint128\"/>
+    </union>
+    <flags id=\"i386_mxcsr\" size=\"4\">
+      <field name=\"IE\" start=\"0\" end=\"0\"/>
+      <field name=\"DE\" start=\"1\" end=\"1\"/>
+      <field name=\"ZE\" start=\"2\" end=\"2\"/>
+      <field name=\"OE\" start=\"3\" end=\"3\"/>
+      <field name=\"UE\" start=\"4\" end=\"4\"/>
+      <field name=\"PE\" start=\"5\" end=\"5\"/>
+      <field name=\"DAZ\" start=\"6\" end=\"6\"/>
+      <field name=\"IM\" start=\"7\" end=\"7\"/>
+      <field name=\"DM\" start=\"8\" end=\"8\"/>
+      <field name=\"ZM\" start=\"9\" end=\"9\"/>
+      <field name=\"OM\" start=\"10\" end=\"10\"/>
+      <field name=\"UM\" start=\"11\" end=\"11\"/>
+      <field name=\"PM\" start=\"12\" end=\"12\"/>
+      <field name=\"FZ\" start=\"15\" end=\"15\"/>
+    </flags>
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_xml_test() {
+        let xml = GdbServer::target_xml(SupportedArch::X64);
+        assert!(xml.contains("<architecture>i386:x86-64</architecture>"));
+        assert!(xml.contains("<reg name=\"rax\" bitsize=\"64\" type=\"int64\" regnum=\"0\"/>"));
+        assert!(xml.contains("<reg name=\"rip\" bitsize=\"64\" type=\"code_ptr\" regnum=\"16\"/>"));
+        assert!(xml.contains("<reg name=\"eflags\" bitsize=\"32\" type=\"i386_eflags\""));
+        assert!(xml.contains("<feature name=\"org.gnu.gdb.i386.linux\">"));
+        assert!(xml.contains("<reg name=\"st0\" bitsize=\"80\" type=\"i387_ext\" regnum=\"24\"/>"));
+        assert!(xml.contains("<reg name=\"fop\" bitsize=\"32\" type=\"int\" regnum=\"39\"/>"));
+        assert!(xml.contains("<feature name=\"org.gnu.gdb.i386.sse\">"));
+        assert!(xml.contains("<reg name=\"xmm15\" bitsize=\"128\" type=\"vec128\" regnum=\"55\"/>"));
+        let mxcsr = "<reg name=\"mxcsr\" bitsize=\"32\" type=\"i386_mxcsr\" regnum=\"56\"/>";
+        assert!(xml.contains(mxcsr));
+        assert!(xml.ends_with("</target>\n"));
+
+        let xml = GdbServer::target_xml(SupportedArch::X86);
+        assert!(xml.contains("<architecture>i386</architecture>"));
+        assert!(xml.contains("<reg name=\"cs\" bitsize=\"32\" type=\"int32\" regnum=\"10\"/>"));
+        assert!(!xml.contains("xcs"));
+        assert!(!xml.contains("fs_base"));
+        assert!(xml.contains("<reg name=\"st0\" bitsize=\"80\" type=\"i387_ext\" regnum=\"16\"/>"));
+        assert!(xml.contains("<reg name=\"xmm7\" bitsize=\"128\" type=\"vec128\" regnum=\"39\"/>"));
+        assert!(!xml.contains("xmm8"));
+    }
+
+    #[test]
+    fn is_accessible_test() {
+        assert!(is_accessible(ProtFlags::PROT_READ | ProtFlags::PROT_WRITE));
+        assert!(is_accessible(ProtFlags::PROT_READ | ProtFlags::PROT_EXEC));
+        assert!(is_accessible(ProtFlags::PROT_EXEC));
+        assert!(!is_accessible(ProtFlags::PROT_NONE));
+    }
 }
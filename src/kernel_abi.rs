@@ -11,14 +11,16 @@ use crate::{
         task::{task_common::read_mem, Task},
     },
 };
+use serde::{Deserialize, Serialize};
 use std::{
-    convert::TryInto,
+    convert::{TryFrom, TryInto},
+    error::Error,
     fmt::{Display, Formatter, LowerHex, Result},
     marker::PhantomData,
     mem::size_of,
 };
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum SupportedArch {
     X86,
     X64,
@@ -41,6 +43,70 @@ impl Default for SupportedArch {
     }
 }
 
+/// DIFF NOTE: Not present in rr
+impl SupportedArch {
+    /// Size of a pointer in the tracee, in bytes
+    pub fn pointer_width(self) -> usize {
+        match self {
+            SupportedArch::X86 => 4,
+            SupportedArch::X64 => 8,
+        }
+    }
+
+    /// Size of a general purpose register, in bytes. Same as `util::word_size()`
+    pub fn word_size(self) -> usize {
+        match self {
+            SupportedArch::X86 => 4,
+            SupportedArch::X64 => 8,
+        }
+    }
+
+    /// The largest value a general purpose register can hold
+    pub fn max_register_value(self) -> u64 {
+        match self {
+            SupportedArch::X86 => u32::MAX as u64,
+            SupportedArch::X64 => u64::MAX,
+        }
+    }
+}
+
+/// Emits the canonical names accepted by `SupportedArch::try_from()`
+/// DIFF NOTE: Not present in rr
+impl Display for SupportedArch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            SupportedArch::X86 => write!(f, "x86"),
+            SupportedArch::X64 => write!(f, "x86-64"),
+        }
+    }
+}
+
+/// See `SupportedArch::try_from()`
+/// DIFF NOTE: Not present in rr
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnknownArchError(pub String);
+
+impl Display for UnknownArchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "Unknown architecture `{}`", self.0)
+    }
+}
+
+impl Error for UnknownArchError {}
+
+/// DIFF NOTE: Not present in rr
+impl TryFrom<&str> for SupportedArch {
+    type Error = UnknownArchError;
+
+    fn try_from(name: &str) -> std::result::Result<Self, Self::Error> {
+        match name {
+            "x86" | "i386" | "i686" => Ok(SupportedArch::X86),
+            "x86_64" | "x86-64" | "amd64" => Ok(SupportedArch::X64),
+            _ => Err(UnknownArchError(name.to_owned())),
+        }
+    }
+}
+
 // All architectures have an mmap syscall, but it has architecture-specific
 // calling semantics. We describe those here, and specializations need to
 // indicate which semantics they use.
@@ -166,9 +232,9 @@ macro_rules! rd_kernel_abi_arch_function {
     };
 }
 
-const INT80_INSN: [u8; 2] = [0xcd, 0x80];
-const SYSENTER_INSN: [u8; 2] = [0x0f, 0x34];
-const SYSCALL_INSN: [u8; 2] = [0x0f, 0x05];
+pub(crate) const INT80_INSN: [u8; 2] = [0xcd, 0x80];
+pub(crate) const SYSENTER_INSN: [u8; 2] = [0x0f, 0x34];
+pub(crate) const SYSCALL_INSN: [u8; 2] = [0x0f, 0x05];
 
 pub fn get_syscall_instruction_arch(
     t: &mut dyn Task,
@@ -417,6 +483,12 @@ pub mod x64 {
         "/syscall_name_arch_x64_generated.rs"
     ));
 
+    // syscall_arg_count_arch_x64_generated.rs is generated by scripts/generate_syscall.py
+    include!(concat!(
+        env!("OUT_DIR"),
+        "/syscall_arg_count_arch_x64_generated.rs"
+    ));
+
     // IMPORTANT ! ////////////////////////
     include!("include/base_arch_defns.rs");
 
@@ -898,6 +970,12 @@ pub mod x86 {
         "/syscall_name_arch_x86_generated.rs"
     ));
 
+    // syscall_arg_count_arch_x86_generated.rs is generated by scripts/generate_syscall.py
+    include!(concat!(
+        env!("OUT_DIR"),
+        "/syscall_arg_count_arch_x86_generated.rs"
+    ));
+
     // IMPORTANT ! ////////////////////////
     include!("include/base_arch_defns.rs");
 
@@ -2,10 +2,28 @@ use crate::{
     bindings::{ptrace::*, signal::siginfo_t},
     flags::Flags,
     kernel_abi,
-    kernel_abi::SupportedArch,
+    kernel_abi::{x64, x86, SupportedArch},
     kernel_supplement::PTRACE_EVENT_SECCOMP_OBSOLETE,
 };
 use nix::sys::mman::ProtFlags;
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref SYSCALL_NAMES_X86: HashMap<i64, &'static str> = syscall_names(x86::SYSCALL_NAMES);
+    static ref SYSCALL_NAMES_X64: HashMap<i64, &'static str> = syscall_names(x64::SYSCALL_NAMES);
+    static ref SYSCALL_NUMBERS_X86: HashMap<&'static str, i64> =
+        syscall_numbers(x86::SYSCALL_NAMES);
+    static ref SYSCALL_NUMBERS_X64: HashMap<&'static str, i64> =
+        syscall_numbers(x64::SYSCALL_NAMES);
+}
+
+fn syscall_names(table: &[(i32, &'static str)]) -> HashMap<i64, &'static str> {
+    table.iter().map(|&(n, name)| (n as i64, name)).collect()
+}
+
+fn syscall_numbers(table: &[(i32, &'static str)]) -> HashMap<&'static str, i64> {
+    table.iter().map(|&(n, name)| (name, n as i64)).collect()
+}
 
 pub fn syscall_name(syscall: i32, arch: SupportedArch) -> String {
     let name = rd_kernel_abi_arch_function!(syscallname_arch, arch, syscall);
@@ -16,6 +34,24 @@ pub fn syscall_name(syscall: i32, arch: SupportedArch) -> String {
     }
 }
 
+/// DIFF NOTE: Not present in rr. Unlike `syscall_name()` this returns None for syscalls not
+/// available on `arch` instead of a "<unknown-syscall-...>" placeholder, and never applies the
+/// rr compatibility renaming.
+pub fn syscall_number_to_name(arch: SupportedArch, n: i64) -> Option<&'static str> {
+    match arch {
+        SupportedArch::X86 => SYSCALL_NAMES_X86.get(&n).copied(),
+        SupportedArch::X64 => SYSCALL_NAMES_X64.get(&n).copied(),
+    }
+}
+
+/// DIFF NOTE: Not present in rr. The inverse of `syscall_number_to_name()`
+pub fn syscall_name_to_number(arch: SupportedArch, name: &str) -> Option<i64> {
+    match arch {
+        SupportedArch::X86 => SYSCALL_NUMBERS_X86.get(name).copied(),
+        SupportedArch::X64 => SYSCALL_NUMBERS_X64.get(name).copied(),
+    }
+}
+
 pub fn signal_name(sig: i32) -> String {
     // strsignal() would be nice to use here, but it provides TMI.
     if 32 <= sig && sig <= 64 {
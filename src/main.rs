@@ -40,7 +40,9 @@ mod registers;
 mod commands;
 mod core;
 mod cpuid_bug_detector;
+mod debug_registers;
 mod emu_fs;
+mod error;
 mod event;
 pub mod extra_registers;
 mod fast_forward;
@@ -48,6 +50,7 @@ mod fd_table;
 mod file_monitor;
 mod gdb_connection;
 mod gdb_expression;
+mod gdb_packet;
 mod gdb_register;
 mod gdb_server;
 mod kernel_supplement;
@@ -81,9 +84,11 @@ use crate::{
     commands::{
         build_id_command::BuildIdCommand,
         dump_command::DumpCommand,
+        export_command::ExportCommand,
         ps_command::PsCommand,
         rd_options::{RdOptions, RdSubCommand},
         rerun_command::ReRunCommand,
+        stats_command::StatsCommand,
         trace_info_command::TraceInfoCommand,
         RdCommand,
     },
@@ -161,6 +166,12 @@ fn main() -> ExitResult<()> {
         RdSubCommand::Ps { .. } => {
             return PsCommand::new(&options).run();
         }
+        RdSubCommand::Export { .. } => {
+            return ExportCommand::new(&options).run();
+        }
+        RdSubCommand::Stats { .. } => {
+            return StatsCommand::new(&options).run();
+        }
         RdSubCommand::Record { .. } => {
             return RecordCommand::new(&options).run();
         }
@@ -354,7 +354,7 @@ fn try_handle_trapped_instruction(t: &mut RecordTask, si: &siginfo_t) -> bool {
         log!(LogDebug, " trapped for cpuid: {:#x}:{:#x}", eax, ecx);
     }
 
-    r.set_ip(r.ip() + len);
+    r.advance_ip_by(len);
     t.set_regs(&r);
 
     t.push_event(Event::instruction_trap());
@@ -2556,7 +2556,7 @@ fn prepare_exit(t: &mut RecordTask, exit_code: i32) {
     // cleanup, we'll restart the call.
     exit_regs.set_syscallno(exit_regs.original_syscallno());
     exit_regs.set_original_syscallno(-1);
-    exit_regs.set_ip(exit_regs.ip() - syscall_instruction_length(t.arch()));
+    exit_regs.rewind_ip_by(syscall_instruction_length(t.arch()));
     let is_at_syscall_instruction = is_at_syscall_instruction(t, exit_regs.ip());
     ed_assert!(
         t,
@@ -2706,6 +2706,7 @@ pub fn rec_process_syscall(t: &mut RecordTask) {
     syscall_state.process_syscall_results(t);
     let regs = t.regs_ref().clone();
     t.on_syscall_exit(sys_ev_number, sys_ev_arch, &regs);
+    t.session().notify_syscall_exit(t, &regs);
     t.syscall_state = None;
 
     MonitoredSharedMemory::check_all(t);
@@ -4369,7 +4370,7 @@ impl TaskSyscallState {
         }
 
         let mut param = MemoryParam::default();
-        let dest = RemotePtr::from(self.syscall_entry_registers.arg(arg));
+        let dest = RemotePtr::from(self.syscall_entry_registers.arg_unchecked(arg));
         if dest.is_null() {
             return RemotePtr::null();
         }
@@ -4658,7 +4659,7 @@ impl TaskSyscallState {
             let mut to_adjust = Vec::<(usize, RemotePtr<Void>)>::new();
             for (i, param) in self.param_list.iter().enumerate() {
                 if param.ptr_in_reg != 0 {
-                    r.set_arg(param.ptr_in_reg, param.scratch.as_usize());
+                    r.set_arg_unchecked(param.ptr_in_reg, param.scratch.as_usize());
                 }
                 if !param.ptr_in_memory.is_null() {
                     // Pointers being relocated must themselves be in scratch memory.
@@ -4723,7 +4724,7 @@ impl TaskSyscallState {
             // Step 2: restore modified in-memory pointers and registers
             for param in &self.param_list {
                 if param.ptr_in_reg > 0 {
-                    r.set_arg(param.ptr_in_reg, param.dest.as_usize());
+                    r.set_arg_unchecked(param.ptr_in_reg, param.dest.as_usize());
                 }
                 if !param.ptr_in_memory.is_null() {
                     memory_cleaned_up = true;
@@ -4795,7 +4796,7 @@ impl TaskSyscallState {
             // restore modified in-memory pointers and registers
             for param in &self.param_list {
                 if param.ptr_in_reg != 0 {
-                    r.set_arg(param.ptr_in_reg, param.dest.as_usize());
+                    r.set_arg_unchecked(param.ptr_in_reg, param.dest.as_usize());
                 }
                 if !param.ptr_in_memory.is_null() {
                     set_remote_ptr(t, param.ptr_in_memory, param.dest);
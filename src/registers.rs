@@ -1,16 +1,33 @@
 use crate::{
     bindings::kernel::user_regs_struct as native_user_regs_struct,
+    flags::Flags,
     gdb_register::*,
-    kernel_abi::{x64, x86, SupportedArch, RD_NATIVE_ARCH},
+    kernel_abi::{
+        x64,
+        x86,
+        SupportedArch,
+        INT80_INSN,
+        RD_NATIVE_ARCH,
+        SYSCALL_INSN,
+        SYSENTER_INSN,
+    },
     kernel_supplement::{ERESTARTNOHAND, ERESTARTNOINTR, ERESTARTSYS, ERESTART_RESTARTBLOCK},
     log::LogLevel::{LogError, LogInfo, LogWarn},
     remote_code_ptr::RemoteCodePtr,
     remote_ptr::{RemotePtr, Void},
+    session::{address_space::address_space::AddressSpace, task::Task},
+    util::word_size,
 };
+use libc::pid_t;
+use nix::sys::uio::pread;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
-    collections::BTreeMap,
-    convert::TryInto,
+    cmp::min,
+    collections::{BTreeMap, HashMap},
+    convert::{TryFrom, TryInto},
+    ffi::CString,
     fmt::{Display, Formatter, Result},
+    hash::{Hash, Hasher},
     io,
     io::Write,
     mem::{self, size_of, transmute_copy},
@@ -18,6 +35,140 @@ use std::{
     ptr::copy_nonoverlapping,
 };
 
+/// Offset of `uc_mcontext` within the kernel's `struct ucontext`:
+/// uc_flags, uc_link and a stack_t precede it.
+const UCONTEXT_MCONTEXT_OFFSET_X86: usize = 20;
+const UCONTEXT_MCONTEXT_OFFSET_X64: usize = 40;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UcontextError {
+    /// The ucontext bytes provided were too short to contain `uc_mcontext`
+    TooShort { needed: usize, got: usize },
+}
+
+/// DIFF NOTE: Not present in rr
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RegisterError {
+    /// Syscall argument indices are 1..=6
+    ArgIndexOutOfRange(usize),
+    /// The register is not part of the arch's register file
+    UnknownRegister(GdbRegister),
+    /// There is no register with this name in the arch's register file
+    UnknownRegisterName(&'static str),
+}
+
+/// See `Registers::try_from()`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RegistersFromBytesError {
+    /// The register data was not the size of the arch's user_regs_struct
+    WrongLength { expected: usize, got: usize },
+    /// The leading arch byte was not recognized
+    UnknownArch(u8),
+}
+
+/// See `Registers::decode_syscall_at_ip()`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SyscallInstructionType {
+    Syscall,
+    Sysenter,
+    Int80,
+}
+
+/// A register whose values differ by more than the allowed tolerance.
+/// See `Registers::compare_with_tolerance()`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RegisterMismatch {
+    pub regno: GdbRegister,
+    pub name: &'static str,
+    pub value1: u64,
+    pub value2: u64,
+}
+
+/// A register whose (masked) values differ. See `Registers::diff()`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RegisterDiff {
+    pub name: &'static str,
+    pub recorded: u64,
+    pub live: u64,
+    /// The comparison mask that was applied to both values
+    pub mask: u64,
+}
+
+/// See `Registers::for_each_mut()`
+pub struct RegisterEntry<'a> {
+    regs: &'a mut Registers,
+    regno: GdbRegister,
+    name: &'static str,
+    value: u64,
+}
+
+impl RegisterEntry<'_> {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn regno(&self) -> GdbRegister {
+        self.regno
+    }
+
+    /// Value of the register when the entry was created.
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    /// Set the register to `value`, truncated to the size of the register.
+    pub fn set(&mut self, value: u64) {
+        // The regno always comes from the regs info so this can't fail
+        self.regs
+            .write_register(&value.to_le_bytes(), self.regno)
+            .unwrap();
+        self.value = value;
+    }
+}
+
+/// Result of `Registers::compare_with_tolerance()`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ComparisonReport {
+    pub mismatches: Vec<RegisterMismatch>,
+}
+
+impl ComparisonReport {
+    pub fn matches(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+const PUSH_BP_INSN: u8 = 0x55;
+const RET_INSN: u8 = 0xc3;
+const RET_IMM16_INSN: u8 = 0xc2;
+const ENDBR32_INSN: [u8; 4] = [0xf3, 0x0f, 0x1e, 0xfb];
+const ENDBR64_INSN: [u8; 4] = [0xf3, 0x0f, 0x1e, 0xfa];
+
+/// Offset of `pr_cursig` in `struct elf_prstatus`. It follows the 12 byte
+/// `struct elf_siginfo` on both x86 and x64.
+const PRSTATUS_CURSIG_OFFSET: usize = 12;
+
+struct PrstatusLayout {
+    size: usize,
+    pid_offset: usize,
+    reg_offset: usize,
+}
+
+fn prstatus_layout(arch: SupportedArch) -> PrstatusLayout {
+    match arch {
+        SupportedArch::X86 => PrstatusLayout {
+            size: 144,
+            pid_offset: 24,
+            reg_offset: 72,
+        },
+        SupportedArch::X64 => PrstatusLayout {
+            size: 336,
+            pid_offset: 32,
+            reg_offset: 112,
+        },
+    }
+}
+
 #[derive(Copy, Clone, PartialEq)]
 enum TraceStyle {
     Annotated,
@@ -27,6 +178,10 @@ enum TraceStyle {
 lazy_static! {
     static ref REGISTERS_X86: BTreeMap<GdbRegister, RegisterValue> = x86regs();
     static ref REGISTERS_X64: BTreeMap<GdbRegister, RegisterValue> = x64regs();
+    static ref REGISTER_NAMES_X86: HashMap<&'static str, (GdbRegister, usize)> =
+        register_names(&REGISTERS_X86, REGISTER_ALIASES_X86);
+    static ref REGISTER_NAMES_X64: HashMap<&'static str, (GdbRegister, usize)> =
+        register_names(&REGISTERS_X64, REGISTER_ALIASES_X64);
 }
 
 macro_rules! rd_get_reg {
@@ -63,6 +218,51 @@ macro_rules! rd_get_reg_signed {
     };
 }
 
+/// Accessors for the 8-bit (low and high), 16-bit and 32-bit parts of one of
+/// the a, b, c or d registers.
+/// Following the x86-64 rules, writing the 32-bit part zero-extends into the
+/// full register while writing the 8/16-bit parts leaves the other bits alone.
+macro_rules! rd_sub_reg_accessors {
+    ($x86case:ident, $x64case:ident,
+     $l:ident, $set_l:ident, $h:ident, $set_h:ident,
+     $x:ident, $set_x:ident, $e:ident, $set_e:ident) => {
+        pub fn $l(&self) -> u8 {
+            rd_get_reg!(self, $x86case, $x64case) as u8
+        }
+
+        pub fn $set_l(&mut self, value: u8) {
+            let full = rd_get_reg!(self, $x86case, $x64case) & !0xff | value as usize;
+            rd_set_reg!(self, $x86case, $x64case, full);
+        }
+
+        pub fn $h(&self) -> u8 {
+            (rd_get_reg!(self, $x86case, $x64case) >> 8) as u8
+        }
+
+        pub fn $set_h(&mut self, value: u8) {
+            let full = rd_get_reg!(self, $x86case, $x64case) & !0xff00 | (value as usize) << 8;
+            rd_set_reg!(self, $x86case, $x64case, full);
+        }
+
+        pub fn $x(&self) -> u16 {
+            rd_get_reg!(self, $x86case, $x64case) as u16
+        }
+
+        pub fn $set_x(&mut self, value: u16) {
+            let full = rd_get_reg!(self, $x86case, $x64case) & !0xffff | value as usize;
+            rd_set_reg!(self, $x86case, $x64case, full);
+        }
+
+        pub fn $e(&self) -> u32 {
+            rd_get_reg!(self, $x86case, $x64case) as u32
+        }
+
+        pub fn $set_e(&mut self, value: u32) {
+            rd_set_reg!(self, $x86case, $x64case, value);
+        }
+    };
+}
+
 #[derive(Copy, Clone, PartialEq, PartialOrd)]
 pub enum MismatchBehavior {
     ExpectMismatches = 1,
@@ -76,6 +276,98 @@ pub const X86_IF_FLAG: usize = 1 << 9;
 pub const X86_DF_FLAG: usize = 1 << 10;
 pub const X86_RF_FLAG: usize = 1 << 16;
 pub const X86_ID_FLAG: usize = 1 << 21;
+/// Bits 3, 5, 15 and 22 upwards of EFLAGS/RFLAGS are reserved and always read as 0.
+/// DIFF NOTE: Not present in rr
+pub const X86_RESERVED_ZERO_FLAGS: usize = (1 << 3) | (1 << 5) | (1 << 15) | !((1 << 22) - 1);
+
+/// The individual fields of the EFLAGS/RFLAGS register.
+/// See `Registers::eflags_fields()`
+/// DIFF NOTE: Not present in rr
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct EflagsFields {
+    /// Carry
+    pub cf: bool,
+    /// Parity
+    pub pf: bool,
+    /// Auxiliary carry
+    pub af: bool,
+    /// Zero
+    pub zf: bool,
+    /// Sign
+    pub sf: bool,
+    /// Trap i.e. singlestep
+    pub tf: bool,
+    /// Interrupt enable
+    pub if_: bool,
+    /// Direction
+    pub df: bool,
+    /// Overflow
+    pub of: bool,
+    /// I/O privilege level. Only the lower 2 bits are used.
+    pub iopl: u8,
+    /// Nested task
+    pub nt: bool,
+    /// Resume
+    pub rf: bool,
+    /// Virtual 8086 mode
+    pub vm: bool,
+    /// Alignment check
+    pub ac: bool,
+    /// Virtual interrupt
+    pub vif: bool,
+    /// Virtual interrupt pending
+    pub vip: bool,
+    /// CPUID available
+    pub id: bool,
+}
+
+impl EflagsFields {
+    pub fn from_raw(flags: usize) -> EflagsFields {
+        let bit = |n: usize| flags & (1 << n) != 0;
+        EflagsFields {
+            cf: bit(0),
+            pf: bit(2),
+            af: bit(4),
+            zf: bit(6),
+            sf: bit(7),
+            tf: flags & X86_TF_FLAG != 0,
+            if_: flags & X86_IF_FLAG != 0,
+            df: flags & X86_DF_FLAG != 0,
+            of: bit(11),
+            iopl: ((flags >> 12) & 3) as u8,
+            nt: bit(14),
+            rf: flags & X86_RF_FLAG != 0,
+            vm: bit(17),
+            ac: bit(18),
+            vif: bit(19),
+            vip: bit(20),
+            id: flags & X86_ID_FLAG != 0,
+        }
+    }
+
+    /// The reserved bit 1 is always set in the result.
+    pub fn to_raw(&self) -> usize {
+        let bit = |b: bool, n: usize| (b as usize) << n;
+        X86_RESERVED_FLAG
+            | bit(self.cf, 0)
+            | bit(self.pf, 2)
+            | bit(self.af, 4)
+            | bit(self.zf, 6)
+            | bit(self.sf, 7)
+            | bit(self.tf, 8)
+            | bit(self.if_, 9)
+            | bit(self.df, 10)
+            | bit(self.of, 11)
+            | ((self.iopl as usize & 3) << 12)
+            | bit(self.nt, 14)
+            | bit(self.rf, 16)
+            | bit(self.vm, 17)
+            | bit(self.ac, 18)
+            | bit(self.vif, 19)
+            | bit(self.vip, 20)
+            | bit(self.id, 21)
+    }
+}
 
 // Max register size
 // DIFF NOTE: Called MAX_SIZE in rr and within the Registers struct
@@ -108,10 +400,7 @@ use Registers::*;
 /// in a union of Arch::user_regs_structs for each known Arch.
 impl Registers {
     fn get_regs_info(&self) -> &'static BTreeMap<GdbRegister, RegisterValue> {
-        match self {
-            X86(_) => &*REGISTERS_X86,
-            X64(_) => &*REGISTERS_X64,
-        }
+        regs_info_for_arch(self.arch())
     }
 
     fn ignore_undefined_register(&self, regno: GdbRegister) -> bool {
@@ -161,8 +450,25 @@ impl Registers {
         regs1: &Registers,
         regs2: &Registers,
         mismatch_behavior: MismatchBehavior,
+        canonicalize: bool,
     ) -> bool {
-        let mut match_ = true;
+        let diffs = if canonicalize {
+            Registers::diff(&regs1.canonical_form(), &regs2.canonical_form())
+        } else {
+            Registers::diff(regs1, regs2)
+        };
+        for d in &diffs {
+            maybe_log_reg_mismatch(mismatch_behavior, d.name, name1, d.recorded, name2, d.live);
+        }
+        diffs.is_empty()
+    }
+
+    /// The registers whose values differ between `regs1` (usually the
+    /// recorded registers) and `regs2` (usually the live registers), taking
+    /// the comparison mask of each register into account.
+    /// DIFF NOTE: Not present in rr. Extracted from compare_registers_arch()
+    pub fn diff(regs1: &Registers, regs2: &Registers) -> Vec<RegisterDiff> {
+        let mut diffs = Vec::new();
         debug_assert_eq!(regs1.arch(), regs2.arch());
         let regs_info = regs1.get_regs_info();
 
@@ -175,15 +481,12 @@ impl Registers {
                 // they reflect original syscall numbers, in which case both will be positive.
                 if regs1_x86.orig_eax >= 0 && regs2_x86.orig_eax > 0 {
                     if regs1_x86.orig_eax != regs2_x86.orig_eax {
-                        maybe_log_reg_mismatch(
-                            mismatch_behavior,
-                            "orig_eax",
-                            name1,
-                            regs1_x86.orig_eax as u64,
-                            name2,
-                            regs2_x86.orig_eax as u64,
-                        );
-                        match_ = false;
+                        diffs.push(RegisterDiff {
+                            name: "orig_eax",
+                            recorded: regs1_x86.orig_eax as u64,
+                            live: regs2_x86.orig_eax as u64,
+                            mask: RegisterValue::mask_for_nbytes(4),
+                        });
                     }
                 }
             }
@@ -192,15 +495,12 @@ impl Registers {
                 // See comment in the x86 case
                 if (regs1_x64.orig_rax as i64) >= 0 && (regs2_x64.orig_rax as i64) > 0 {
                     if regs1_x64.orig_rax != regs2_x64.orig_rax {
-                        maybe_log_reg_mismatch(
-                            mismatch_behavior,
-                            "orig_rax",
-                            name1,
-                            regs1_x64.orig_rax,
-                            name2,
-                            regs2_x64.orig_rax,
-                        );
-                        match_ = false;
+                        diffs.push(RegisterDiff {
+                            name: "orig_rax",
+                            recorded: regs1_x64.orig_rax,
+                            live: regs2_x64.orig_rax,
+                            mask: RegisterValue::mask_for_nbytes(8),
+                        });
                     }
                 }
             }
@@ -243,12 +543,16 @@ impl Registers {
             }
 
             if val1 & rv.comparison_mask != val2 & rv.comparison_mask {
-                maybe_log_reg_mismatch(mismatch_behavior, rv.name, name1, val1, name2, val2);
-                match_ = false;
+                diffs.push(RegisterDiff {
+                    name: rv.name,
+                    recorded: val1,
+                    live: val2,
+                    mask: rv.comparison_mask,
+                });
             }
         }
 
-        match_
+        diffs
     }
 
     fn compare_register_files_internal(
@@ -257,9 +561,17 @@ impl Registers {
         name2: &str,
         regs2: &Registers,
         mismatch_behavior: MismatchBehavior,
+        canonicalize: bool,
     ) -> bool {
         debug_assert_eq!(regs1.arch(), regs2.arch());
-        Registers::compare_registers_arch(name1, name2, regs1, regs2, mismatch_behavior)
+        Registers::compare_registers_arch(
+            name1,
+            name2,
+            regs1,
+            regs2,
+            mismatch_behavior,
+            canonicalize,
+        )
     }
 
     /// Return true if `regs1` matches `regs2`.  Passing EXPECT_MISMATCHES
@@ -268,6 +580,9 @@ impl Registers {
     /// mismatch.  Passing LOG_MISMATCHES will log the registers that don't
     /// match.  Passing BAIL_ON_MISMATCH will additionally abort on
     /// mismatch.
+    ///
+    /// DIFF NOTE: If `Flags::canonicalize_before_compare` is set, the canonical
+    /// forms of `regs1` and `regs2` are compared. See `canonical_form()`
     pub fn compare_register_files(
         maybe_t: Option<&ReplayTask>,
         name1: &str,
@@ -283,6 +598,7 @@ impl Registers {
             name2,
             regs2,
             mismatch_behavior,
+            Flags::get().canonicalize_before_compare,
         );
         if let Some(t) = maybe_t {
             ed_assert!(
@@ -308,17 +624,85 @@ impl Registers {
         match_
     }
 
+    /// Compare `a` and `b` register by register. Registers in `tolerance`
+    /// match if their values differ by at most the given amount; all other
+    /// registers must be equal (after applying their comparison mask).
+    /// DIFF NOTE: Not present in rr
+    pub fn compare_with_tolerance(
+        a: &Registers,
+        b: &Registers,
+        tolerance: &HashMap<GdbRegister, u64>,
+    ) -> ComparisonReport {
+        debug_assert_eq!(a.arch(), b.arch());
+        let mut report = ComparisonReport::default();
+        for (&regno, rv) in a.get_regs_info() {
+            let mut buf1 = [0u8; 8];
+            let mut buf2 = [0u8; 8];
+            if a.read_register(&mut buf1, regno).is_none()
+                || b.read_register(&mut buf2, regno).is_none()
+            {
+                continue;
+            }
+            let value1 = u64::from_le_bytes(buf1) & rv.comparison_mask;
+            let value2 = u64::from_le_bytes(buf2) & rv.comparison_mask;
+            let allowed = tolerance.get(&regno).copied().unwrap_or(0);
+            let diff = if value1 > value2 {
+                value1 - value2
+            } else {
+                value2 - value1
+            };
+            if diff > allowed {
+                report.mismatches.push(RegisterMismatch {
+                    regno,
+                    name: rv.name,
+                    value1,
+                    value2,
+                });
+            }
+        }
+        report
+    }
+
+    /// Unlike `matches()` (and `==`) this compares every byte of the
+    /// user_regs_struct, without applying comparison masks.
+    /// DIFF NOTE: Not present in rr
+    pub fn identical(&self, other: &Registers) -> bool {
+        self.arch() == other.arch()
+            && self.get_ptrace_for_self_arch() == other.get_ptrace_for_self_arch()
+    }
+
+    /// DIFF NOTE: rr calls compare_register_files() here. We never canonicalize
+    /// because `==` must stay consistent with `Hash`
     pub fn matches(&self, other: &Registers) -> bool {
-        Registers::compare_register_files(
-            None,
+        Registers::compare_register_files_internal(
             "",
             self,
             "",
             other,
             MismatchBehavior::ExpectMismatches,
+            false,
         )
     }
 
+    /// A copy of these registers with the architecturally reserved bits
+    /// normalized: bit 1 of EFLAGS is set and the reserved-zero bits (see
+    /// `X86_RESERVED_ZERO_FLAGS`) are cleared. On x64 the upper 32 bits of the
+    /// segment selectors are also cleared.
+    /// DIFF NOTE: Not present in rr
+    pub fn canonical_form(&self) -> Registers {
+        let mut r = self.clone();
+        r.set_flags((r.flags() | X86_RESERVED_FLAG) & !X86_RESERVED_ZERO_FLAGS);
+        if let X64(regs) = &mut r {
+            regs.cs &= 0xffffffff;
+            regs.ss &= 0xffffffff;
+            regs.ds &= 0xffffffff;
+            regs.es &= 0xffffffff;
+            regs.fs &= 0xffffffff;
+            regs.gs &= 0xffffffff;
+        }
+        r
+    }
+
     /// Write the value for register `regno` into `buf`, which should
     /// be large enough to hold any register supported by the target.
     /// Return the size of the register in bytes. If None is returned it
@@ -350,16 +734,139 @@ impl Registers {
         }
     }
 
+    /// Return the name and value of every readable register whose name fully
+    /// matches the regex `pattern`, sorted by GDB register number.
+    /// DIFF NOTE: Not present in rr
+    #[cfg(feature = "regex")]
+    pub fn read_registers_matching(
+        &self,
+        pattern: &str,
+    ) -> std::result::Result<Vec<(String, u64)>, regex::Error> {
+        let re = regex::Regex::new(&format!("^(?:{})$", pattern))?;
+        Ok(self
+            .iter()
+            .filter(|(name, _)| re.is_match(name))
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect())
+    }
+
+    /// Read the register called `name` e.g. "rax". Names of the other arch's
+    /// registers like "eax" on x64 are accepted too.
+    /// DIFF NOTE: Not present in rr
+    pub fn read_by_name(&self, name: &str) -> Option<u64> {
+        let (regno, nbytes) = gdb_register_by_name(self.arch(), name)?;
+        let mut buf = [0u8; 8];
+        self.read_register(&mut buf, regno)?;
+        Some(u64::from_le_bytes(buf) & RegisterValue::mask_for_nbytes(nbytes))
+    }
+
+    /// Returns false if there is no register called `name` or `value` does not
+    /// fit in it. Writing e.g. "eax" on x64 zero extends `value` into rax.
+    /// The upper 32 bits of "rflags" on x64 are reserved and must be zero.
+    /// DIFF NOTE: Not present in rr
+    pub fn write_by_name(&mut self, name: &str, value: u64) -> bool {
+        let (regno, nbytes) = match gdb_register_by_name(self.arch(), name) {
+            Some(r) => r,
+            None => return false,
+        };
+        // Registers like x64 eflags are stored with fewer bytes than their alias
+        let nbytes = min(nbytes, self.get_regs_info()[&regno].nbytes);
+        if nbytes == 0 || value & !RegisterValue::mask_for_nbytes(nbytes) != 0 {
+            return false;
+        }
+        self.write_register(&value.to_le_bytes(), regno).is_ok()
+    }
+
+    /// Write the `live` value of each of `diffs` (see `diff()`) to the register
+    /// of the same name. Nothing is written if any of the names is not a
+    /// register of this arch.
+    /// DIFF NOTE: Not present in rr
+    pub fn apply_diff(&mut self, diffs: &[RegisterDiff]) -> std::result::Result<(), RegisterError> {
+        let mut writes = Vec::with_capacity(diffs.len());
+        for d in diffs {
+            match gdb_register_by_name(self.arch(), d.name) {
+                Some((regno, _)) => writes.push((regno, d.live)),
+                None => return Err(RegisterError::UnknownRegisterName(d.name)),
+            }
+        }
+        for (regno, value) in writes {
+            // Only the low bytes of `value` that fit in the register are used
+            self.write_register(&value.to_le_bytes(), regno)?;
+        }
+        Ok(())
+    }
+
+    /// Snapshot of every register that can be read, in GdbRegister order.
+    /// Restore it with `set_all()`.
+    /// DIFF NOTE: Not present in rr
+    pub fn get_all(&self) -> Vec<(GdbRegister, u64)> {
+        self.get_regs_info()
+            .keys()
+            .filter_map(|&regno| {
+                let mut buf = [0u8; 8];
+                self.read_register(&mut buf, regno)?;
+                Some((regno, u64::from_le_bytes(buf)))
+            })
+            .collect()
+    }
+
+    /// Write each register in `values` e.g. a snapshot from `get_all()`.
+    /// Registers that are not part of this arch are ignored.
+    /// DIFF NOTE: Not present in rr
+    pub fn set_all(&mut self, values: &[(GdbRegister, u64)]) {
+        for &(regno, value) in values {
+            if self.get_regs_info().contains_key(&regno) {
+                // Only the low `nbytes` bytes of `value` are used
+                self.write_register(&value.to_le_bytes(), regno).unwrap();
+            }
+        }
+    }
+
+    /// The name and value of every register that can be read, in GdbRegister
+    /// order.
+    /// DIFF NOTE: Not present in rr
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.get_regs_info().iter().filter_map(move |(&regno, rv)| {
+            let mut buf = [0u8; 8];
+            let nbytes = self.read_register(&mut buf, regno)?;
+            debug_assert!(nbytes <= 8);
+            Some((rv.name, u64::from_le_bytes(buf)))
+        })
+    }
+
+    /// Call `f` with a `RegisterEntry` for every register that can be read, in
+    /// GdbRegister order. The entry can be used to modify the register.
+    /// This is not an iterator since every entry needs to borrow `self` mutably.
+    /// DIFF NOTE: Not present in rr
+    pub fn for_each_mut<F: FnMut(RegisterEntry<'_>)>(&mut self, mut f: F) {
+        for (&regno, rv) in self.get_regs_info() {
+            let mut buf = [0u8; 8];
+            if self.read_register(&mut buf, regno).is_some() {
+                f(RegisterEntry {
+                    regs: &mut *self,
+                    regno,
+                    name: rv.name,
+                    value: u64::from_le_bytes(buf),
+                });
+            }
+        }
+    }
+
     /// Update the register named `reg_name` to `value` with
     /// `value_size` number of bytes.
-    pub fn write_register(&mut self, value: &[u8], regno: GdbRegister) {
+    /// DIFF NOTE: rr silently ignores registers that are not part of the arch
+    pub fn write_register(
+        &mut self,
+        value: &[u8],
+        regno: GdbRegister,
+    ) -> std::result::Result<(), RegisterError> {
         let regs = self.get_regs_info();
         if let Some(rv) = regs.get(&regno) {
             match rv.nbytes {
                 0 => {
                     // TODO: can we get away with not writing these?
                     if self.ignore_undefined_register(regno) {
-                        return;
+                        return Ok(());
                     }
                     log!(LogWarn, "Unhandled register name {}", regno);
                 }
@@ -379,6 +886,9 @@ impl Registers {
                     debug_assert!(false, format!("Unknown register size: {}", rv.nbytes));
                 }
             }
+            Ok(())
+        } else {
+            Err(RegisterError::UnknownRegister(regno))
         }
     }
 
@@ -438,6 +948,92 @@ impl Registers {
         }
     }
 
+    /// Build a Registers from the bytes of a kernel `struct ucontext` e.g. as
+    /// pushed on the stack by the kernel when a signal handler is entered.
+    /// The general purpose registers are read from `uc_mcontext`.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn set_from_ucontext(
+        arch: SupportedArch,
+        uctx_bytes: &[u8],
+    ) -> std::result::Result<Registers, UcontextError> {
+        let (offset, size) = match arch {
+            SupportedArch::X86 => (UCONTEXT_MCONTEXT_OFFSET_X86, size_of::<x86::sigcontext>()),
+            SupportedArch::X64 => (UCONTEXT_MCONTEXT_OFFSET_X64, size_of::<x64::sigcontext>()),
+        };
+        if uctx_bytes.len() < offset + size {
+            return Err(UcontextError::TooShort {
+                needed: offset + size,
+                got: uctx_bytes.len(),
+            });
+        }
+
+        let mcontext = &uctx_bytes[offset..offset + size];
+        let regs = match arch {
+            SupportedArch::X86 => {
+                let sc: x86::sigcontext =
+                    unsafe { std::ptr::read_unaligned(mcontext.as_ptr() as *const _) };
+                X86(x86::user_regs_struct {
+                    ebx: sc.bx as i32,
+                    ecx: sc.cx as i32,
+                    edx: sc.dx as i32,
+                    esi: sc.si as i32,
+                    edi: sc.di as i32,
+                    ebp: sc.bp as i32,
+                    eax: sc.ax as i32,
+                    xds: sc.ds as i32,
+                    xes: sc.es as i32,
+                    xfs: sc.fs as i32,
+                    xgs: sc.gs as i32,
+                    // We're not in a syscall
+                    orig_eax: -1,
+                    eip: sc.ip as i32,
+                    xcs: sc.cs as i32,
+                    eflags: sc.flags as i32,
+                    esp: sc.sp as i32,
+                    xss: sc.ss as i32,
+                })
+            }
+            SupportedArch::X64 => {
+                let sc: x64::sigcontext =
+                    unsafe { std::ptr::read_unaligned(mcontext.as_ptr() as *const _) };
+                X64(x64::user_regs_struct {
+                    r15: sc.r15,
+                    r14: sc.r14,
+                    r13: sc.r13,
+                    r12: sc.r12,
+                    rbp: sc.bp,
+                    rbx: sc.bx,
+                    r11: sc.r11,
+                    r10: sc.r10,
+                    r9: sc.r9,
+                    r8: sc.r8,
+                    rax: sc.ax,
+                    rcx: sc.cx,
+                    rdx: sc.dx,
+                    rsi: sc.si,
+                    rdi: sc.di,
+                    // We're not in a syscall
+                    orig_rax: -1i64 as u64,
+                    rip: sc.ip,
+                    cs: sc.cs as u64,
+                    eflags: sc.flags,
+                    rsp: sc.sp,
+                    // ss, fs_base, gs_base, ds, es are not part of the x64 sigcontext
+                    ss: 0,
+                    fs_base: 0,
+                    gs_base: 0,
+                    ds: 0,
+                    es: 0,
+                    fs: sc.fs as u64,
+                    gs: sc.gs as u64,
+                })
+            }
+        };
+
+        Ok(regs)
+    }
+
     pub fn arch(&self) -> SupportedArch {
         match self {
             X86(_) => SupportedArch::X86,
@@ -544,6 +1140,45 @@ impl Registers {
         }
     }
 
+    /// Encode these registers as the descriptor of an ELF `NT_PRSTATUS` note
+    /// i.e. a `struct elf_prstatus` (see <sys/procfs.h>) for task `tid` that
+    /// stopped with signal `signo`. Fields other than the signal, pid and
+    /// registers are zero.
+    /// DIFF NOTE: Not present in rr
+    pub fn encode_as_nt_prstatus(&self, tid: pid_t, signo: i32) -> Vec<u8> {
+        let layout = prstatus_layout(self.arch());
+        let mut buf = vec![0u8; layout.size];
+        // pr_info.si_signo
+        buf[0..4].copy_from_slice(&signo.to_le_bytes());
+        buf[PRSTATUS_CURSIG_OFFSET..PRSTATUS_CURSIG_OFFSET + 2]
+            .copy_from_slice(&(signo as i16).to_le_bytes());
+        buf[layout.pid_offset..layout.pid_offset + 4].copy_from_slice(&tid.to_le_bytes());
+        let regs = self.get_ptrace_for_self_arch();
+        buf[layout.reg_offset..layout.reg_offset + regs.len()].copy_from_slice(regs);
+        buf
+    }
+
+    /// Parse the registers out of the descriptor of an ELF `NT_PRSTATUS` note
+    /// for a task of arch `arch`. Returns `None` if `note` is too short.
+    /// DIFF NOTE: Not present in rr
+    pub fn from_core_dump_note(arch: SupportedArch, note: &[u8]) -> Option<Registers> {
+        let layout = prstatus_layout(arch);
+        let regs_size = match arch {
+            SupportedArch::X86 => size_of::<x86::user_regs_struct>(),
+            SupportedArch::X64 => size_of::<x64::user_regs_struct>(),
+        };
+        if note.len() < layout.reg_offset + regs_size {
+            return None;
+        }
+
+        let mut r = Registers::new(arch);
+        r.set_from_ptrace_for_arch(
+            arch,
+            &note[layout.reg_offset..layout.reg_offset + regs_size],
+        );
+        Some(r)
+    }
+
     /// Get a user_regs_struct for a particular Arch from these Registers.
     /// It's invalid to call this when 'arch' is 64-bit and the
     /// rd build is 32-bit, or when the Registers' arch is completely different
@@ -568,7 +1203,8 @@ impl Registers {
     /// to the rd build (e.g. ARM vs x86).
     pub fn set_from_ptrace_for_arch(&mut self, arch: SupportedArch, data: &[u8]) {
         if arch == RD_NATIVE_ARCH {
-            debug_assert_eq!(data.len(), size_of::<native_user_regs_struct>());
+            // DIFF NOTE: rr only asserts this in debug builds
+            assert_eq!(data.len(), size_of::<native_user_regs_struct>());
             let mut n: native_user_regs_struct = Default::default();
             unsafe {
                 copy_nonoverlapping(data.as_ptr(), &raw mut n as *mut u8, data.len());
@@ -578,7 +1214,7 @@ impl Registers {
             debug_assert_eq!(arch, SupportedArch::X86);
             debug_assert_eq!(RD_NATIVE_ARCH, SupportedArch::X64);
             debug_assert_eq!(self.arch(), SupportedArch::X86);
-            debug_assert_eq!(data.len(), size_of::<x86::user_regs_struct>());
+            assert_eq!(data.len(), size_of::<x86::user_regs_struct>());
             unsafe {
                 copy_nonoverlapping(
                     data.as_ptr(),
@@ -626,6 +1262,21 @@ impl Registers {
         }
     }
 
+    /// Name of the flags register for display e.g. in error messages.
+    /// DIFF NOTE: Not present in rr
+    pub fn flags_register_name(&self) -> &'static str {
+        match self {
+            X86(_) => "eflags",
+            X64(_) => "rflags",
+        }
+    }
+
+    /// Size of the flags register in bytes.
+    /// DIFF NOTE: Not present in rr
+    pub fn flags_register_size(&self) -> usize {
+        self.arch().word_size()
+    }
+
     pub fn set_flags(&mut self, value: usize) {
         match self {
             X86(regs_x86) => regs_x86.eflags = value as i32,
@@ -639,6 +1290,17 @@ impl Registers {
         -4096 < result && result < 0
     }
 
+    /// Returns the syscall result as a pointer for pointer-returning syscalls
+    /// like mmap or shmat, or `None` if syscall_result() indicates failure.
+    /// DIFF NOTE: Not present in rr
+    pub fn syscall_result_as_ptr<T>(&self) -> Option<RemotePtr<T>> {
+        if self.syscall_failed() {
+            None
+        } else {
+            Some(RemotePtr::new(self.syscall_result()))
+        }
+    }
+
     /// Returns true if syscall_result() indicates a syscall restart.
     pub fn syscall_may_restart(&self) -> bool {
         // Note the negation
@@ -657,6 +1319,26 @@ impl Registers {
         rd_set_reg!(self, eip, rip, addr.as_usize());
     }
 
+    /// Move ip() back by `bytes` e.g. to re-execute a syscall or faulting
+    /// instruction. Wraps around at the address width of this Registers' arch.
+    /// DIFF NOTE: Not present in rr
+    pub fn rewind_ip_by(&mut self, bytes: usize) {
+        match self {
+            X86(regs) => regs.eip = (Wrapping(regs.eip as u32) - Wrapping(bytes as u32)).0 as i32,
+            X64(regs) => regs.rip = (Wrapping(regs.rip) - Wrapping(bytes as u64)).0,
+        }
+    }
+
+    /// Move ip() forward by `bytes`. Wraps around at the address width of this
+    /// Registers' arch.
+    /// DIFF NOTE: Not present in rr
+    pub fn advance_ip_by(&mut self, bytes: usize) {
+        match self {
+            X86(regs) => regs.eip = (Wrapping(regs.eip as u32) + Wrapping(bytes as u32)).0 as i32,
+            X64(regs) => regs.rip = (Wrapping(regs.rip) + Wrapping(bytes as u64)).0,
+        }
+    }
+
     pub fn sp(&self) -> RemotePtr<Void> {
         let addr = rd_get_reg!(self, esp, rsp);
         RemotePtr::<u8>::new(addr)
@@ -799,24 +1481,95 @@ impl Registers {
     }
 
     /// NOTE: Arg count starts from 1 and NOT 0
-    pub fn arg(&self, index: usize) -> usize {
+    /// DIFF NOTE: rr returns the value directly and debug asserts on the index
+    pub fn arg(&self, index: usize) -> std::result::Result<usize, RegisterError> {
         match index {
-            1 => self.arg1(),
-            2 => self.arg2(),
-            3 => self.arg3(),
-            4 => self.arg4(),
-            5 => self.arg5(),
-            6 => self.arg6(),
-            _ => {
-                // DIFF NOTE: This is a debug assert in rr
-                assert!(false, "Argument index out of range");
-                0
-            }
+            1 => Ok(self.arg1()),
+            2 => Ok(self.arg2()),
+            3 => Ok(self.arg3()),
+            4 => Ok(self.arg4()),
+            5 => Ok(self.arg5()),
+            6 => Ok(self.arg6()),
+            _ => Err(RegisterError::ArgIndexOutOfRange(index)),
+        }
+    }
+
+    /// Like `arg()` but panics if `index` is out of range.
+    /// NOTE: Arg count starts from 1 and NOT 0
+    pub fn arg_unchecked(&self, index: usize) -> usize {
+        match self.arg(index) {
+            Ok(value) => value,
+            // DIFF NOTE: This is a debug assert in rr
+            Err(_) => panic!("Argument index out of range"),
         }
     }
 
+    /// Returns true if syscall argument `index` looks like a plausible fd i.e.
+    /// 0 <= fd <= 1048576 (the default upper bound of `ulimit -n`).
     /// NOTE: Arg count starts from 1 and NOT 0
-    pub fn set_arg(&mut self, index: usize, value: usize) {
+    /// DIFF NOTE: Not present in rr
+    pub fn arg_is_valid_fd(&self, index: usize) -> bool {
+        match self.arg(index) {
+            Ok(fd) => (0..=1048576).contains(&(fd as i32)),
+            Err(_) => false,
+        }
+    }
+
+    /// Read the C string in `task`'s memory that syscall argument `index`
+    /// points to. Returns None if the argument is a null pointer.
+    /// NOTE: Arg count starts from 1 and NOT 0
+    /// DIFF NOTE: Not present in rr
+    pub fn arg_as_cstr(&self, task: &mut dyn Task, index: usize) -> Option<CString> {
+        let child_addr = RemotePtr::<u8>::new(self.arg(index).ok()?);
+        if child_addr.is_null() {
+            None
+        } else {
+            Some(task.read_c_str(child_addr))
+        }
+    }
+
+    /// All six syscall arguments, `arg1()` first.
+    /// DIFF NOTE: Not present in rr
+    pub fn syscall_args(&self) -> [usize; 6] {
+        [
+            self.arg1(),
+            self.arg2(),
+            self.arg3(),
+            self.arg4(),
+            self.arg5(),
+            self.arg6(),
+        ]
+    }
+
+    /// Set all six syscall arguments, `args[0]` being `arg1`.
+    /// DIFF NOTE: Not present in rr
+    pub fn set_syscall_args(&mut self, args: [usize; 6]) {
+        self.set_arg1(args[0]);
+        self.set_arg2(args[1]);
+        self.set_arg3(args[2]);
+        self.set_arg4(args[3]);
+        self.set_arg5(args[4]);
+        self.set_arg6(args[5]);
+    }
+
+    /// The number of arguments (0-6) syscall `syscallno` takes on `arch`, or
+    /// `None` if unknown.
+    /// DIFF NOTE: Not present in rr
+    pub fn arg_count_for_syscall(arch: SupportedArch, syscallno: isize) -> Option<u8> {
+        let syscallno: i32 = syscallno.try_into().ok()?;
+        match arch {
+            SupportedArch::X86 => x86::syscall_arg_count_arch(syscallno),
+            SupportedArch::X64 => x64::syscall_arg_count_arch(syscallno),
+        }
+    }
+
+    /// NOTE: Arg count starts from 1 and NOT 0
+    /// DIFF NOTE: rr debug asserts on the index
+    pub fn set_arg(
+        &mut self,
+        index: usize,
+        value: usize,
+    ) -> std::result::Result<(), RegisterError> {
         match index {
             1 => self.set_arg1(value),
             2 => self.set_arg2(value),
@@ -824,29 +1577,30 @@ impl Registers {
             4 => self.set_arg4(value),
             5 => self.set_arg5(value),
             6 => self.set_arg6(value),
-            _ => {
-                // DIFF NOTE: This is a debug assert in rr
-                assert!(false, "Argument index out of range");
-            }
+            _ => return Err(RegisterError::ArgIndexOutOfRange(index)),
         }
+        Ok(())
     }
 
+    /// Like `set_arg()` but panics if `index` is out of range.
     /// NOTE: Arg count starts from 1 and NOT 0
-    pub fn set_arg_from_remote_ptr<T>(&mut self, index: i32, value: RemotePtr<T>) {
-        match index {
-            1 => self.set_arg1_from_remote_ptr(value),
-            2 => self.set_arg2_from_remote_ptr(value),
-            3 => self.set_arg3_from_remote_ptr(value),
-            4 => self.set_arg4_from_remote_ptr(value),
-            5 => self.set_arg5_from_remote_ptr(value),
-            6 => self.set_arg6_from_remote_ptr(value),
-            _ => {
-                // DIFF NOTE: This is a debug assert in rr
-                assert!(false, "Argument index out of range");
-            }
+    pub fn set_arg_unchecked(&mut self, index: usize, value: usize) {
+        if self.set_arg(index, value).is_err() {
+            // DIFF NOTE: This is a debug assert in rr
+            panic!("Argument index out of range");
         }
     }
 
+    /// NOTE: Arg count starts from 1 and NOT 0
+    /// DIFF NOTE: rr debug asserts on the index. `index` is an i32 in rr.
+    pub fn set_arg_from_remote_ptr<T>(
+        &mut self,
+        index: usize,
+        value: RemotePtr<T>,
+    ) -> std::result::Result<(), RegisterError> {
+        self.set_arg(index, value.as_usize())
+    }
+
     /// Set the output registers of the `rdtsc` instruction.
     pub fn set_rdtsc_output(&mut self, value: u64) {
         rd_set_reg!(self, eax, rax, value & 0xffffffff);
@@ -912,16 +1666,37 @@ impl Registers {
         rd_get_reg!(self, ebp, rbp)
     }
 
+    // DIFF NOTE: Not present in rr
+    // `ax()`, `cx()` etc. return the full register (like in rr) so the 16-bit
+    // parts are called `ax16()`, `cx16()` etc.
+    rd_sub_reg_accessors!(eax, rax, al, set_al, ah, set_ah, ax16, set_ax16, eax, set_eax);
+    rd_sub_reg_accessors!(ebx, rbx, bl, set_bl, bh, set_bh, bx16, set_bx16, ebx, set_ebx);
+    rd_sub_reg_accessors!(ecx, rcx, cl, set_cl, ch, set_ch, cx16, set_cx16, ecx, set_ecx);
+    rd_sub_reg_accessors!(edx, rdx, dl, set_dl, dh, set_dh, dx16, set_dx16, edx, set_edx);
+
+    /// DIFF NOTE: Not present in rr
+    pub fn eflags_fields(&self) -> EflagsFields {
+        EflagsFields::from_raw(self.flags())
+    }
+
+    /// DIFF NOTE: Not present in rr
+    pub fn set_eflags_fields(&mut self, fields: EflagsFields) {
+        self.set_flags(fields.to_raw());
+    }
+
     pub fn singlestep_flag(&self) -> bool {
-        self.flags() & X86_TF_FLAG == X86_TF_FLAG
+        self.eflags_fields().tf
     }
 
     pub fn clear_singlestep_flag(&mut self) {
-        self.set_flags(self.flags() & !X86_TF_FLAG);
+        self.set_eflags_fields(EflagsFields {
+            tf: false,
+            ..self.eflags_fields()
+        });
     }
 
     pub fn df_flag(&self) -> bool {
-        self.flags() & X86_DF_FLAG == X86_DF_FLAG
+        self.eflags_fields().df
     }
 
     /// DIFF NOTE: rr returns a usize instead
@@ -973,6 +1748,227 @@ impl Registers {
         rd_get_reg!(self, xgs, gs)
     }
 
+    /// Number of XMM registers architecturally available to the tracee.
+    pub fn xmm_register_count(&self) -> u8 {
+        match self {
+            X86(_) => 8,
+            X64(_) => 16,
+        }
+    }
+
+    /// @TODO XMM registers are not part of the general-purpose register set.
+    /// This always returns `None` ("not yet captured") until extended register
+    /// support lands in Registers.
+    /// DIFF NOTE: Not present in rr
+    pub fn read_xmm_placeholder(&self, index: u8) -> Option<[u8; 16]> {
+        debug_assert!(index < self.xmm_register_count());
+        None
+    }
+
+    /// Returns true if ip() is in the vDSO of `vm` and points at what looks
+    /// like a function prologue (`push %ebp`/`push %rbp` optionally preceded by
+    /// `endbr32`/`endbr64`).
+    /// DIFF NOTE: Not present in rr
+    pub fn is_at_vdso_entry(&self, vm: &AddressSpace) -> bool {
+        match self.read_vdso_insn_bytes(vm) {
+            Some(insn) => insn[0] == PUSH_BP_INSN || insn == ENDBR32_INSN || insn == ENDBR64_INSN,
+            None => false,
+        }
+    }
+
+    /// Returns true if ip() is in the vDSO of `vm` and points at a `ret`.
+    /// DIFF NOTE: Not present in rr
+    pub fn is_at_vdso_exit(&self, vm: &AddressSpace) -> bool {
+        match self.read_vdso_insn_bytes(vm) {
+            Some(insn) => insn[0] == RET_INSN || insn[0] == RET_IMM16_INSN,
+            None => false,
+        }
+    }
+
+    /// Read the first few bytes of the instruction at ip() if ip() is in the
+    /// vDSO. Any breakpoints are replaced by the original instruction bytes.
+    fn read_vdso_insn_bytes(&self, vm: &AddressSpace) -> Option<[u8; 4]> {
+        let ip = self.ip().to_data_ptr::<Void>();
+        if !vm.vdso().contains_ptr(ip) {
+            return None;
+        }
+
+        let mut insn = [0u8; 4];
+        let nread = pread(vm.mem_fd().as_raw(), &mut insn, ip.as_usize() as i64).ok()?;
+        // We only need 1 byte for `push` and `ret`
+        if nread < 1 {
+            return None;
+        }
+        vm.replace_breakpoints_with_original_values(&mut insn[0..nread], RemotePtr::cast(ip));
+        Some(insn)
+    }
+
+    /// Returns a bitmask where bit `n` is set iff the GDB register numbered `n`
+    /// is callee-saved according to the ABI of this Registers' arch.
+    /// (System V AMD64: rbx, rbp, rsp, r12-r15. i386: ebx, esi, edi, ebp, esp.)
+    /// DIFF NOTE: Not present in rr
+    pub fn call_saved_register_mask(&self) -> u64 {
+        let call_saved: &[GdbRegister] = match self {
+            X86(_) => &[DREG_EBX, DREG_ESP, DREG_EBP, DREG_ESI, DREG_EDI],
+            X64(_) => &[
+                DREG_RBX, DREG_RBP, DREG_RSP, DREG_R12, DREG_R13, DREG_R14, DREG_R15,
+            ],
+        };
+
+        call_saved
+            .iter()
+            .fold(0, |mask, regno| mask | (1u64 << regno.as_usize()))
+    }
+
+    /// Decode the target of a direct far branch (`EA ptr16:32` ljmp or
+    /// `9A ptr16:32` lcall) at the start of `instruction_bytes` and return
+    /// `(new_cs, new_ip)`.
+    ///
+    /// Returns `None` for anything else, including indirect far branches
+    /// (`FF /3`, `FF /5`) whose target lives in memory and needs to be
+    /// dereferenced by the caller. Always returns `None` on x64 where the
+    /// direct forms are invalid opcodes.
+    ///
+    /// DIFF NOTE: Not present in rr. Operand size prefixes are not handled.
+    pub fn decode_far_branch_target(&self, instruction_bytes: &[u8]) -> Option<(u16, u32)> {
+        if self.arch() == SupportedArch::X64 {
+            return None;
+        }
+        match instruction_bytes {
+            [0xea, o0, o1, o2, o3, s0, s1, ..] | [0x9a, o0, o1, o2, o3, s0, s1, ..] => Some((
+                u16::from_le_bytes([*s0, *s1]),
+                u32::from_le_bytes([*o0, *o1, *o2, *o3]),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Classify `instruction_bytes`, the code at `ip()`, as one of the x86
+    /// system call instructions. Returns None if it is not one.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn decode_syscall_at_ip(&self, instruction_bytes: &[u8]) -> Option<SyscallInstructionType> {
+        match instruction_bytes.get(0..2)? {
+            insn if insn == SYSCALL_INSN => Some(SyscallInstructionType::Syscall),
+            insn if insn == SYSENTER_INSN => Some(SyscallInstructionType::Sysenter),
+            insn if insn == INT80_INSN => Some(SyscallInstructionType::Int80),
+            _ => None,
+        }
+    }
+
+    /// Opcode bytes of the software breakpoint instruction for this arch.
+    ///
+    /// DIFF NOTE: Not present in rr
+    /// @TODO Return `BRK #0` (4 bytes) when/if AArch64 is supported.
+    pub fn breakpoint_instruction_bytes(&self) -> &'static [u8] {
+        match self.arch() {
+            SupportedArch::X86 | SupportedArch::X64 => &[AddressSpace::BREAKPOINT_INSN],
+        }
+    }
+
+    /// Number of bytes that need to be patched (and restored) to set a
+    /// software breakpoint.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn breakpoint_instruction_length(&self) -> usize {
+        self.breakpoint_instruction_bytes().len()
+    }
+
+    /// Write the value of `regno` as the reply to a GDB remote protocol `p`
+    /// packet: each byte, in target (little-endian) order, as two hex digits.
+    /// An undefined register is written as "xx" for each byte.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn write_register_for_gdb_stub(
+        &self,
+        w: &mut dyn Write,
+        regno: GdbRegister,
+    ) -> io::Result<()> {
+        let mut buf = [0u8; 8];
+        match self.read_register(&mut buf, regno) {
+            Some(nbytes) => {
+                for b in &buf[0..nbytes] {
+                    write!(w, "{:02x}", b)?;
+                }
+            }
+            None => {
+                let nbytes = match self.get_regs_info().get(&regno) {
+                    Some(rv) => rv.nbytes,
+                    None => word_size(self.arch()),
+                };
+                for _ in 0..nbytes {
+                    write!(w, "xx")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the general purpose registers as the reply to a GDB remote
+    /// protocol `g` packet i.e. `write_register_for_gdb_stub()` for each of them
+    /// in GDB register order. The floating point and vector registers are left
+    /// out of the reply; gdb fetches those with `p` packets.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn write_registers_for_gdb_stub(&self, w: &mut dyn Write) -> io::Result<()> {
+        for regno in self.gdb_general_registers() {
+            self.write_register_for_gdb_stub(w, regno)?;
+        }
+        Ok(())
+    }
+
+    /// Update the general purpose registers from the payload of a GDB remote
+    /// protocol `G` packet (without the leading 'G'), laid out as in
+    /// `write_registers_for_gdb_stub()`. Registers sent as "xx" are left
+    /// unchanged, as is anything after the general purpose registers.
+    /// Returns false if the payload is malformed, in which case some registers
+    /// may already have been updated.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn read_registers_from_gdb_stub(&mut self, hex: &[u8]) -> bool {
+        let mut rest = hex;
+        for regno in self.gdb_general_registers() {
+            if rest.is_empty() {
+                break;
+            }
+            let nbytes = match self.get_regs_info().get(&regno) {
+                Some(rv) if rv.nbytes > 0 => rv.nbytes,
+                _ => return false,
+            };
+            if rest.len() < 2 * nbytes {
+                return false;
+            }
+            let (reg_hex, tail) = rest.split_at(2 * nbytes);
+            rest = tail;
+            if reg_hex.iter().all(|&c| c == b'x') {
+                continue;
+            }
+            let mut value = [0u8; 8];
+            for (i, digits) in reg_hex.chunks(2).enumerate() {
+                match std::str::from_utf8(digits)
+                    .ok()
+                    .and_then(|d| u8::from_str_radix(d, 16).ok())
+                {
+                    Some(b) => value[i] = b,
+                    None => return false,
+                }
+            }
+            if self.write_register(&value[0..nbytes], regno).is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The registers in a GDB `g` packet, in order.
+    fn gdb_general_registers(&self) -> impl Iterator<Item = GdbRegister> {
+        let end = match self {
+            X86(_) => DREG_FIRST_FXSAVE_REG,
+            X64(_) => DREG_64_FIRST_FXSAVE_REG,
+        };
+        (0..end.as_usize() as u32).filter_map(|regno| GdbRegister::try_from(regno).ok())
+    }
+
     pub fn write_register_file_for_trace_raw(&self, f: &mut dyn Write) -> io::Result<()> {
         let x86 = match self {
             X86(x86_regs) => *x86_regs,
@@ -1105,6 +2101,20 @@ impl Registers {
         Ok(())
     }
 
+    /// Like `write_register_file()` but as a single line JSON object mapping each
+    /// register name to its value, plus an "arch" field e.g.
+    /// `{"arch":"X64","eflags":514,"rax":0,...}`
+    /// DIFF NOTE: Not present in rr
+    pub fn write_register_file_json(&self, f: &mut dyn Write) -> io::Result<()> {
+        let mut obj = serde_json::Map::new();
+        obj.insert("arch".into(), serde_json::to_value(self.arch())?);
+        for (name, value) in self.iter() {
+            obj.insert(name.into(), value.into());
+        }
+        serde_json::to_writer(&mut *f, &obj)?;
+        writeln!(f)
+    }
+
     pub fn write_register_file_compact(&self, f: &mut dyn Write) -> io::Result<()> {
         self.write_register_file_for_trace(f, TraceStyle::Annotated)
     }
@@ -1447,6 +2457,55 @@ macro_rules! rv_x86_with_mask {
     };
 }
 
+// `user_regs_struct` is part of the ptrace ABI (see arch/x86/include/asm/user_32.h and
+// user_64.h in the kernel). Make sure our definitions lay it out exactly like the kernel does.
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, ebx), 0);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, ecx), 4);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, edx), 8);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, esi), 12);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, edi), 16);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, ebp), 20);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, eax), 24);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, xds), 28);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, xes), 32);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, xfs), 36);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, xgs), 40);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, orig_eax), 44);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, eip), 48);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, xcs), 52);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, eflags), 56);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, esp), 60);
+const_assert_eq!(mem::offset_of!(x86::user_regs_struct, xss), 64);
+const_assert_eq!(size_of::<x86::user_regs_struct>(), 68);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, r15), 0);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, r14), 8);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, r13), 16);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, r12), 24);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, rbp), 32);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, rbx), 40);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, r11), 48);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, r10), 56);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, r9), 64);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, r8), 72);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, rax), 80);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, rcx), 88);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, rdx), 96);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, rsi), 104);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, rdi), 112);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, orig_rax), 120);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, rip), 128);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, cs), 136);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, eflags), 144);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, rsp), 152);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, ss), 160);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, fs_base), 168);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, gs_base), 176);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, ds), 184);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, es), 192);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, fs), 200);
+const_assert_eq!(mem::offset_of!(x64::user_regs_struct, gs), 208);
+const_assert_eq!(size_of::<x64::user_regs_struct>(), 216);
+
 fn x86regs() -> BTreeMap<GdbRegister, RegisterValue> {
     let regs = [
         rv_x86!(DREG_EAX, eax),
@@ -1523,6 +2582,76 @@ fn x64regs() -> BTreeMap<GdbRegister, RegisterValue> {
     map
 }
 
+/// (alias, name in the arch's regs info, size in bytes of the alias)
+const REGISTER_ALIASES_X86: &[(&str, &str, usize)] = &[
+    ("rax", "eax", 4),
+    ("rcx", "ecx", 4),
+    ("rdx", "edx", 4),
+    ("rbx", "ebx", 4),
+    ("rsp", "esp", 4),
+    ("rbp", "ebp", 4),
+    ("rsi", "esi", 4),
+    ("rdi", "edi", 4),
+    ("rip", "eip", 4),
+    ("rflags", "eflags", 4),
+    ("cs", "xcs", 4),
+    ("ss", "xss", 4),
+    ("ds", "xds", 4),
+    ("es", "xes", 4),
+    ("fs", "xfs", 4),
+    ("gs", "xgs", 4),
+    ("orig_rax", "orig_eax", 4),
+];
+
+/// The 32-bit names refer to the lower 32 bits of the 64-bit registers
+const REGISTER_ALIASES_X64: &[(&str, &str, usize)] = &[
+    ("eax", "rax", 4),
+    ("ecx", "rcx", 4),
+    ("edx", "rdx", 4),
+    ("ebx", "rbx", 4),
+    ("esp", "rsp", 4),
+    ("ebp", "rbp", 4),
+    ("esi", "rsi", 4),
+    ("edi", "rdi", 4),
+    ("eip", "rip", 4),
+    ("rflags", "eflags", 8),
+    ("orig_eax", "orig_rax", 4),
+];
+
+fn register_names(
+    regs: &BTreeMap<GdbRegister, RegisterValue>,
+    aliases: &[(&'static str, &str, usize)],
+) -> HashMap<&'static str, (GdbRegister, usize)> {
+    let mut names = HashMap::new();
+    for (&regno, rv) in regs {
+        names.insert(rv.name, (regno, rv.nbytes));
+    }
+    for &(alias, name, nbytes) in aliases {
+        let (regno, _) = names[name];
+        names.insert(alias, (regno, nbytes));
+    }
+    names
+}
+
+/// The registers of `arch` that `Registers` holds, keyed by GDB register number.
+/// DIFF NOTE: Not present in rr
+pub fn regs_info_for_arch(arch: SupportedArch) -> &'static BTreeMap<GdbRegister, RegisterValue> {
+    match arch {
+        SupportedArch::X86 => &*REGISTERS_X86,
+        SupportedArch::X64 => &*REGISTERS_X64,
+    }
+}
+
+/// The register called `name` (or one of its aliases) in `arch` and the size
+/// in bytes of the register accessed via that name.
+/// DIFF NOTE: Not present in rr
+pub fn gdb_register_by_name(arch: SupportedArch, name: &str) -> Option<(GdbRegister, usize)> {
+    match arch {
+        SupportedArch::X86 => REGISTER_NAMES_X86.get(name).copied(),
+        SupportedArch::X64 => REGISTER_NAMES_X64.get(name).copied(),
+    }
+}
+
 fn maybe_log_reg_mismatch(
     mismatch_behavior: MismatchBehavior,
     regname: &str,
@@ -1580,3 +2709,799 @@ impl Default for Registers {
         Registers::X86(x86::user_regs_struct::default())
     }
 }
+
+/// Two `Registers` are equal iff they have the same arch and
+/// `Registers::diff()` finds no differences i.e. registers are compared
+/// using their comparison masks, just like `compare_register_files()`.
+/// The exception is orig_eax/orig_rax: diff() only compares them when the
+/// recorded value is >= 0 and the live one > 0, which is not symmetric, so
+/// they are left out here (like they are in `Hash`).
+/// DIFF NOTE: Not present in rr
+impl PartialEq for Registers {
+    fn eq(&self, other: &Self) -> bool {
+        self.arch() == other.arch()
+            && Registers::diff(self, other)
+                .iter()
+                .all(|d| d.name == "orig_eax" || d.name == "orig_rax")
+    }
+}
+
+impl Eq for Registers {}
+
+/// Only the masked values of registers that take part in comparisons are
+/// hashed so that this is consistent with `PartialEq`.
+/// DIFF NOTE: Not present in rr
+impl Hash for Registers {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.arch().hash(state);
+        for (&regno, rv) in self.get_regs_info() {
+            if rv.nbytes == 0 || rv.comparison_mask == 0 {
+                continue;
+            }
+            let mut buf = [0u8; 8];
+            if self.read_register(&mut buf, regno).is_some() {
+                (u64::from_le_bytes(buf) & rv.comparison_mask).hash(state);
+            }
+        }
+    }
+}
+
+/// How `Registers` are (de)serialized: the arch and the raw bytes of the
+/// arch's user_regs_struct.
+/// DIFF NOTE: Not present in rr
+#[derive(Serialize, Deserialize)]
+struct SerializedRegisters {
+    arch: SupportedArch,
+    user_regs: Vec<u8>,
+}
+
+impl Serialize for Registers {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        SerializedRegisters {
+            arch: self.arch(),
+            user_regs: self.get_ptrace_for_self_arch().to_vec(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Registers {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let serialized = SerializedRegisters::deserialize(deserializer)?;
+        Registers::try_from((serialized.arch, serialized.user_regs.as_slice())).map_err(|_| {
+            D::Error::invalid_length(
+                serialized.user_regs.len(),
+                &"the size of the user_regs_struct of the arch",
+            )
+        })
+    }
+}
+
+/// Arch byte used by the `Vec<u8>` encoding of `Registers`.
+const REGISTERS_ARCH_X86: u8 = 0;
+const REGISTERS_ARCH_X64: u8 = 1;
+
+/// Registers from the raw bytes of a user_regs_struct of `arch`.
+/// DIFF NOTE: Not present in rr
+impl TryFrom<(SupportedArch, &[u8])> for Registers {
+    type Error = RegistersFromBytesError;
+
+    fn try_from(
+        (arch, data): (SupportedArch, &[u8]),
+    ) -> std::result::Result<Self, RegistersFromBytesError> {
+        let mut r = Registers::new(arch);
+        let l = r.get_ptrace_for_self_arch().len();
+        if data.len() != l {
+            return Err(RegistersFromBytesError::WrongLength {
+                expected: l,
+                got: data.len(),
+            });
+        }
+        let dest = match &mut r {
+            X86(regs_x86) => regs_x86 as *mut x86::user_regs_struct as *mut u8,
+            X64(regs_x64) => regs_x64 as *mut x64::user_regs_struct as *mut u8,
+        };
+        unsafe {
+            copy_nonoverlapping(data.as_ptr(), dest, l);
+        }
+        Ok(r)
+    }
+}
+
+/// Decode the format produced by `Vec::<u8>::from(Registers)`: an arch byte
+/// followed by the raw bytes of the arch's user_regs_struct.
+/// DIFF NOTE: Not present in rr
+impl TryFrom<&[u8]> for Registers {
+    type Error = RegistersFromBytesError;
+
+    fn try_from(data: &[u8]) -> std::result::Result<Self, RegistersFromBytesError> {
+        let arch = match data.first() {
+            Some(&REGISTERS_ARCH_X86) => SupportedArch::X86,
+            Some(&REGISTERS_ARCH_X64) => SupportedArch::X64,
+            Some(&b) => return Err(RegistersFromBytesError::UnknownArch(b)),
+            None => {
+                return Err(RegistersFromBytesError::WrongLength {
+                    expected: 1,
+                    got: 0,
+                })
+            }
+        };
+        Registers::try_from((arch, &data[1..]))
+    }
+}
+
+/// DIFF NOTE: Not present in rr
+impl From<Registers> for Vec<u8> {
+    fn from(r: Registers) -> Vec<u8> {
+        let arch_byte = match r.arch() {
+            SupportedArch::X86 => REGISTERS_ARCH_X86,
+            SupportedArch::X64 => REGISTERS_ARCH_X64,
+        };
+        let mut v = vec![arch_byte];
+        v.extend_from_slice(r.get_ptrace_for_self_arch());
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::u8_slice;
+    use rand::random;
+    use std::collections::HashSet;
+
+    #[test]
+    fn xmm_register_count_test() {
+        assert_eq!(8, Registers::new(SupportedArch::X86).xmm_register_count());
+        assert_eq!(16, Registers::new(SupportedArch::X64).xmm_register_count());
+    }
+
+    #[test]
+    fn call_saved_register_mask_test() {
+        let mask = Registers::new(SupportedArch::X64).call_saved_register_mask();
+        assert_eq!(0, mask & (1 << DREG_RAX.as_usize()));
+        assert_ne!(0, mask & (1 << DREG_RBX.as_usize()));
+
+        let mask = Registers::new(SupportedArch::X86).call_saved_register_mask();
+        assert_eq!(0, mask & (1 << DREG_EAX.as_usize()));
+        assert_ne!(0, mask & (1 << DREG_EBX.as_usize()));
+    }
+
+    #[test]
+    fn read_xmm_placeholder_test() {
+        let r = Registers::new(SupportedArch::X64);
+        for i in 0..r.xmm_register_count() {
+            assert!(r.read_xmm_placeholder(i).is_none());
+        }
+    }
+
+    #[test]
+    fn syscall_args_test() {
+        for &arch in &[SupportedArch::X86, SupportedArch::X64] {
+            let mut r = Registers::new(arch);
+            r.set_syscall_args([1, 2, 3, 4, 5, 6]);
+            assert_eq!([1, 2, 3, 4, 5, 6], r.syscall_args());
+            assert_eq!(1, r.arg1());
+            assert_eq!(6, r.arg6());
+        }
+    }
+
+    #[test]
+    fn identical_test() {
+        let orig = Registers::new(SupportedArch::X64);
+        let mut r = orig.clone();
+        r.set_flags(X86_RESERVED_FLAG);
+        // eflags isn't compared by matches()
+        assert!(r.matches(&orig));
+        assert!(!r.identical(&orig));
+
+        // Read-modify-write that restores the original values
+        let flags = orig.flags();
+        r.set_flags(flags);
+        assert!(r.identical(&orig));
+        assert!(!r.identical(&Registers::new(SupportedArch::X86)));
+    }
+
+    #[test]
+    fn arg_index_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        assert_eq!(Ok(()), r.set_arg(6, 42));
+        assert_eq!(Ok(42), r.arg(6));
+        assert_eq!(42, r.arg_unchecked(6));
+        assert_eq!(Err(RegisterError::ArgIndexOutOfRange(0)), r.arg(0));
+        assert_eq!(Err(RegisterError::ArgIndexOutOfRange(7)), r.set_arg(7, 1));
+        assert!(!r.arg_is_valid_fd(7));
+        assert_eq!(
+            Err(RegisterError::UnknownRegister(DREG_64_XMM0)),
+            r.write_register(&[0u8; 16], DREG_64_XMM0)
+        );
+    }
+
+    #[test]
+    fn by_name_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        assert!(r.write_by_name("rax", 0x1_0000_0002));
+        assert_eq!(Some(0x1_0000_0002), r.read_by_name("rax"));
+        assert_eq!(Some(2), r.read_by_name("eax"));
+        assert!(r.write_by_name("eax", 3));
+        assert_eq!(3, r.ax());
+        assert!(!r.write_by_name("eax", 0x1_0000_0000));
+        assert_eq!(
+            Some((DREG_64_EFLAGS, 8)),
+            gdb_register_by_name(SupportedArch::X64, "rflags")
+        );
+        assert!(r.write_by_name("rflags", 0x246));
+        assert_eq!(Some(0x246), r.read_by_name("rflags"));
+        assert_eq!(0x246, r.flags());
+        assert!(!r.write_by_name("rflags", 0x1_0000_0246));
+        assert!(!r.write_by_name("nonsense", 0));
+        assert_eq!(None, r.read_by_name("nonsense"));
+
+        let mut r = Registers::new(SupportedArch::X86);
+        assert!(r.write_by_name("rcx", 5));
+        assert_eq!(5, r.cx());
+        assert_eq!(Some(5), r.read_by_name("ecx"));
+        assert!(!r.write_by_name("eax", 0x1_0000_0000));
+        assert_eq!(
+            Some(DREG_EIP),
+            <crate::arch::X86Arch as crate::arch::Architecture>::gdb_register_by_name("rip")
+        );
+    }
+
+    #[test]
+    fn eflags_fields_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        r.set_flags(X86_TF_FLAG | X86_DF_FLAG | (3 << 12) | 1);
+        let fields = r.eflags_fields();
+        assert!(fields.cf && fields.tf && fields.df);
+        assert!(!fields.zf && !fields.if_);
+        assert_eq!(3, fields.iopl);
+        assert!(r.singlestep_flag());
+        assert!(r.df_flag());
+
+        r.clear_singlestep_flag();
+        assert!(!r.singlestep_flag());
+        assert_eq!(X86_RESERVED_FLAG | X86_DF_FLAG | (3 << 12) | 1, r.flags());
+
+        r.set_eflags_fields(EflagsFields::default());
+        assert_eq!(X86_RESERVED_FLAG, r.flags());
+    }
+
+    #[test]
+    fn apply_diff_test() {
+        let mut r1 = Registers::new(SupportedArch::X64);
+        r1.set_arg1(1);
+        let mut r2 = r1.clone();
+        r2.set_arg1(2);
+        r2.set_ip(RemoteCodePtr::from_val(0x401000));
+        r2.x64_mut().fs = 0x63;
+
+        let diffs = Registers::diff(&r1, &r2);
+        assert_eq!(3, diffs.len());
+        r1.apply_diff(&diffs).unwrap();
+        assert!(Registers::diff(&r1, &r2).is_empty());
+        assert!(r1.identical(&r2));
+
+        let mut r3 = Registers::new(SupportedArch::X64);
+        let bogus = RegisterDiff {
+            name: "xyz",
+            recorded: 0,
+            live: 1,
+            mask: !0,
+        };
+        assert_eq!(
+            Err(RegisterError::UnknownRegisterName("xyz")),
+            r3.apply_diff(&[diffs[0], bogus])
+        );
+        assert!(r3.identical(&Registers::new(SupportedArch::X64)));
+    }
+
+    #[test]
+    fn canonical_form_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        r.set_flags(X86_TF_FLAG | (1 << 3) | (1 << 5) | (1 << 15) | (1 << 22) | (1 << 40));
+        r.x64_mut().cs = 0xdead_0000_0033;
+        r.x64_mut().fs_base = 0xdead_0000_0000;
+        let c = r.canonical_form();
+        assert_eq!(X86_RESERVED_FLAG | X86_TF_FLAG, c.flags());
+        assert_eq!(0x33, c.x64().cs);
+        assert_eq!(0xdead_0000_0000, c.x64().fs_base);
+        assert!(c.canonical_form().identical(&c));
+        assert!(!r.identical(&c));
+        assert!(Registers::compare_registers_arch(
+            "a",
+            "b",
+            &r,
+            &c,
+            MismatchBehavior::ExpectMismatches,
+            true
+        ));
+
+        let mut r = Registers::new(SupportedArch::X86);
+        r.set_flags(!0);
+        assert_eq!(
+            0x3f7fd7,
+            r.canonical_form().flags(),
+            "Only the defined bits survive"
+        );
+    }
+
+    #[test]
+    fn eq_hash_test() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |r: &Registers| {
+            let mut h = DefaultHasher::new();
+            r.hash(&mut h);
+            h.finish()
+        };
+
+        let mut r1 = Registers::new(SupportedArch::X64);
+        let mut r2 = r1.clone();
+        r1.set_flags(X86_RESERVED_FLAG);
+        r2.set_flags(X86_RESERVED_FLAG | (1 << 3));
+        assert!(r1 == r2);
+        assert_eq!(hash(&r1), hash(&r2));
+
+        r2.set_arg1(1);
+        assert!(r1 != r2);
+
+        // All zeroes in both cases, but different archs
+        let r3 = Registers::new(SupportedArch::X86);
+        let r4 = Registers::new(SupportedArch::X64);
+        assert!(r3 != r4);
+
+        let mut set = HashSet::new();
+        set.insert(r1.clone());
+        assert!(set.contains(&r1));
+        assert!(!set.contains(&r2));
+    }
+
+    #[test]
+    fn eq_symmetric_test() {
+        for &arch in &[SupportedArch::X86, SupportedArch::X64] {
+            let a = Registers::new(arch);
+            let mut b = a.clone();
+            b.set_original_syscallno(5);
+            assert_eq!(a == b, b == a);
+            assert!(a == b);
+
+            let mut c = a.clone();
+            c.set_original_syscallno(-1);
+            assert_eq!(b == c, c == b);
+        }
+    }
+
+    #[test]
+    fn diff_test() {
+        let mut r1 = Registers::new(SupportedArch::X64);
+        let mut r2 = r1.clone();
+        assert!(Registers::diff(&r1, &r2).is_empty());
+
+        r1.set_arg1(1);
+        r2.set_arg1(2);
+        // Only meaningful when both are syscall numbers
+        r1.set_original_syscallno(-1);
+        r2.set_original_syscallno(3);
+        let diffs = Registers::diff(&r1, &r2);
+        assert_eq!(1, diffs.len());
+        assert_eq!("rdi", diffs[0].name);
+        assert_eq!(1, diffs[0].recorded);
+        assert_eq!(2, diffs[0].live);
+        assert!(!r1.matches(&r2));
+
+        r1.set_original_syscallno(4);
+        let diffs = Registers::diff(&r1, &r2);
+        assert_eq!(2, diffs.len());
+        assert_eq!("orig_rax", diffs[0].name);
+    }
+
+    #[test]
+    fn iter_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        r.set_arg2(0xabcd);
+        let all: Vec<(&str, u64)> = r.iter().collect();
+        assert!(all.contains(&("rsi", 0xabcd)));
+        assert!(all.contains(&("rax", 0)));
+        assert_eq!(None, all.iter().find(|(name, _)| name.is_empty()));
+
+        r.for_each_mut(|mut entry| {
+            if entry.name() == "rdi" {
+                entry.set(0x1234);
+            } else if entry.name() == "rsi" {
+                assert_eq!(0xabcd, entry.value());
+                entry.set(entry.value() + 1);
+            }
+        });
+        assert_eq!(0x1234, r.arg1());
+        assert_eq!(0xabce, r.arg2());
+    }
+
+    #[test]
+    fn bytes_roundtrip_test() {
+        let mut r = Registers::new(SupportedArch::X86);
+        r.set_arg1(0x1234);
+        let bytes: Vec<u8> = r.clone().into();
+        let r2 = Registers::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(SupportedArch::X86, r2.arch());
+        assert_eq!(0x1234, r2.arg1());
+
+        assert_eq!(
+            Some(RegistersFromBytesError::WrongLength {
+                expected: bytes.len() - 1,
+                got: bytes.len() - 2
+            }),
+            Registers::try_from(&bytes[..bytes.len() - 1]).err()
+        );
+        let mut bad = bytes.clone();
+        bad[0] = 7;
+        assert_eq!(
+            Some(RegistersFromBytesError::UnknownArch(7)),
+            Registers::try_from(bad.as_slice()).err()
+        );
+
+        let r3 = Registers::try_from((SupportedArch::X86, &bytes[1..])).unwrap();
+        assert_eq!(0x1234, r3.arg1());
+    }
+
+    #[test]
+    fn serde_roundtrip_test() {
+        for &arch in &[SupportedArch::X86, SupportedArch::X64] {
+            let mut r = Registers::new(arch);
+            r.set_ip(RemoteCodePtr::from_val(0x0804_8000));
+            r.set_sp(RemotePtr::new(0xbfff_f000));
+            r.set_arg1(0x1234_5678);
+            r.set_syscallno(3);
+            let serialized = serde_json::to_string(&r).unwrap();
+            let r2: Registers = serde_json::from_str(&serialized).unwrap();
+            assert_eq!(arch, r2.arch());
+            assert_eq!(r.get_ptrace_for_self_arch(), r2.get_ptrace_for_self_arch());
+        }
+
+        let bad = r#"{"arch":"X64","user_regs":[1,2,3]}"#;
+        assert!(serde_json::from_str::<Registers>(bad).is_err());
+    }
+
+    #[test]
+    fn sub_register_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        r.set_syscall_result(0x1122_3344_5566_7788);
+        assert_eq!(0x88, r.al());
+        assert_eq!(0x77, r.ah());
+        assert_eq!(0x7788, r.ax16());
+        assert_eq!(0x5566_7788, r.eax());
+
+        r.set_al(0xaa);
+        assert_eq!(0x1122_3344_5566_77aa, r.ax());
+        r.set_ah(0xbb);
+        assert_eq!(0x1122_3344_5566_bbaa, r.ax());
+        r.set_ax16(0x1234);
+        assert_eq!(0x1122_3344_5566_1234, r.ax());
+        // Writing the 32-bit part zero-extends
+        r.set_eax(0xdead_beef);
+        assert_eq!(0xdead_beef, r.ax());
+
+        r.set_dx16(0xffff);
+        assert_eq!(0xff, r.dl());
+        assert_eq!(0xff, r.dh());
+        assert_eq!(0xffff, r.edx());
+
+        let mut r = Registers::new(SupportedArch::X86);
+        r.set_cx(0x8000_0001);
+        assert_eq!(0x01, r.cl());
+        assert_eq!(0x8000_0001, r.ecx());
+        r.set_ch(0x42);
+        assert_eq!(0x8000_4201, r.cx());
+    }
+
+    #[test]
+    fn compare_with_tolerance_test() {
+        let mut a = Registers::new(SupportedArch::X64);
+        let mut b = Registers::new(SupportedArch::X64);
+        // e.g. the high half of a TSC read by rdtsc
+        a.set_arg3(5000);
+        b.set_arg3(5100);
+        let mut tolerance = HashMap::new();
+        assert!(!Registers::compare_with_tolerance(&a, &b, &tolerance).matches());
+
+        tolerance.insert(DREG_RDX, 1000);
+        assert!(Registers::compare_with_tolerance(&a, &b, &tolerance).matches());
+
+        b.set_arg1(1);
+        let report = Registers::compare_with_tolerance(&a, &b, &tolerance);
+        assert_eq!(
+            vec![RegisterMismatch {
+                regno: DREG_RDI,
+                name: "rdi",
+                value1: 0,
+                value2: 1
+            }],
+            report.mismatches
+        );
+    }
+
+    #[test]
+    fn write_register_for_gdb_stub_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        r.set_ip(RemoteCodePtr::from_val(0x0040_1a2b));
+        let mut out: Vec<u8> = Vec::new();
+        r.write_register_for_gdb_stub(&mut out, DREG_RIP).unwrap();
+        assert_eq!("2b1a400000000000", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn write_register_file_json_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        r.set_ip(RemoteCodePtr::from_val(0x401a2b));
+        let mut out: Vec<u8> = Vec::new();
+        r.write_register_file_json(&mut out).unwrap();
+        assert_eq!(Some(&b'\n'), out.last());
+
+        let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!("X64", v["arch"]);
+        assert_eq!(0x401a2b, v["rip"]);
+        assert_eq!(0, v["rax"]);
+        assert_eq!(r.iter().count() + 1, v.as_object().unwrap().len());
+    }
+
+    #[test]
+    fn get_all_set_all_test() {
+        for &arch in &[SupportedArch::X86, SupportedArch::X64] {
+            let regnos: Vec<GdbRegister> = Registers::new(arch)
+                .get_all()
+                .into_iter()
+                .map(|(regno, _)| regno)
+                .collect();
+            assert!(!regnos.is_empty());
+
+            for _ in 0..100 {
+                let values: Vec<(GdbRegister, u64)> =
+                    regnos.iter().map(|&regno| (regno, random())).collect();
+
+                let mut once = Registers::new(arch);
+                once.set_all(&values);
+                let mut twice = Registers::new(arch);
+                twice.set_all(&values);
+                twice.set_all(&values);
+                assert!(once.identical(&twice));
+
+                // Restoring a snapshot reproduces the registers exactly
+                let mut restored = Registers::new(arch);
+                restored.set_all(&once.get_all());
+                assert!(restored.identical(&once));
+                assert_eq!(once.get_all(), restored.get_all());
+            }
+        }
+
+        // Registers of the other arch are ignored
+        let mut r = Registers::new(SupportedArch::X86);
+        r.set_all(&[(DREG_FS_BASE, 1)]);
+        assert!(r.identical(&Registers::new(SupportedArch::X86)));
+    }
+
+    #[test]
+    fn registers_for_gdb_stub_test() {
+        for &(arch, nbytes) in &[(SupportedArch::X86, 16 * 4), (SupportedArch::X64, 164)] {
+            let mut r = Registers::new(arch);
+            r.set_ip(RemoteCodePtr::from_val(0x0040_1a2b));
+            r.set_arg1(0x1234);
+            let mut out: Vec<u8> = Vec::new();
+            r.write_registers_for_gdb_stub(&mut out).unwrap();
+            assert_eq!(2 * nbytes, out.len());
+
+            let mut r2 = Registers::new(arch);
+            assert!(r2.read_registers_from_gdb_stub(&out));
+            assert!(r2.identical(&r));
+        }
+
+        let mut r = Registers::new(SupportedArch::X64);
+        // rax is left alone, rbx is set
+        assert!(r.read_registers_from_gdb_stub(b"xxxxxxxxxxxxxxxx0100000000000000"));
+        assert_eq!(Some(0), r.read_by_name("rax"));
+        assert_eq!(Some(1), r.read_by_name("rbx"));
+        assert!(!r.read_registers_from_gdb_stub(b"zz00000000000000"));
+        assert!(!r.read_registers_from_gdb_stub(b"0100"));
+    }
+
+    #[test]
+    fn flags_register_test() {
+        let r = Registers::new(SupportedArch::X86);
+        assert_eq!("eflags", r.flags_register_name());
+        assert_eq!(4, r.flags_register_size());
+        let r = Registers::new(SupportedArch::X64);
+        assert_eq!("rflags", r.flags_register_name());
+        assert_eq!(8, r.flags_register_size());
+    }
+
+    #[test]
+    fn decode_syscall_at_ip_test() {
+        let r = Registers::new(SupportedArch::X64);
+        assert_eq!(
+            Some(SyscallInstructionType::Syscall),
+            r.decode_syscall_at_ip(&[0x0f, 0x05])
+        );
+        assert_eq!(
+            Some(SyscallInstructionType::Sysenter),
+            r.decode_syscall_at_ip(&[0x0f, 0x34, 0x90])
+        );
+        assert_eq!(
+            Some(SyscallInstructionType::Int80),
+            r.decode_syscall_at_ip(&[0xcd, 0x80])
+        );
+        assert_eq!(None, r.decode_syscall_at_ip(&[0xcd, 0x03]));
+        assert_eq!(None, r.decode_syscall_at_ip(&[0x0f]));
+    }
+
+    #[test]
+    fn breakpoint_instruction_test() {
+        for &arch in &[SupportedArch::X86, SupportedArch::X64] {
+            let r = Registers::new(arch);
+            assert_eq!(&[0xcc], r.breakpoint_instruction_bytes());
+            assert_eq!(1, r.breakpoint_instruction_length());
+            assert_eq!(
+                r.breakpoint_instruction_bytes().len(),
+                r.breakpoint_instruction_length()
+            );
+        }
+    }
+
+    #[test]
+    fn decode_far_branch_target_test() {
+        let r = Registers::new(SupportedArch::X86);
+        // ljmp $0x23,$0x8048000
+        let ljmp = [0xea, 0x00, 0x80, 0x04, 0x08, 0x23, 0x00];
+        assert_eq!(Some((0x23, 0x0804_8000)), r.decode_far_branch_target(&ljmp));
+        // lcall $0x33,$0x12345678
+        let lcall = [0x9a, 0x78, 0x56, 0x34, 0x12, 0x33, 0x00];
+        assert_eq!(
+            Some((0x33, 0x1234_5678)),
+            r.decode_far_branch_target(&lcall)
+        );
+        // ljmp *(%eax)
+        assert_eq!(None, r.decode_far_branch_target(&[0xff, 0x28]));
+        // Truncated
+        assert_eq!(None, r.decode_far_branch_target(&ljmp[0..5]));
+
+        let r = Registers::new(SupportedArch::X64);
+        assert_eq!(None, r.decode_far_branch_target(&ljmp));
+        assert_eq!(None, r.decode_far_branch_target(&lcall));
+    }
+
+    #[test]
+    fn arg_is_valid_fd_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        r.set_arg1(-1isize as usize);
+        assert!(!r.arg_is_valid_fd(1));
+        r.set_arg1(0);
+        assert!(r.arg_is_valid_fd(1));
+        r.set_arg1(3);
+        assert!(r.arg_is_valid_fd(1));
+        r.set_arg1(i32::MAX as usize);
+        assert!(!r.arg_is_valid_fd(1));
+    }
+
+    #[test]
+    fn syscall_result_as_ptr_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        r.set_syscall_result(0x7f12_3456_7000);
+        assert_eq!(
+            Some(0x7f12_3456_7000),
+            r.syscall_result_as_ptr::<u8>().map(|p| p.as_usize())
+        );
+        r.set_syscall_result_signed(-(libc::ENOMEM as isize));
+        assert!(r.syscall_result_as_ptr::<u8>().is_none());
+    }
+
+    #[test]
+    fn rewind_advance_ip_test() {
+        let mut r = Registers::new(SupportedArch::X86);
+        r.set_ip(RemoteCodePtr::from_val(0x1));
+        r.rewind_ip_by(2);
+        assert_eq!(0xffff_ffff, r.ip().as_usize());
+        r.advance_ip_by(2);
+        assert_eq!(0x1, r.ip().as_usize());
+
+        let mut r = Registers::new(SupportedArch::X64);
+        r.set_ip(RemoteCodePtr::from_val(0xffff_ffff));
+        r.advance_ip_by(2);
+        assert_eq!(0x1_0000_0001, r.ip().as_usize());
+        r.set_ip(RemoteCodePtr::from_val(0x1));
+        r.rewind_ip_by(2);
+        assert_eq!(usize::MAX, r.ip().as_usize());
+    }
+
+    #[test]
+    fn set_from_ucontext_test() {
+        let mut sc = x64::sigcontext::default();
+        sc.ip = 0x4005d0;
+        sc.sp = 0x7ffc_1234_5678;
+        let mut uctx = vec![0u8; UCONTEXT_MCONTEXT_OFFSET_X64];
+        uctx.extend_from_slice(u8_slice(&sc));
+        let r = Registers::set_from_ucontext(SupportedArch::X64, &uctx).unwrap();
+        assert_eq!(0x4005d0, r.ip().as_usize());
+        assert_eq!(0x7ffc_1234_5678, r.sp().as_usize());
+
+        let mut sc = x86::sigcontext::default();
+        sc.ip = 0x8048000;
+        sc.sp = 0xbfff_0000;
+        let mut uctx = vec![0u8; UCONTEXT_MCONTEXT_OFFSET_X86];
+        uctx.extend_from_slice(u8_slice(&sc));
+        let r = Registers::set_from_ucontext(SupportedArch::X86, &uctx).unwrap();
+        assert_eq!(0x8048000, r.ip().as_usize());
+        assert_eq!(0xbfff_0000, r.sp().as_usize());
+
+        assert_eq!(
+            Some(UcontextError::TooShort {
+                needed: UCONTEXT_MCONTEXT_OFFSET_X86 + size_of::<x86::sigcontext>(),
+                got: 4
+            }),
+            Registers::set_from_ucontext(SupportedArch::X86, &[0u8; 4]).err()
+        );
+    }
+
+    #[test]
+    fn nt_prstatus_round_trip_test() {
+        for &arch in &[SupportedArch::X86, SupportedArch::X64] {
+            let mut r = Registers::new(arch);
+            r.set_ip(RemoteCodePtr::from_val(0x8048123));
+            r.set_sp(RemotePtr::new(0xbfff_1230));
+            r.set_arg1(1);
+            r.set_arg2(2);
+            r.set_arg3(3);
+            r.set_syscall_result(0x42);
+            r.set_flags(0x246);
+
+            let note = r.encode_as_nt_prstatus(1234, libc::SIGSEGV);
+            assert_eq!(prstatus_layout(arch).size, note.len());
+            let decoded = Registers::from_core_dump_note(arch, &note).unwrap();
+            assert_eq!(
+                r.get_ptrace_for_self_arch(),
+                decoded.get_ptrace_for_self_arch()
+            );
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn read_registers_matching_test() {
+        let mut r = Registers::new(SupportedArch::X64);
+        r.set_arg1(0x1234);
+        let matching = r.read_registers_matching("r.*").unwrap();
+        let names: Vec<&str> = matching.iter().map(|(name, _)| name.as_str()).collect();
+        for name in &[
+            "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11",
+            "r12", "r13", "r14", "r15", "rip",
+        ] {
+            assert!(names.contains(name), "{} not matched", name);
+        }
+        assert!(!names.contains(&"orig_rax"));
+        assert!(!names.contains(&"eflags"));
+        assert!(matching.contains(&("rdi".to_owned(), 0x1234)));
+
+        assert!(r.read_registers_matching("(").is_err());
+    }
+
+    #[test]
+    fn arg_count_for_syscall_test() {
+        assert_eq!(
+            Some(3),
+            Registers::arg_count_for_syscall(SupportedArch::X64, x64::READ as isize)
+        );
+        assert_eq!(
+            Some(0),
+            Registers::arg_count_for_syscall(SupportedArch::X64, x64::GETPID as isize)
+        );
+        assert_eq!(
+            Some(6),
+            Registers::arg_count_for_syscall(SupportedArch::X86, x86::MMAP2 as isize)
+        );
+        assert_eq!(
+            None,
+            Registers::arg_count_for_syscall(SupportedArch::X64, 100_000)
+        );
+        assert_eq!(
+            None,
+            Registers::arg_count_for_syscall(SupportedArch::X64, -1)
+        );
+    }
+}
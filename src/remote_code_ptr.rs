@@ -55,6 +55,23 @@ impl RemoteCodePtr {
         self + 1 as usize
     }
 
+    /// DIFF NOTE: Not present in rr. Move by `delta` bytes in either direction.
+    pub fn offset(self, delta: isize) -> RemoteCodePtr {
+        if delta < 0 {
+            self - delta.abs() as usize
+        } else {
+            self + delta as usize
+        }
+    }
+
+    /// DIFF NOTE: Not present in rr. Round up to the next multiple of `align` bytes. `align`
+    /// must be a power of 2.
+    pub fn align_to(self, align: usize) -> RemoteCodePtr {
+        debug_assert!(align.is_power_of_two());
+        // Will automatically deal with overflow in debug mode.
+        Self::from_val((self.ptr + align - 1) & !(align - 1))
+    }
+
     pub fn to_data_ptr<T>(self) -> RemotePtr<T> {
         RemotePtr::<T>::new(self.as_usize())
     }
@@ -118,4 +135,14 @@ mod tests {
         let a = RemoteCodePtr::null();
         assert_eq!(0, a.as_usize());
     }
+
+    #[test]
+    fn arithmetic_test() {
+        let a = RemoteCodePtr::from_val(0x401003);
+        assert_eq!(0x401005, a.offset(2).as_usize());
+        assert_eq!(0x401001, a.offset(-2).as_usize());
+        assert_eq!(-2, a.offset(-2) - a);
+        assert_eq!(0x401010, a.align_to(16).as_usize());
+        assert!(RemoteCodePtr::null().is_null());
+    }
 }
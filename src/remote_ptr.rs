@@ -2,6 +2,7 @@ use crate::remote_code_ptr::RemoteCodePtr;
 use std::{
     cmp::Ordering,
     convert::TryInto,
+    error::Error,
     fmt::{Display, Formatter, Result},
     marker::PhantomData,
     ops::{Add, AddAssign, Sub, SubAssign},
@@ -78,10 +79,26 @@ impl<T> RemotePtr<T> {
         std::mem::size_of::<T>()
     }
 
+    /// Does not check alignment. See `try_cast()` for a checked version.
     pub fn cast<U>(r: RemotePtr<U>) -> RemotePtr<T> {
         RemotePtr::<T>::new(r.ptr)
     }
 
+    /// DIFF NOTE: Not present in rr. Like `RemotePtr::<U>::cast(self)` but fails if the address
+    /// is not suitably aligned for a `U`.
+    pub fn try_cast<U>(self) -> std::result::Result<RemotePtr<U>, AlignmentError> {
+        let required_align = std::mem::align_of::<U>();
+        if self.ptr % required_align == 0 {
+            Ok(RemotePtr::<U>::cast(self))
+        } else {
+            Err(AlignmentError {
+                addr: self.ptr,
+                required_align,
+                got_align: 1 << self.ptr.trailing_zeros(),
+            })
+        }
+    }
+
     pub fn to_code_ptr(self) -> RemoteCodePtr {
         RemoteCodePtr::from_val(self.ptr)
     }
@@ -89,8 +106,55 @@ impl<T> RemotePtr<T> {
     pub fn as_rptr_u8(self) -> RemotePtr<u8> {
         RemotePtr::<u8>::new(self.ptr)
     }
+
+    /// DIFF NOTE: Not present in rr. Move by `count` elements of `T` in either direction.
+    /// Same as `self + count`.
+    pub fn offset(self, count: isize) -> RemotePtr<T> {
+        self + count
+    }
+
+    /// DIFF NOTE: Not present in rr. The signed distance from `origin` to `self` in elements of
+    /// `T`. Unlike `self - origin`, `self` may be below `origin`.
+    pub fn offset_from(self, origin: RemotePtr<T>) -> isize {
+        let delta = if self.ptr >= origin.ptr {
+            // Will automatically deal with overflow in debug mode.
+            (self.ptr - origin.ptr) as isize
+        } else {
+            -((origin.ptr - self.ptr) as isize)
+        };
+        delta / std::mem::size_of::<T>() as isize
+    }
+
+    /// DIFF NOTE: Not present in rr. Round up to the next multiple of `align` bytes. `align`
+    /// must be a power of 2.
+    pub fn align_to(self, align: usize) -> RemotePtr<T> {
+        debug_assert!(align.is_power_of_two());
+        // Will automatically deal with overflow in debug mode.
+        RemotePtr::new((self.ptr + align - 1) & !(align - 1))
+    }
 }
 
+/// See `RemotePtr::try_cast()`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AlignmentError {
+    pub addr: usize,
+    pub required_align: usize,
+    /// The largest power of 2 that `addr` is a multiple of
+    pub got_align: usize,
+}
+
+impl Display for AlignmentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "{:#x} is {} byte aligned but {} byte alignment is required",
+            self.addr, self.got_align, self.required_align
+        )
+    }
+}
+
+impl Error for AlignmentError {}
+
 impl<T> Display for RemotePtr<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(f, "{:#x}", self.ptr)
@@ -269,6 +333,21 @@ mod tests {
         assert_eq!(8, a.referent_size());
     }
 
+    #[test]
+    fn try_cast_test() {
+        let a = RemotePtr::<Void>::new(0x1008);
+        assert_eq!(0x1008, a.try_cast::<u64>().unwrap().as_usize());
+        assert_eq!(
+            Err(AlignmentError {
+                addr: 0x1006,
+                required_align: 4,
+                got_align: 2
+            }),
+            RemotePtr::<Void>::new(0x1006).try_cast::<u32>()
+        );
+        assert!(RemotePtr::<Void>::null().try_cast::<u64>().is_ok());
+    }
+
     #[test]
     fn comparison_test() {
         struct S(u64, u64);
@@ -281,4 +360,29 @@ mod tests {
         assert!(d > c);
         assert!(c != d);
     }
+
+    #[test]
+    fn offset_test() {
+        let a = RemotePtr::<u64>::new(0x1000);
+        assert_eq!(0x1010, a.offset(2).as_usize());
+        assert_eq!(0xff8, a.offset(-1).as_usize());
+        assert_eq!(2, a.offset(2).offset_from(a));
+        assert_eq!(-2, a.offset_from(a.offset(2)));
+    }
+
+    #[test]
+    fn align_to_test() {
+        let a = RemotePtr::<u8>::new(0x1000);
+        assert_eq!(0x1000, a.align_to(0x1000).as_usize());
+        assert_eq!(0x2000, (a + 1usize).align_to(0x1000).as_usize());
+        assert_eq!(0x18, RemotePtr::<u64>::new(0x11).align_to(8).as_usize());
+        assert!(RemotePtr::<u8>::null().align_to(16).is_null());
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn add_overflow_test() {
+        let _ = RemotePtr::<u64>::new(usize::MAX - 4) + 1usize;
+    }
 }
@@ -54,7 +54,7 @@ use crate::{
     },
     kernel_metadata::{is_sigreturn, shm_flags_to_mmap_prot, syscall_name},
     kernel_supplement::{ARCH_GET_CPUID, ARCH_SET_CPUID},
-    log::LogLevel::LogDebug,
+    log::LogLevel::{LogDebug, LogWarn},
     preload_interface::{syscallbuf_hdr, SYS_rdcall_reload_auxv},
     registers::{with_converted_registers, Registers},
     remote_ptr::{RemotePtr, Void},
@@ -149,6 +149,7 @@ use std::{
     ffi::{CString, OsStr, OsString},
     mem::size_of,
     os::unix::ffi::{OsStrExt, OsStringExt},
+    rc::Rc,
 };
 use trace_stream::{MappedDataSource, TraceRemoteFd};
 
@@ -527,6 +528,13 @@ fn prepare_clone<Arch: Architecture>(t: &mut ReplayTask) {
         new_task
             .vm_shr_ptr()
             .remove_all_watchpoints(new_task, Some(t));
+        // Session-wide watchpoints apply to every address space though
+        if !Rc::ptr_eq(&new_task.vm_shr_ptr(), &t.vm_shr_ptr()) {
+            let session = new_task.session();
+            if !session.watchpoint_manager().install_watchpoints(new_task) {
+                log!(LogWarn, "Could not install session watchpoints in new task");
+            }
+        }
 
         let mut remote = AutoRemoteSyscalls::new(new_task);
         for (&k, m) in &t.vm().maps() {
@@ -1,28 +1,66 @@
 use crate::{
     auto_remote_syscalls::AutoRemoteSyscalls,
+    bindings::{
+        ptrace::PTRACE_SETSIGINFO,
+        signal::{siginfo_t, SEGV_MAPERR},
+    },
     emu_fs::EmuFs,
-    kernel_abi::SupportedArch,
+    kernel_abi::{
+        has_mmap2_syscall,
+        syscall_number_for_mmap,
+        syscall_number_for_mmap2,
+        SupportedArch,
+    },
+    remote_code_ptr::RemoteCodePtr,
     remote_ptr::{RemotePtr, Void},
     session::{
-        address_space::{address_space::AddressSpaceSharedPtr, MappingFlags},
+        address_space::{
+            address_space::{AddressSpace, AddressSpaceSharedPtr},
+            MappingFlags,
+        },
         diversion_session::DiversionSession,
         record_session::RecordSession,
         replay_session::ReplaySession,
-        session_inner::{AddressSpaceMap, SessionInner, TaskMap, ThreadGroupMap},
+        session_inner::{
+            AddressSpaceMap,
+            SessionInner,
+            SignalHandler,
+            SyscallObserver,
+            TaskMap,
+            TaskMapRef,
+            ThreadGroupMap,
+        },
         task::{
             task_common,
-            task_inner::{CloneFlags, WriteFlags},
+            task_inner::{
+                CloneFlags,
+                PtraceData,
+                ResumeRequest,
+                TicksRequest,
+                WaitRequest,
+                WriteFlags,
+            },
             Task,
             TaskSharedPtr,
         },
     },
+    sig,
     taskish_uid::{AddressSpaceUid, TaskUid, ThreadGroupUid},
     thread_group::{ThreadGroup, ThreadGroupSharedPtr},
-    trace::trace_stream::TraceStream,
+    trace::{trace_frame::FrameTime, trace_stream::TraceStream},
+    util::{ceil_page_size, is_zombie_process, page_size, u8_slice},
+};
+use libc::{c_void, kill, pid_t, EINVAL};
+use nix::{
+    errno::errno,
+    sys::mman::{MapFlags, ProtFlags},
 };
-use libc::pid_t;
 use std::{
     cell::{Ref, RefMut},
+    collections::HashMap,
+    ffi::OsStr,
+    fs::read_to_string,
+    io,
     ops::DerefMut,
     rc::{Rc, Weak},
 };
@@ -37,6 +75,9 @@ pub mod session_common;
 pub mod session_inner;
 pub mod task;
 
+#[cfg(test)]
+pub mod test_util;
+
 /// Note that this is NOT Rc<RefCell<Box<dyn Session>>>
 /// Session will be shared.
 /// Individual parts of the session can be wrapped in RefCell<> as required
@@ -109,6 +150,24 @@ pub trait Session: DerefMut<Target = SessionInner> {
         None
     }
 
+    /// The time of the trace stream of this session, if it has one.
+    /// DIFF NOTE: Not present in rr
+    fn current_frame_time(&self) -> Option<FrameTime> {
+        self.trace_stream().map(|trace| trace.time())
+    }
+
+    /// True if this session has a trace stream and it is before `time`.
+    /// DIFF NOTE: Not present in rr
+    fn is_before_frame(&self, time: FrameTime) -> bool {
+        self.current_frame_time().map_or(false, |now| now < time)
+    }
+
+    /// True if this session has a trace stream and it is after `time`.
+    /// DIFF NOTE: Not present in rr
+    fn is_after_frame(&self, time: FrameTime) -> bool {
+        self.current_frame_time().map_or(false, |now| now > time)
+    }
+
     fn cpu_binding(&self, trace: &TraceStream) -> Option<u32> {
         trace.bound_to_cpu()
     }
@@ -219,6 +278,34 @@ pub trait Session: DerefMut<Target = SessionInner> {
             .map_or(None, |shr_ptr| Some(shr_ptr.clone()))
     }
 
+    /// Return the task whose live OS tid is `native_tid`, or None if no such
+    /// task exists. During replay this generally differs from the recorded tid.
+    /// DIFF NOTE: Not present in rr
+    fn task_for_native_tid(&self, native_tid: pid_t) -> Option<TaskSharedPtr> {
+        self.tasks()
+            .values()
+            .find(|t| t.borrow().tid == native_tid)
+            .cloned()
+    }
+
+    /// Return all tasks whose thread name i.e. comm (see `TaskInner::thread_name()`) is
+    /// exactly `name`. Every task is mutably borrowed in turn, so none may be borrowed by
+    /// the caller.
+    /// DIFF NOTE: Not present in rr
+    fn find_task_by_comm(&self, name: &str) -> Vec<TaskSharedPtr> {
+        self.find_task_by_comm_matching(&|comm| comm == name)
+    }
+
+    /// Like `find_task_by_comm()` but with a custom match e.g. a prefix match.
+    /// DIFF NOTE: Not present in rr
+    fn find_task_by_comm_matching(&self, matches: &dyn Fn(&OsStr) -> bool) -> Vec<TaskSharedPtr> {
+        self.tasks()
+            .values()
+            .filter(|t| t.borrow_mut().thread_name().map_or(false, |n| matches(&n)))
+            .cloned()
+            .collect()
+    }
+
     /// NOTE: Method is simply called Session::find task() in rr
     fn find_task_from_task_uid(&self, tuid: TaskUid) -> Option<TaskSharedPtr> {
         self.find_task_from_rec_tid(tuid.tid())
@@ -234,6 +321,16 @@ pub trait Session: DerefMut<Target = SessionInner> {
             .map(|t| t.upgrade().unwrap())
     }
 
+    /// Return a snapshot of the tasks in the thread group whose unique ID is `tguid`. Empty
+    /// if no such thread group exists.
+    /// DIFF NOTE: Not present in rr
+    fn tasks_in_thread_group(&self, tguid: ThreadGroupUid) -> Vec<TaskSharedPtr> {
+        match self.find_thread_group_from_tguid(tguid) {
+            Some(tg) => tg.borrow().task_set().iter().collect(),
+            None => Vec::new(),
+        }
+    }
+
     /// Find the thread group for a specific pid
     /// NOTE: Method is simply called Session::find thread_group() in rr
     fn find_thread_group_from_pid(&self, pid: pid_t) -> Option<ThreadGroupSharedPtr> {
@@ -293,6 +390,18 @@ pub trait Session: DerefMut<Target = SessionInner> {
         self.as_session_inner().task_map.borrow()
     }
 
+    /// See `TaskMapRef`
+    /// DIFF NOTE: Not present in rr
+    fn tasks_iter(&self) -> TaskMapRef<'_> {
+        TaskMapRef(self.tasks())
+    }
+
+    /// The tids of the tasks in this session, in ascending order.
+    /// DIFF NOTE: Not present in rr
+    fn tasks_tids(&self) -> Vec<pid_t> {
+        self.tasks().keys().copied().collect()
+    }
+
     fn tasks_mut(&self) -> RefMut<'_, TaskMap> {
         self.finish_initializing();
         self.as_session_inner().task_map.borrow_mut()
@@ -314,6 +423,257 @@ pub trait Session: DerefMut<Target = SessionInner> {
         self.as_session_inner().vm_map.borrow_mut()
     }
 
+    /// Panic if any OS task killed by `kill_all_tasks()` still exists.
+    /// Zombies are not considered leaks: they no longer run and just wait to
+    /// be reaped.
+    /// Since tids can be reused this is only reliable shortly after the kill.
+    /// DIFF NOTE: Not present in rr
+    fn assert_no_leaking_tasks(&self) {
+        let leaked: Vec<pid_t> = self
+            .killed_tids
+            .borrow()
+            .iter()
+            .copied()
+            .filter(|&tid| unsafe { kill(tid, 0) } == 0 && !is_zombie_process(tid))
+            .collect();
+        assert!(
+            leaked.is_empty(),
+            "Tasks {:?} still exist after kill_all_tasks()",
+            leaked
+        );
+    }
+
+    /// Replay until the frame at `target` is the next one to be replayed.
+    /// Only replay sessions can do this, see `ReplaySession::fast_forward_to()`.
+    /// DIFF NOTE: Not present in rr
+    fn fast_forward_to(&self, target: FrameTime) -> io::Result<()> {
+        match self.as_replay() {
+            Some(replay_session) => replay_session.fast_forward_to(target),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only replay sessions can fast forward to a frame",
+            )),
+        }
+    }
+
+    /// All tasks whose current instruction pointer is `ip`.
+    /// DIFF NOTE: Not present in rr
+    fn find_tasks_at_ip(&self, ip: RemoteCodePtr) -> Vec<TaskSharedPtr> {
+        self.tasks()
+            .values()
+            .filter(|t| t.borrow().regs_ref().ip() == ip)
+            .cloned()
+            .collect()
+    }
+
+    /// Remove entries from the thread group map whose ThreadGroup no longer
+    /// exists. Returns the number of entries removed.
+    /// DIFF NOTE: Not present in rr
+    fn prune_dead_thread_groups(&self) -> usize {
+        let mut tg_map = self.thread_group_map_mut();
+        let before = tg_map.len();
+        tg_map.retain(|_, tg| tg.upgrade().is_some());
+        before - tg_map.len()
+    }
+
+    /// Read the VmRSS (in kB) of every task in this session from
+    /// /proc/<tid>/status. The result is keyed by rec_tid and is also appended
+    /// to `vmrss_history()` along with the current frame time.
+    /// Tasks whose status could not be read are omitted.
+    /// DIFF NOTE: Not present in rr
+    fn capture_vmrss_snapshot(&self) -> HashMap<pid_t, u64> {
+        let mut snapshot = HashMap::new();
+        for (&rec_tid, t) in self.tasks().iter() {
+            let status = match read_to_string(format!("/proc/{}/status", t.borrow().tid)) {
+                Ok(status) => status,
+                Err(_) => continue,
+            };
+            let maybe_rss = status
+                .lines()
+                .find(|l| l.starts_with("VmRSS:"))
+                .and_then(|l| l.split_whitespace().nth(1))
+                .and_then(|kb| kb.parse::<u64>().ok());
+            if let Some(rss) = maybe_rss {
+                snapshot.insert(rec_tid, rss);
+            }
+        }
+
+        let time = self.current_frame_time().unwrap_or(0);
+        self.vmrss_history_
+            .borrow_mut()
+            .push((time, snapshot.clone()));
+        snapshot
+    }
+
+    /// Register `obs` to be invoked on every syscall entry and exit of every
+    /// task in this session, during both recording and replay.
+    /// DIFF NOTE: Not present in rr
+    fn observe_all_syscalls(&self, obs: Box<dyn SyscallObserver>) {
+        self.syscall_observers.borrow_mut().push(obs);
+    }
+
+    /// Register `handler` to be run whenever a task in this session receives
+    /// a signal during recording. See `SignalHandler`.
+    /// DIFF NOTE: Not present in rr
+    fn register_signal_handler(&self, handler: Box<dyn SignalHandler>) {
+        self.signal_handlers.borrow_mut().push(handler);
+    }
+
+    /// Make `t` observe a page fault at `faulting_addr`: the tracee receives a
+    /// SIGSEGV (SEGV_MAPERR) whose `si_addr` is `faulting_addr`.
+    ///
+    /// PTRACE_SETSIGINFO only works when the tracee is in a signal-delivery-stop.
+    /// The caller must supply `t` in one. Otherwise this fails with `InvalidInput`
+    /// rather than running tracee code (unrecorded) to get to such a stop.
+    ///
+    /// The SIGSEGV replaces the signal `t` is stopped for: that signal is
+    /// dropped and never reaches the tracee. Callers that still want it
+    /// delivered must send it again.
+    /// DIFF NOTE: Not present in rr
+    fn inject_page_fault(
+        &self,
+        t: &mut dyn Task,
+        faulting_addr: RemotePtr<Void>,
+    ) -> io::Result<()> {
+        self.assert_fully_initialized();
+        if !t.maybe_stop_sig().is_sig() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Task {} is not in a signal-delivery-stop", t.tid),
+            ));
+        }
+
+        let si = page_fault_siginfo(faulting_addr);
+        if t.fallible_ptrace(
+            PTRACE_SETSIGINFO,
+            RemotePtr::null(),
+            &mut PtraceData::ReadFrom(u8_slice(&si)),
+        ) < 0
+        {
+            return Err(io::Error::from_raw_os_error(errno()));
+        }
+
+        // The kernel uses the siginfo we just set because its si_signo matches
+        // the signal we resume with.
+        t.resume_execution(
+            ResumeRequest::ResumeSinglestep,
+            WaitRequest::ResumeWait,
+            TicksRequest::ResumeNoTicks,
+            Some(sig::SIGSEGV),
+        );
+        Ok(())
+    }
+
+    /// Create a mapping in `t`'s address space by executing a real mmap syscall
+    /// in the tracee and then record it in `t`'s AddressSpace.
+    /// Returns the address of the new mapping. `offset` is in bytes and must be
+    /// page aligned.
+    /// DIFF NOTE: Not present in rr
+    fn inject_mmap(
+        &self,
+        t: &mut dyn Task,
+        addr: RemotePtr<Void>,
+        size: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: u64,
+    ) -> io::Result<RemotePtr<Void>> {
+        if offset % page_size() as u64 != 0 {
+            return Err(io::Error::from_raw_os_error(EINVAL));
+        }
+
+        let mut remote = AutoRemoteSyscalls::new(t);
+        let arch = remote.arch();
+        let ret = if has_mmap2_syscall(arch) {
+            rd_syscall!(
+                remote,
+                syscall_number_for_mmap2(arch),
+                addr.as_usize(),
+                size,
+                prot,
+                flags,
+                fd,
+                offset / page_size() as u64
+            )
+        } else {
+            rd_syscall!(
+                remote,
+                syscall_number_for_mmap(arch),
+                addr.as_usize(),
+                size,
+                prot,
+                flags,
+                fd,
+                offset
+            )
+        };
+        if -4096 < ret && ret < 0 {
+            return Err(io::Error::from_raw_os_error(-ret as i32));
+        }
+
+        let map_addr = RemotePtr::<Void>::new(ret as usize);
+        // Let the kernel tell us what got mapped so that fsname/device/inode are right
+        // for both anonymous and file backed mappings.
+        let km = AddressSpace::read_kernel_mapping(remote.task(), map_addr);
+        remote.task().vm_shr_ptr().map(
+            remote.task_mut(),
+            map_addr,
+            ceil_page_size(size),
+            ProtFlags::from_bits_truncate(prot),
+            MapFlags::from_bits_truncate(flags),
+            offset,
+            km.fsname(),
+            km.device(),
+            km.inode(),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        Ok(map_addr)
+    }
+
+    /// Read `size` bytes at `addr` from `t` and write them, along with their
+    /// RawDataMetadata, to the RawData substream of the trace being recorded.
+    /// The record becomes part of the next frame written. Replay restores the
+    /// bytes once it has replayed that frame, see
+    /// `ReplaySession::replay_step_with_constraints()`.
+    /// Returns an error if this is not a recording or the read fails.
+    /// DIFF NOTE: Not present in rr
+    fn record_raw_bytes_for_addr(
+        &self,
+        t: &mut dyn Task,
+        addr: RemotePtr<Void>,
+        size: usize,
+    ) -> io::Result<()> {
+        let rec_session = self.as_record().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "Raw bytes can only be recorded while recording",
+            )
+        })?;
+
+        // Keep raw data records in order with any pending syscallbuf records
+        t.as_rec_mut_unwrap().maybe_flush_syscallbuf();
+        let mut buf = vec![0u8; size];
+        match t.read_bytes_fallible(addr, &mut buf) {
+            Ok(nread) if nread == size => (),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Could not read {} bytes at {}", size, addr),
+                ))
+            }
+        }
+
+        rec_session
+            .trace_writer_mut()
+            .write_raw(t.rec_tid, &buf, addr);
+        Ok(())
+    }
+
     /// Call `post_exec()` immediately after a tracee has successfully
     /// `execve()`'d.  After that, `done_initial_exec()` returns true.
     /// This is called while we're still in the execve syscall so it's not safe
@@ -346,3 +706,84 @@ fn on_create_task_common<S: Session>(sess: &S, t: TaskSharedPtr) {
     let rec_tid = t.borrow().rec_tid;
     sess.task_map.borrow_mut().insert(rec_tid, t);
 }
+
+/// The siginfo of a SIGSEGV for an access to unmapped memory at `faulting_addr`
+fn page_fault_siginfo(faulting_addr: RemotePtr<Void>) -> siginfo_t {
+    let mut si: siginfo_t = Default::default();
+    si.si_signo = sig::SIGSEGV.as_raw();
+    si.si_code = SEGV_MAPERR as i32;
+    si._sifields._sigfault.si_addr = faulting_addr.as_usize() as *mut c_void;
+    si
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bindings::ptrace::PTRACE_SETSIGMASK,
+        kernel_supplement::sig_set_t,
+        session::test_util::start_recording,
+        trace::test_util::TempTraceDir,
+        util::signal_bit,
+    };
+    use std::mem::size_of;
+
+    fn set_sigmask(t: &mut dyn Task, mask: sig_set_t) {
+        let ret = t.fallible_ptrace(
+            PTRACE_SETSIGMASK,
+            RemotePtr::from(size_of::<sig_set_t>()),
+            &mut PtraceData::ReadFrom(u8_slice(&mask)),
+        );
+        assert!(ret >= 0);
+    }
+
+    #[test]
+    fn inject_page_fault_test() {
+        let dir = TempTraceDir::new("inject-page-fault");
+        // The initial tracee stopping before its exec is all we need
+        let session = start_recording(&dir, &["/bin/true"]);
+        let task = session.tasks().values().next().unwrap().clone();
+        let mut t = task.borrow_mut();
+        let faulting_addr = RemotePtr::<Void>::new(0xdead_b000);
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            session
+                .inject_page_fault(t.as_mut(), faulting_addr)
+                .unwrap_err()
+                .kind()
+        );
+
+        assert_eq!(0, unsafe { kill(t.tid, sig::SIGUSR1.as_raw()) });
+        t.resume_execution(
+            ResumeRequest::ResumeCont,
+            WaitRequest::ResumeWait,
+            TicksRequest::ResumeNoTicks,
+            None,
+        );
+        assert_eq!(sig::SIGUSR1, t.maybe_stop_sig().unwrap_sig());
+
+        // While SIGSEGV is blocked the kernel queues the injected signal
+        // instead of delivering it. Once unblocked the tracee dequeues it and
+        // we see it in a signal-delivery-stop, with the siginfo the tracee
+        // would get.
+        set_sigmask(t.as_mut(), signal_bit(sig::SIGSEGV));
+        session
+            .inject_page_fault(t.as_mut(), faulting_addr)
+            .unwrap();
+        set_sigmask(t.as_mut(), 0);
+        t.resume_execution(
+            ResumeRequest::ResumeCont,
+            WaitRequest::ResumeWait,
+            TicksRequest::ResumeNoTicks,
+            None,
+        );
+        // The SIGUSR1 was dropped in favor of the SIGSEGV
+        assert_eq!(sig::SIGSEGV, t.maybe_stop_sig().unwrap_sig());
+        let si = t.get_siginfo();
+        assert_eq!(sig::SIGSEGV.as_raw(), si.si_signo);
+        assert_eq!(SEGV_MAPERR as i32, si.si_code);
+        assert_eq!(faulting_addr.as_usize(), unsafe {
+            si._sifields._sigfault.si_addr as usize
+        });
+    }
+}
@@ -58,6 +58,28 @@ pub enum WatchType {
     WatchReadWrite = 0x03,
 }
 
+/// The lengths that an x86 debug register can watch. The watched address
+/// must be aligned to the length.
+/// DIFF NOTE: Not present in rr
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WatchpointSize {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+impl WatchpointSize {
+    pub fn num_bytes(self) -> usize {
+        match self {
+            WatchpointSize::Byte => 1,
+            WatchpointSize::Word => 2,
+            WatchpointSize::Dword => 4,
+            WatchpointSize::Qword => 8,
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 #[repr(usize)]
 pub enum DebugStatus {
@@ -16,10 +16,40 @@ use nix::{
 use std::{
     ffi::OsStr,
     fs::File,
+    io,
     io::{BufRead, BufReader},
     os::unix::ffi::OsStrExt,
 };
 
+/// Like `KernelMapIterator` but reports errors instead of aborting.
+/// See `TaskInner::memory_maps()`
+/// DIFF NOTE: Not present in rr
+pub struct MemoryMapIterator {
+    buf_reader: BufReader<File>,
+}
+
+impl Iterator for MemoryMapIterator {
+    type Item = io::Result<KernelMapping>;
+
+    fn next(&mut self) -> Option<io::Result<KernelMapping>> {
+        let mut raw_line = Vec::new();
+        match self.buf_reader.read_until(b'\n', &mut raw_line) {
+            Ok(0) => None,
+            Ok(_) => Some(KernelMapIterator::try_parse_rawline(&raw_line)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl MemoryMapIterator {
+    pub fn new_from_tid(tid: pid_t) -> io::Result<MemoryMapIterator> {
+        let file = File::open(format!("/proc/{}/maps", tid))?;
+        Ok(MemoryMapIterator {
+            buf_reader: BufReader::new(file),
+        })
+    }
+}
+
 pub struct KernelMapIterator {
     tid: pid_t,
     buf_reader: BufReader<File>,
@@ -76,29 +106,53 @@ impl KernelMapIterator {
     }
 
     fn parse_rawline(raw_line: &[u8]) -> KernelMapping {
+        match Self::try_parse_rawline(raw_line) {
+            Ok(km) => km,
+            Err(e) => fatal!("{}", e),
+        }
+    }
+
+    /// Parse a line of /proc/{tid}/maps. Fails with `InvalidData` if the line
+    /// is malformed.
+    /// DIFF NOTE: Not present in rr. Extracted from parse_rawline()
+    fn try_parse_rawline(raw_line: &[u8]) -> io::Result<KernelMapping> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Could not parse maps line {:?}",
+                    String::from_utf8_lossy(raw_line)
+                ),
+            )
+        };
         let mut iter = raw_line.splitn(6, |c| *c == b' ');
-        let addr_range = String::from_utf8_lossy(iter.next().unwrap());
-        let perms_s = String::from_utf8_lossy(iter.next().unwrap());
-        let offset_s = String::from_utf8_lossy(iter.next().unwrap());
-        let device = String::from_utf8_lossy(iter.next().unwrap());
-        let inode_s = String::from_utf8_lossy(iter.next().unwrap());
+        let mut next_field = || iter.next().map(String::from_utf8_lossy).ok_or_else(invalid);
+        let addr_range = next_field()?;
+        let perms_s = next_field()?;
+        let offset_s = next_field()?;
+        let device = next_field()?;
+        let inode_s = next_field()?;
         // Strip leading ascii spaces and trailing newlines also
-        let filename_unescaped = iter.next().unwrap();
+        let filename_unescaped = iter.next().ok_or_else(invalid)?;
 
         let mut addr_iter = addr_range.split('-');
-        let addr_low_s = addr_iter.next().unwrap();
-        let addr_high_s = addr_iter.next().unwrap();
+        let addr_low_s = addr_iter.next().ok_or_else(invalid)?;
+        let addr_high_s = addr_iter.next().ok_or_else(invalid)?;
 
         let mut dev_iter = device.split(':');
-        let dev_major_s = dev_iter.next().unwrap();
-        let dev_minor_s = dev_iter.next().unwrap();
+        let dev_major_s = dev_iter.next().ok_or_else(invalid)?;
+        let dev_minor_s = dev_iter.next().ok_or_else(invalid)?;
 
-        let addr_low: RemotePtr<Void> = usize::from_str_radix(addr_low_s, 16).unwrap().into();
-        let addr_high: RemotePtr<Void> = usize::from_str_radix(addr_high_s, 16).unwrap().into();
-        let offset: u64 = u64::from_str_radix(&offset_s, 16).unwrap();
-        let dev_major: u32 = u32::from_str_radix(dev_major_s, 16).unwrap();
-        let dev_minor: u32 = u32::from_str_radix(dev_minor_s, 16).unwrap();
-        let inode: ino_t = inode_s.parse::<ino_t>().unwrap();
+        let addr_low: RemotePtr<Void> = usize::from_str_radix(addr_low_s, 16)
+            .map_err(|_| invalid())?
+            .into();
+        let addr_high: RemotePtr<Void> = usize::from_str_radix(addr_high_s, 16)
+            .map_err(|_| invalid())?
+            .into();
+        let offset: u64 = u64::from_str_radix(&offset_s, 16).map_err(|_| invalid())?;
+        let dev_major: u32 = u32::from_str_radix(dev_major_s, 16).map_err(|_| invalid())?;
+        let dev_minor: u32 = u32::from_str_radix(dev_minor_s, 16).map_err(|_| invalid())?;
+        let inode: ino_t = inode_s.parse::<ino_t>().map_err(|_| invalid())?;
 
         // Ignore leading spaces
         let mut start_index = 0;
@@ -152,7 +206,7 @@ impl KernelMapIterator {
 
         let prot: ProtFlags = Self::get_prot(&perms_s);
         let map_flags: MapFlags = Self::get_map_flags(&perms_s);
-        KernelMapping::new_with_opts(
+        Ok(KernelMapping::new_with_opts(
             addr_low,
             addr_high,
             OsStr::from_bytes(&filename),
@@ -161,7 +215,7 @@ impl KernelMapIterator {
             prot,
             map_flags,
             offset,
-        )
+        ))
     }
 
     fn get_prot(perms_s: &str) -> ProtFlags {
@@ -196,3 +250,48 @@ impl KernelMapIterator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_parse_rawline_test() {
+        let km = KernelMapIterator::try_parse_rawline(
+            b"00400000-0040b000 r-xp 00001000 08:02 173521      /usr/bin/dbus-daemon\n",
+        )
+        .unwrap();
+        assert_eq!(RemotePtr::<Void>::from(0x400000usize), km.start());
+        assert_eq!(RemotePtr::<Void>::from(0x40b000usize), km.end());
+        assert_eq!(ProtFlags::PROT_READ | ProtFlags::PROT_EXEC, km.prot());
+        assert_eq!(MapFlags::MAP_PRIVATE, km.flags());
+        assert_eq!(0x1000, km.file_offset_bytes());
+        assert_eq!(173521, km.inode());
+        assert_eq!(OsStr::new("/usr/bin/dbus-daemon"), km.fsname());
+
+        let anon =
+            KernelMapIterator::try_parse_rawline(b"7fff0000-7fff1000 rw-p 00000000 00:00 0 \n")
+                .unwrap();
+        assert!(anon.fsname().is_empty());
+
+        for bad in &[
+            &b"garbage\n"[..],
+            b"0040000g-0040b000 r-xp 00000000 08:02 1 x\n",
+        ] {
+            let err = KernelMapIterator::try_parse_rawline(bad).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[test]
+    fn memory_map_iterator_test() {
+        let maps: Vec<KernelMapping> = MemoryMapIterator::new_from_tid(getpid().as_raw())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert!(!maps.is_empty());
+        assert!(maps.windows(2).all(|w| w[0].end() <= w[1].start()));
+
+        assert!(MemoryMapIterator::new_from_tid(-1).is_err());
+    }
+}
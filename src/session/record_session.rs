@@ -2,7 +2,7 @@ use super::{
     address_space::{address_space::AddressSpace, Privileged},
     on_create_task_common,
     session_common::kill_all_tasks,
-    session_inner::PtraceSyscallSeccompOrdering,
+    session_inner::{PtraceSyscallSeccompOrdering, SignalDisposition},
     task::{
         record_task::{
             self,
@@ -147,9 +147,12 @@ use libc::{
 };
 use mem::size_of;
 use nix::{
+    errno::Errno,
     fcntl::{open, OFlag},
+    sched::{sched_getaffinity, sched_setaffinity, CpuSet},
     sys::stat::{stat, Mode, SFlag},
-    unistd::{access, read, AccessFlags},
+    unistd::{access, read, AccessFlags, Pid},
+    Error,
 };
 use std::{
     cell::{Cell, Ref, RefCell, RefMut},
@@ -158,6 +161,7 @@ use std::{
     env,
     ffi::{OsStr, OsString},
     fs,
+    io,
     mem,
     ops::{Deref, DerefMut},
     os::unix::ffi::{OsStrExt, OsStringExt},
@@ -330,6 +334,11 @@ pub struct RecordSession {
     /// `None` means the user did not provide any trace dir options and we need
     /// to use the default trace dir.
     output_trace_dir: Option<OsString>,
+
+    /// DIFF NOTE: Not present in rr
+    /// rd's CPU affinity before it bound itself to the trace's CPU (if any).
+    /// See `set_cpu_affinity()`
+    original_cpu_affinity: CpuSet,
 }
 
 impl Drop for RecordSession {
@@ -371,6 +380,12 @@ impl RecordSession {
             }
         }
 
+        // The binding to the trace's CPU (if any) happens when the first tracee is spawned
+        let original_cpu_affinity = match sched_getaffinity(Pid::from_raw(0)) {
+            Ok(mask) => mask,
+            Err(e) => fatal!("Couldn't get CPU affinity: {:?}", e),
+        };
+
         let mut rec_sess = RecordSession {
             session_inner: SessionInner::new(),
             trace_out: RefCell::new(TraceWriter::new(
@@ -378,6 +393,7 @@ impl RecordSession {
                 choose_cpu(flags.bind_cpu),
                 flags.output_trace_dir.as_deref(),
                 TicksSemantics::default(),
+                flags.compression,
             )),
             scheduler_: sched,
             initial_thread_group: Default::default(),
@@ -396,6 +412,7 @@ impl RecordSession {
             asan_active_: asan_active,
             wait_for_all_: flags.wait_for_all,
             output_trace_dir: flags.output_trace_dir.clone(),
+            original_cpu_affinity,
         };
 
         if !SessionInner::has_cpuid_faulting()
@@ -404,6 +421,11 @@ impl RecordSession {
             fatal!("CPUID faulting required to disable CPUID features");
         }
 
+        rec_sess
+            .trace_out
+            .borrow_mut()
+            .set_frame_index_interval(flags.frame_index_interval);
+
         // CPU affinity has been set.
         rec_sess.trace_out.borrow_mut().setup_cpuid_records(
             SessionInner::has_cpuid_faulting(),
@@ -942,6 +964,21 @@ impl RecordSession {
                 si.si_code,
                 unsafe { si._sifields._sigpoll.si_fd }
             );
+        } else {
+            let mut si = rt.get_siginfo().clone();
+            match rt.session().on_signal_delivery(rt, sig.as_raw(), &si) {
+                SignalDisposition::Pass => (),
+                SignalDisposition::Suppress => {
+                    log!(LogDebug, "Suppressing {} at the request of a handler", sig);
+                    // Like above, declining to deliver the signal is achieved by ignoring
+                    // the ptrace notification.
+                    return true;
+                }
+                SignalDisposition::Replace(new_signo) => {
+                    si.si_signo = new_signo;
+                    rt.set_siginfo(&si);
+                }
+            }
         }
         rt.stash_sig();
 
@@ -1410,6 +1447,8 @@ impl RecordSession {
                 debug_exec_state("EXEC_SYSCALL_ENTRY", t);
                 ed_assert!(t, !t.emulated_stop_pending);
 
+                let entry_regs = t.regs_ref().clone();
+                self.notify_syscall_entry(t, &entry_regs);
                 self.last_task_switchable.set(rec_prepare_syscall(t));
                 t.ev_mut().syscall_event_mut().switchable = self.last_task_switchable.get();
                 let regs = t.ev().syscall_event().regs.clone();
@@ -2039,9 +2078,69 @@ impl RecordSession {
     /// Close trace output without flushing syscall buffers or writing
     /// task exit/termination records to the trace.
     pub fn close_trace_writer(&self, status: CloseStatus) {
-        self.trace_out
-            .borrow_mut()
-            .close(status, Some(*self.trace_id.clone()));
+        // DIFF NOTE: rd also stores the session statistics in the trace metadata
+        let stats = self.session_statistics();
+        let mut trace_out = self.trace_out.borrow_mut();
+        stats.write_to_metadata(trace_out.metadata_mut());
+        trace_out.close(status, Some(*self.trace_id.clone()));
+    }
+
+    /// Bind rd and all tracees to `cpu` or, if `cpu` is None, restore the affinity rd had
+    /// when the session was created. Fails without changing any affinity if `cpu` is out
+    /// of range or offline. The binding is stored in the trace so that replay binds to the
+    /// same CPU (see `Session::cpu_binding()`).
+    ///
+    /// @TODO The trace stores a single binding for the whole recording and the CPUID
+    /// records are not collected again, so this should be called before tracees
+    /// have run.
+    /// DIFF NOTE: Not present in rr. rr only binds once, before spawning the first tracee.
+    pub fn set_cpu_affinity(&self, cpu: Option<u32>) -> io::Result<()> {
+        let mask = match cpu {
+            Some(cpu) => {
+                let mut mask = CpuSet::new();
+                if mask.set(cpu as usize).is_err() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "CPU {} is out of range, at most {} CPUs are supported",
+                            cpu,
+                            CpuSet::count()
+                        ),
+                    ));
+                }
+                mask
+            }
+            None => self.original_cpu_affinity,
+        };
+
+        // rd itself first so an offline CPU is detected before any tracee is affected
+        match sched_setaffinity(Pid::from_raw(0), &mask) {
+            Ok(()) => (),
+            Err(Error::Sys(Errno::EINVAL)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("CPU {:?} is offline or not permitted", cpu),
+                ))
+            }
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Couldn't set CPU affinity of rd: {:?}", e),
+                ))
+            }
+        }
+        for t in self.tasks_iter() {
+            let tid = t.borrow().tid;
+            if let Err(e) = sched_setaffinity(Pid::from_raw(tid), &mask) {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Couldn't set CPU affinity of tracee {}: {:?}", tid, e),
+                ));
+            }
+        }
+
+        self.trace_out.borrow_mut().set_bound_cpu(cpu);
+        Ok(())
     }
 
     pub fn trace_writer(&self) -> Ref<'_, TraceWriter> {
@@ -18,6 +18,7 @@ use crate::{
     },
     cpuid_bug_detector::CPUIDBugDetector,
     emu_fs::{EmuFs, EmuFsSharedPtr},
+    error::exit_on_error,
     event::{Event, EventType, SignalDeterministic, SignalEventData, SyscallState},
     fast_forward::{fast_forward_through_instruction, FastForwardStatus},
     flags::Flags as ProgramFlags,
@@ -485,7 +486,7 @@ impl ReplaySession {
     fn new<T: AsRef<OsStr>>(dir: Option<&T>, flags: Flags) -> ReplaySession {
         let mut rs = ReplaySession {
             emu_fs: EmuFs::create(),
-            trace_in: RefCell::new(TraceReader::new(dir)),
+            trace_in: RefCell::new(exit_on_error(TraceReader::open_with_migration(dir))),
             trace_frame: Default::default(),
             current_step: Default::default(),
             ticks_at_start_of_event: Default::default(),
@@ -546,6 +547,7 @@ impl ReplaySession {
         }
 
         *self.trace_frame.borrow_mut() = self.trace_in.borrow_mut().read_frame();
+        self.accumulate_event();
     }
 
     /// Create a replay session that will use the trace directory specified
@@ -736,6 +738,12 @@ impl ReplaySession {
 
                 debug_memory(t);
 
+                // DIFF NOTE: Not present in rr. Raw data records the frame's replay
+                // didn't consume e.g. the ones written by
+                // `Session::record_raw_bytes_for_addr()`. Apply them now, otherwise the
+                // RawData substream gets out of step with the frames.
+                t.apply_all_data_records_from_trace();
+
                 self.check_for_watchpoint_changes(t, &mut result.break_status);
                 self.check_approaching_ticks_target(t, &constraints, &mut result.break_status);
             }
@@ -1015,6 +1023,46 @@ impl ReplaySession {
         self.replay_step_with_constraints(StepConstraints::new(command))
     }
 
+    /// Replay until the frame at `target` is the next one to be replayed.
+    /// Breakpoints and watchpoints hit on the way are ignored.
+    ///
+    /// The frame index of the trace (see `TraceReader::seek_to_time()`) can't
+    /// be used to jump ahead here: the tracees have to actually execute up to
+    /// `target`, so skipping frames would need a checkpoint of the tracee state
+    /// at an indexed frame. Instead this replays forward from the current
+    /// frame.
+    ///
+    /// Only forward seeks are supported: if `target` is behind the current
+    /// frame an `InvalidInput` error is returned and the session is left as is.
+    /// @TODO Restore the nearest checkpoint before `target` instead once
+    /// clone_replay() is implemented.
+    /// DIFF NOTE: Not present in rr
+    pub fn fast_forward_to(&self, target: FrameTime) -> io::Result<()> {
+        let current = self.current_frame_time();
+        if target < current {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Can't replay backwards from frame {} to frame {}",
+                    current, target
+                ),
+            ));
+        }
+
+        let mut constraints = StepConstraints::new(RunCommand::RunContinue);
+        constraints.stop_at_time = target;
+        while self.current_frame_time() < target {
+            let result = self.replay_step_with_constraints(constraints.clone());
+            if result.status == ReplayStatus::ReplayExited {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("Replay exited before reaching frame {}", target),
+                ));
+            }
+        }
+        Ok(())
+    }
+
     fn emulate_signal_delivery(&self, t: &mut ReplayTask, sig: Sig) -> Completion {
         let maybe_t = self.current_task();
         match maybe_t {
@@ -1243,6 +1291,8 @@ impl ReplaySession {
             }
         }
 
+        let entry_regs = t.regs_ref().clone();
+        self.notify_syscall_entry(t, &entry_regs);
         if self.current_trace_frame().event().syscall_event().state == SyscallState::EnteringSyscall
         {
             rep_after_enter_syscall(t);
@@ -1258,6 +1308,8 @@ impl ReplaySession {
 
         t.apply_all_data_records_from_trace();
         t.set_return_value_from_trace();
+        let exit_regs = t.regs_ref().clone();
+        self.notify_syscall_exit(t, &exit_regs);
 
         let mut flags = ReplayTaskIgnore::IgnoreNone;
         if t.arch() == SupportedArch::X86
@@ -84,6 +84,7 @@ pub(super) fn kill_all_tasks<S: Session>(sess: &S) {
 
     while !sess.task_map.borrow().is_empty() {
         let (_, t) = sess.task_map.borrow_mut().pop_last().unwrap();
+        sess.killed_tids.borrow_mut().push(t.borrow().tid);
         if !t.borrow().unstable.get() {
             // Destroy the OS task backing this by sending it SIGKILL and
             // ensuring it was delivered.  After `kill()`, the only
@@ -1,9 +1,11 @@
-use super::address_space::WatchType;
+use super::address_space::{WatchType, WatchpointSize};
 use crate::{
     bindings::signal::siginfo_t,
     flags::Flags,
     log::LogLevel::LogDebug,
     perf_counters::{self, PerfCounters, TicksSemantics},
+    registers::Registers,
+    remote_code_ptr::RemoteCodePtr,
     remote_ptr::{RemotePtr, Void},
     scoped_fd::ScopedFd,
     session::{
@@ -23,20 +25,24 @@ use crate::{
     taskish_uid::{AddressSpaceUid, ThreadGroupUid},
     thread_group::{ThreadGroup, ThreadGroupSharedPtr, ThreadGroupSharedWeakPtr},
     ticks::Ticks,
+    trace::{trace_frame::FrameTime, trace_stream::TraceMetadata},
     util::cpuid_faulting_works,
 };
-use libc::{pid_t, SIGTRAP};
+use libc::{getrusage, pid_t, rusage, timeval, RUSAGE_CHILDREN, SIGTRAP};
 use nix::{
     fcntl::OFlag,
     unistd::{pipe2, read},
 };
 use std::{
-    cell::{Cell, RefCell},
-    collections::{BTreeMap, HashMap},
+    cell::{Cell, Ref, RefCell},
+    collections::{btree_map, BTreeMap, BTreeSet, HashMap},
     ffi::{OsStr, OsString},
+    io,
+    mem,
     os::unix::ffi::OsStringExt,
     rc::Rc,
     sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
 };
 
 #[derive(Clone)]
@@ -128,6 +134,214 @@ pub fn is_singlestep(command: RunCommand) -> bool {
     command == RunCommand::RunSinglestep || command == RunCommand::RunSinglestepFastForward
 }
 
+/// Implemented by tools that want to see every syscall entry and exit of
+/// every task in a session without modifying rd itself.
+/// See `Session::observe_all_syscalls()`.
+pub trait SyscallObserver {
+    /// Called when `t` enters a syscall. `regs` are the registers at entry.
+    fn on_entry(&self, t: &dyn Task, regs: &Registers);
+
+    /// Called when `t` exits a syscall. `regs` contain the syscall result.
+    fn on_exit(&self, t: &dyn Task, regs: &Registers);
+}
+
+/// What should happen to a signal received by a tracee.
+/// See `SignalHandler`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SignalDisposition {
+    /// Deliver the signal as usual.
+    Pass,
+    /// Don't deliver the signal to the tracee.
+    Suppress,
+    /// Deliver this signal number instead.
+    Replace(i32),
+}
+
+/// Implemented by tools that want to intercept (and possibly modify) the
+/// delivery of signals to tracees during recording.
+/// See `Session::register_signal_handler()`.
+pub trait SignalHandler {
+    /// Called when `t` receives signal `signo` from ptrace. `si` is the
+    /// siginfo of the signal. `signo` reflects replacements made by
+    /// handlers registered earlier.
+    fn on_signal(&self, t: &dyn Task, signo: i32, si: &siginfo_t) -> SignalDisposition;
+}
+
+/// Session-wide software (INT3) breakpoints. Each breakpoint is installed as
+/// a BkptUser breakpoint in every address space of the session.
+///
+/// Patching in the breakpoint instruction, remembering the original byte and
+/// restoring it whenever a task (possibly one of many hitting the same
+/// breakpoint) needs to execute the original instruction is done by
+/// AddressSpace.
+///
+/// NOTE: Address spaces created after a breakpoint was added don't get it.
+/// DIFF NOTE: Not present in rr
+pub struct BreakpointManager<'a> {
+    session: &'a SessionInner,
+}
+
+impl<'a> BreakpointManager<'a> {
+    pub fn add_breakpoint(&self, addr: RemoteCodePtr) -> io::Result<()> {
+        if self.is_breakpoint(addr) {
+            return Ok(());
+        }
+
+        let vms = self.session.vms();
+        for (i, vm) in vms.iter().enumerate() {
+            let t = match vm.any_task_from_task_set() {
+                Some(t) => t,
+                None => continue,
+            };
+            let added = vm.add_breakpoint(t.borrow_mut().as_mut(), addr, BreakpointType::BkptUser);
+            if !added {
+                // Undo what we've done so far
+                for vm in &vms[0..i] {
+                    if let Some(t) = vm.any_task_from_task_set() {
+                        vm.remove_breakpoint(
+                            addr,
+                            BreakpointType::BkptUser,
+                            t.borrow_mut().as_mut(),
+                        );
+                    }
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Could not set breakpoint at {}", addr),
+                ));
+            }
+        }
+
+        self.session.user_breakpoints.borrow_mut().insert(addr);
+        Ok(())
+    }
+
+    /// Returns false if there was no breakpoint at `addr`.
+    pub fn remove_breakpoint(&self, addr: RemoteCodePtr) -> bool {
+        if !self.session.user_breakpoints.borrow_mut().remove(&addr) {
+            return false;
+        }
+
+        for vm in self.session.vms() {
+            if let Some(t) = vm.any_task_from_task_set() {
+                vm.remove_breakpoint(addr, BreakpointType::BkptUser, t.borrow_mut().as_mut());
+            }
+        }
+        true
+    }
+
+    pub fn is_breakpoint(&self, addr: RemoteCodePtr) -> bool {
+        self.session.user_breakpoints.borrow().contains(&addr)
+    }
+}
+
+/// Identifies a watchpoint added with `WatchpointManager::add_watchpoint()`.
+/// DIFF NOTE: Not present in rr
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct WatchpointHandle(u32);
+
+/// Session-wide hardware watchpoints. Each watchpoint is added to every
+/// address space of the session.
+///
+/// AddressSpace allocates the debug registers and programs them into every
+/// task of the address space, including threads cloned later on (see
+/// `AddressSpace::after_clone()`). Address spaces created by a fork copy the
+/// watchpoints of their parent. During replay those are dropped so the
+/// session watchpoints are installed again, see `install_watchpoints()`.
+/// DIFF NOTE: Not present in rr
+pub struct WatchpointManager<'a> {
+    session: &'a SessionInner,
+}
+
+impl<'a> WatchpointManager<'a> {
+    /// Fails if `addr` is not aligned to `size` or if some address space
+    /// does not have enough free debug registers left.
+    pub fn add_watchpoint(
+        &self,
+        addr: RemotePtr<Void>,
+        size: WatchpointSize,
+        type_: WatchType,
+    ) -> io::Result<WatchpointHandle> {
+        let num_bytes = size.num_bytes();
+        if addr.as_usize() % num_bytes != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Watchpoint at {} is not aligned to {} bytes",
+                    addr, num_bytes
+                ),
+            ));
+        }
+
+        let vms = self.session.vms();
+        for (i, vm) in vms.iter().enumerate() {
+            let t = match vm.any_task_from_task_set() {
+                Some(t) => t,
+                None => continue,
+            };
+            let added = vm.add_watchpoint(addr, num_bytes, type_, t.borrow_mut().as_mut());
+            if !added {
+                // AddressSpace keeps the watchpoint even if it could not be
+                // programmed, so undo this address space too
+                for vm in &vms[0..=i] {
+                    if let Some(t) = vm.any_task_from_task_set() {
+                        vm.remove_watchpoint(addr, num_bytes, type_, t.borrow_mut().as_mut());
+                    }
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Could not set watchpoint at {}", addr),
+                ));
+            }
+        }
+
+        let handle = WatchpointHandle(self.session.next_watchpoint_handle.get());
+        self.session.next_watchpoint_handle.set(handle.0 + 1);
+        self.session
+            .user_watchpoints
+            .borrow_mut()
+            .insert(handle, WatchConfig::new(addr, num_bytes, type_));
+        Ok(handle)
+    }
+
+    /// Returns false if there is no watchpoint with `handle`.
+    pub fn remove_watchpoint(&self, handle: WatchpointHandle) -> bool {
+        let w = match self.session.user_watchpoints.borrow_mut().remove(&handle) {
+            Some(w) => w,
+            None => return false,
+        };
+
+        for vm in self.session.vms() {
+            if let Some(t) = vm.any_task_from_task_set() {
+                vm.remove_watchpoint(w.addr, w.num_bytes, w.type_, t.borrow_mut().as_mut());
+            }
+        }
+        true
+    }
+
+    /// All watchpoints of the session, in the order they were added.
+    pub fn watchpoints(&self) -> Vec<(WatchpointHandle, WatchConfig)> {
+        self.session
+            .user_watchpoints
+            .borrow()
+            .iter()
+            .map(|(&handle, &w)| (handle, w))
+            .collect()
+    }
+
+    /// Add all the session watchpoints to the address space of `t`, which
+    /// must not have any of them yet. Returns false if the debug registers
+    /// could not be programmed.
+    pub fn install_watchpoints(&self, t: &mut dyn Task) -> bool {
+        let vm = t.vm_shr_ptr();
+        let mut ok = true;
+        for w in self.session.user_watchpoints.borrow().values() {
+            ok &= vm.add_watchpoint(w.addr, w.num_bytes, w.type_, t);
+        }
+        ok
+    }
+}
+
 /// AddressSpaces and ThreadGroups are indexed by their first task's TaskUid
 /// (effectively), so that if the first task dies and its tid is recycled,
 /// we don't get confused. TaskMap is indexed by tid since there can never be
@@ -136,6 +350,34 @@ pub type AddressSpaceMap = HashMap<AddressSpaceUid, AddressSpaceSharedWeakPtr>;
 pub type TaskMap = BTreeMap<pid_t, TaskSharedPtr>;
 pub type ThreadGroupMap = HashMap<ThreadGroupUid, ThreadGroupSharedWeakPtr>;
 
+/// Returned by `Session::tasks_iter()` so tasks can be iterated over with a plain `for` loop.
+///
+/// Iterating by value (`for t in session.tasks_iter()`) iterates over a snapshot of the tasks
+/// and releases the borrow of the task map first, so the loop body may add or remove tasks.
+/// Iterating by reference (`for t in &session.tasks_iter()`) avoids the copy but keeps the
+/// task map borrowed for the duration of the loop.
+/// DIFF NOTE: Not present in rr
+pub struct TaskMapRef<'a>(pub Ref<'a, TaskMap>);
+
+impl IntoIterator for TaskMapRef<'_> {
+    type Item = TaskSharedPtr;
+    type IntoIter = std::vec::IntoIter<TaskSharedPtr>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let tasks: Vec<TaskSharedPtr> = self.0.values().cloned().collect();
+        tasks.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a TaskMapRef<'_> {
+    type Item = &'a TaskSharedPtr;
+    type IntoIter = btree_map::Values<'a, pid_t, TaskSharedPtr>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.values()
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum PtraceSyscallSeccompOrdering {
     SyscallBeforeSeccomp,
@@ -165,6 +407,47 @@ pub(super) struct CloneCompletion {
     pub address_spaces: Vec<AddressSpaceClone>,
 }
 
+/// Summary of a session's resource usage. See `SessionInner::session_statistics()`.
+/// Stored in the trace metadata when a recording is closed.
+/// DIFF NOTE: Not present in rr
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SessionStatistics {
+    pub event_count: u64,
+    pub task_count: u32,
+    pub peak_rss_bytes: u64,
+    pub elapsed_wall_ns: u64,
+    pub elapsed_cpu_ns: u64,
+}
+
+impl SessionStatistics {
+    const EVENT_COUNT_KEY: &'static str = "rd.stats.event_count";
+    const TASK_COUNT_KEY: &'static str = "rd.stats.task_count";
+    const PEAK_RSS_BYTES_KEY: &'static str = "rd.stats.peak_rss_bytes";
+    const ELAPSED_WALL_NS_KEY: &'static str = "rd.stats.elapsed_wall_ns";
+    const ELAPSED_CPU_NS_KEY: &'static str = "rd.stats.elapsed_cpu_ns";
+
+    pub fn write_to_metadata(&self, metadata: &mut TraceMetadata) {
+        metadata.set(Self::EVENT_COUNT_KEY, &self.event_count.to_string());
+        metadata.set(Self::TASK_COUNT_KEY, &self.task_count.to_string());
+        metadata.set(Self::PEAK_RSS_BYTES_KEY, &self.peak_rss_bytes.to_string());
+        metadata.set(Self::ELAPSED_WALL_NS_KEY, &self.elapsed_wall_ns.to_string());
+        metadata.set(Self::ELAPSED_CPU_NS_KEY, &self.elapsed_cpu_ns.to_string());
+    }
+
+    /// None if any of the statistics is missing or malformed e.g. because the trace was
+    /// recorded by an older rd.
+    pub fn from_metadata(metadata: &TraceMetadata) -> Option<SessionStatistics> {
+        let get = |key: &str| metadata.get(key)?.parse::<u64>().ok();
+        Some(SessionStatistics {
+            event_count: get(Self::EVENT_COUNT_KEY)?,
+            task_count: get(Self::TASK_COUNT_KEY)? as u32,
+            peak_rss_bytes: get(Self::PEAK_RSS_BYTES_KEY)?,
+            elapsed_wall_ns: get(Self::ELAPSED_WALL_NS_KEY)?,
+            elapsed_cpu_ns: get(Self::ELAPSED_CPU_NS_KEY)?,
+        })
+    }
+}
+
 /// Sessions track the global state of a set of tracees corresponding
 /// to an rd recorder or replayer.  During recording, the tracked
 /// tracees will all write to the same TraceWriter, and during
@@ -341,6 +624,90 @@ impl SessionInner {
         *self.statistics_.borrow()
     }
 
+    /// Call whenever a trace frame is written (during recording) or read (during replay)
+    /// i.e. whenever the trace stream's time is ticked.
+    /// DIFF NOTE: Not present in rr
+    pub fn accumulate_event(&self) {
+        self.event_count_.set(self.event_count_.get() + 1);
+    }
+
+    /// Note that CPU time and peak RSS come from `getrusage(RUSAGE_CHILDREN)`, so they only
+    /// account for tracees that have exited and been reaped. They also include any other
+    /// children of this process.
+    /// DIFF NOTE: Not present in rr. Named differently to avoid a clash with `statistics()`
+    pub fn session_statistics(&self) -> SessionStatistics {
+        let mut usage: rusage = unsafe { mem::zeroed() };
+        if unsafe { getrusage(RUSAGE_CHILDREN, &mut usage) } < 0 {
+            log!(
+                LogDebug,
+                "getrusage failed: {:?}",
+                io::Error::last_os_error()
+            );
+        }
+        let to_ns = |tv: &timeval| tv.tv_sec as u64 * 1_000_000_000 + tv.tv_usec as u64 * 1000;
+        SessionStatistics {
+            event_count: self.event_count_.get(),
+            task_count: self.task_map.borrow().len() as u32,
+            // ru_maxrss is in kB
+            peak_rss_bytes: usage.ru_maxrss as u64 * 1024,
+            elapsed_wall_ns: self.created_at.elapsed().as_nanos() as u64,
+            elapsed_cpu_ns: to_ns(&usage.ru_utime) + to_ns(&usage.ru_stime),
+        }
+    }
+
+    /// DIFF NOTE: Not present in rr
+    pub fn breakpoint_manager(&self) -> BreakpointManager<'_> {
+        BreakpointManager { session: self }
+    }
+
+    /// DIFF NOTE: Not present in rr
+    pub fn watchpoint_manager(&self) -> WatchpointManager<'_> {
+        WatchpointManager { session: self }
+    }
+
+    /// Snapshots taken by `Session::capture_vmrss_snapshot()`, oldest first.
+    pub fn vmrss_history(&self) -> Ref<'_, Vec<(FrameTime, HashMap<pid_t, u64>)>> {
+        self.vmrss_history_.borrow()
+    }
+
+    /// Run all registered SignalHandler-s, in registration order, for signal
+    /// `signo` received by `t`. Stops at the first handler that suppresses the
+    /// signal.
+    pub fn on_signal_delivery(
+        &self,
+        t: &dyn Task,
+        signo: i32,
+        si: &siginfo_t,
+    ) -> SignalDisposition {
+        let mut disposition = SignalDisposition::Pass;
+        let mut signo = signo;
+        for handler in self.signal_handlers.borrow().iter() {
+            match handler.on_signal(t, signo, si) {
+                SignalDisposition::Pass => (),
+                SignalDisposition::Suppress => return SignalDisposition::Suppress,
+                SignalDisposition::Replace(new_signo) => {
+                    signo = new_signo;
+                    disposition = SignalDisposition::Replace(new_signo);
+                }
+            }
+        }
+        disposition
+    }
+
+    /// Notify all registered SyscallObserver-s that `t` just entered a syscall.
+    pub fn notify_syscall_entry(&self, t: &dyn Task, regs: &Registers) {
+        for obs in self.syscall_observers.borrow().iter() {
+            obs.on_entry(t, regs);
+        }
+    }
+
+    /// Notify all registered SyscallObserver-s that `t` just exited a syscall.
+    pub fn notify_syscall_exit(&self, t: &dyn Task, regs: &Registers) {
+        for obs in self.syscall_observers.borrow().iter() {
+            obs.on_exit(t, regs);
+        }
+    }
+
     pub fn read_spawned_task_error(&self) -> OsString {
         let mut buf: Vec<u8> = vec![0; 1000];
         let res = read(self.spawned_task_error_fd_.borrow().as_raw(), &mut buf);
@@ -396,6 +763,15 @@ impl SessionInner {
             ticks_semantics_: PerfCounters::default_ticks_semantics(),
             done_initial_exec_: Default::default(),
             visible_execution_: true,
+            syscall_observers: Default::default(),
+            signal_handlers: Default::default(),
+            vmrss_history_: Default::default(),
+            user_breakpoints: Default::default(),
+            user_watchpoints: Default::default(),
+            next_watchpoint_handle: Cell::new(1),
+            killed_tids: Default::default(),
+            event_count_: Cell::new(0),
+            created_at: Instant::now(),
         };
         log!(LogDebug, "Session {} created", s.unique_id);
         s
@@ -589,6 +965,37 @@ pub struct SessionInner {
 
     /// True while the execution of this session is visible to users.
     pub(super) visible_execution_: bool,
+
+    /// DIFF NOTE: Not present in rr
+    /// Observers notified on every syscall entry and exit across all tasks.
+    pub(super) syscall_observers: RefCell<Vec<Box<dyn SyscallObserver>>>,
+
+    /// DIFF NOTE: Not present in rr
+    /// Handlers run whenever a signal is received by a tracee during recording.
+    pub(super) signal_handlers: RefCell<Vec<Box<dyn SignalHandler>>>,
+
+    /// DIFF NOTE: Not present in rr
+    /// VmRSS (in kB) of each task, keyed by rec_tid, at various frame times.
+    pub(super) vmrss_history_: RefCell<Vec<(FrameTime, HashMap<pid_t, u64>)>>,
+
+    /// DIFF NOTE: Not present in rr
+    /// Breakpoints added via `breakpoint_manager()`.
+    pub(super) user_breakpoints: RefCell<BTreeSet<RemoteCodePtr>>,
+
+    /// DIFF NOTE: Not present in rr
+    /// Watchpoints added via `watchpoint_manager()`.
+    pub(super) user_watchpoints: RefCell<BTreeMap<WatchpointHandle, WatchConfig>>,
+    pub(super) next_watchpoint_handle: Cell<u32>,
+
+    /// DIFF NOTE: Not present in rr
+    /// Real tids of the tasks removed by `kill_all_tasks()`.
+    /// See `Session::assert_no_leaking_tasks()`.
+    pub(super) killed_tids: RefCell<Vec<pid_t>>,
+
+    /// DIFF NOTE: Not present in rr
+    /// See `accumulate_event()` and `session_statistics()`.
+    pub(super) event_count_: Cell<u64>,
+    pub(super) created_at: Instant,
 }
 
 impl Default for SessionInner {
@@ -596,3 +1003,82 @@ impl Default for SessionInner {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kernel_abi::{is_exit_group_syscall, SupportedArch},
+        session::{
+            record_session::RecordResult,
+            test_util::{finish_recording, start_recording},
+            Session,
+        },
+        trace::test_util::TempTraceDir,
+    };
+
+    type SyscallLog = Rc<RefCell<Vec<(pid_t, i32, SupportedArch)>>>;
+
+    /// Logs the tid, syscall number and arch of every syscall entry and exit
+    struct CollectingObserver {
+        entries: SyscallLog,
+        exits: SyscallLog,
+    }
+
+    impl SyscallObserver for CollectingObserver {
+        fn on_entry(&self, t: &dyn Task, regs: &Registers) {
+            let syscallno = regs.original_syscallno() as i32;
+            self.entries
+                .borrow_mut()
+                .push((t.tid, syscallno, regs.arch()));
+        }
+
+        fn on_exit(&self, t: &dyn Task, regs: &Registers) {
+            let syscallno = regs.original_syscallno() as i32;
+            self.exits
+                .borrow_mut()
+                .push((t.tid, syscallno, regs.arch()));
+        }
+    }
+
+    #[test]
+    fn syscall_observer_test() {
+        let dir = TempTraceDir::new("syscall-observer");
+        let session = start_recording(&dir, &["/bin/true"]);
+        let entries = SyscallLog::default();
+        let exits = SyscallLog::default();
+        session.observe_all_syscalls(Box::new(CollectingObserver {
+            entries: entries.clone(),
+            exits: exits.clone(),
+        }));
+        assert!(matches!(
+            finish_recording(&session),
+            RecordResult::StepExited(_)
+        ));
+
+        let mut entries = entries.borrow().clone();
+        // exit_group is the only syscall that doesn't exit
+        let (_, syscallno, arch) = entries.pop().unwrap();
+        assert!(is_exit_group_syscall(syscallno, arch));
+        assert!(!entries.is_empty());
+        assert_eq!(entries, *exits.borrow());
+    }
+
+    #[test]
+    fn session_statistics_metadata_test() {
+        let stats = SessionStatistics {
+            event_count: 1234,
+            task_count: 3,
+            peak_rss_bytes: 64 << 20,
+            elapsed_wall_ns: 5_000_000_000,
+            elapsed_cpu_ns: 4_000_000_000,
+        };
+        let mut metadata = TraceMetadata::default();
+        assert_eq!(None, SessionStatistics::from_metadata(&metadata));
+        stats.write_to_metadata(&mut metadata);
+        assert_eq!(Some(stats), SessionStatistics::from_metadata(&metadata));
+
+        metadata.set("rd.stats.task_count", "many");
+        assert_eq!(None, SessionStatistics::from_metadata(&metadata));
+    }
+}
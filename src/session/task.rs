@@ -48,8 +48,10 @@ use task_inner::TrapReasons;
 use super::SessionSharedPtr;
 use crate::weak_ptr_set::WeakPtrSet;
 
+pub mod fd_info;
 pub mod record_task;
 pub mod replay_task;
+pub mod stack_frame;
 pub mod task_common;
 pub mod task_inner;
 
@@ -611,6 +613,7 @@ pub trait Task: DerefMut<Target = TaskInner> {
         let bytes_read = res.unwrap();
         ed_assert!(self, bytes_read > 0);
         self.prname = OsString::from_vec(buf);
+        self.thread_name_cache = None;
     }
 
     fn compute_trap_reasons(&mut self) -> TrapReasons;
@@ -0,0 +1,235 @@
+//! Read-only snapshot of a task's open file descriptors from /proc. Useful when debugging
+//! I/O related divergences.
+//!
+//! DIFF NOTE: Not present in rr.
+//!
+//! Nothing here uses ptrace, so it is safe to call at any time. Note that the fd table can change
+//! under us if the task is running, so fds that disappear while we read them are skipped.
+
+use crate::log::LogLevel::LogDebug;
+use libc::pid_t;
+use nix::fcntl::readlink;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::unix::ffi::OsStrExt,
+};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FdType {
+    File,
+    Socket,
+    Pipe,
+    EventFd,
+    Epoll,
+    Other,
+}
+
+impl FdType {
+    /// Classify an fd by the target of its /proc/{tid}/fd/{fd} link
+    fn from_link(link: &[u8]) -> FdType {
+        if link.starts_with(b"/") {
+            FdType::File
+        } else if link.starts_with(b"socket:[") {
+            FdType::Socket
+        } else if link.starts_with(b"pipe:[") {
+            FdType::Pipe
+        } else if link == b"anon_inode:[eventfd]" {
+            FdType::EventFd
+        } else if link == b"anon_inode:[eventpoll]" {
+            FdType::Epoll
+        } else {
+            FdType::Other
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FileDescriptorInfo {
+    /// Target of the /proc/{tid}/fd/{fd} link e.g. "/tmp/foo" or "socket:[1234]"
+    pub path: OsString,
+    /// The `flags:` field in /proc/{tid}/fdinfo/{fd} i.e. the O_* file status flags
+    pub flags: i32,
+    pub pos: u64,
+    pub fd_type: FdType,
+    /// Only available for TCP sockets. @TODO unix socket peers don't fit in a `SocketAddr`
+    pub socket_peer: Option<SocketAddr>,
+}
+
+/// See `TaskInner::file_descriptors()`
+pub fn read_fd_table(tid: pid_t) -> HashMap<i32, FileDescriptorInfo> {
+    let mut fds = HashMap::new();
+    let fd_dir = format!("/proc/{}/fd", tid);
+    let entries = match fs::read_dir(&fd_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log!(LogDebug, "Could not read {}: {:?}", fd_dir, e);
+            return fds;
+        }
+    };
+
+    let mut tcp_peers: Option<HashMap<u64, SocketAddr>> = None;
+    for entry in entries.filter_map(Result::ok) {
+        let fd = match entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<i32>().ok())
+        {
+            Some(fd) => fd,
+            None => continue,
+        };
+        // The fd may have been closed since we read the directory
+        let path = match readlink(entry.path().as_path()) {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        let (flags, pos) = match fs::read_to_string(format!("/proc/{}/fdinfo/{}", tid, fd)) {
+            Ok(fdinfo) => parse_fdinfo(&fdinfo),
+            Err(_) => continue,
+        };
+        let fd_type = FdType::from_link(path.as_bytes());
+        let socket_peer = if fd_type == FdType::Socket {
+            socket_inode(path.as_bytes()).and_then(|inode| {
+                tcp_peers
+                    .get_or_insert_with(|| read_tcp_peers(tid))
+                    .get(&inode)
+                    .copied()
+            })
+        } else {
+            None
+        };
+
+        fds.insert(
+            fd,
+            FileDescriptorInfo {
+                path,
+                flags,
+                pos,
+                fd_type,
+                socket_peer,
+            },
+        );
+    }
+
+    fds
+}
+
+/// Returns the (flags, pos) fields. Missing or malformed fields are returned as 0.
+fn parse_fdinfo(fdinfo: &str) -> (i32, u64) {
+    let mut flags = 0;
+    let mut pos = 0;
+    for line in fdinfo.lines() {
+        if let Some(v) = line.strip_prefix("pos:") {
+            pos = v.trim().parse::<u64>().unwrap_or(0);
+        } else if let Some(v) = line.strip_prefix("flags:") {
+            // flags are printed in octal
+            flags = i32::from_str_radix(v.trim(), 8).unwrap_or(0);
+        }
+    }
+    (flags, pos)
+}
+
+/// "socket:[1234]" -> 1234
+fn socket_inode(link: &[u8]) -> Option<u64> {
+    let link = std::str::from_utf8(link).ok()?;
+    let inode = link.strip_prefix("socket:[")?.strip_suffix(']')?;
+    inode.parse::<u64>().ok()
+}
+
+/// Map of socket inode -> remote address for the tcp and tcp6 sockets in the task's
+/// network namespace
+fn read_tcp_peers(tid: pid_t) -> HashMap<u64, SocketAddr> {
+    let mut peers = HashMap::new();
+    for proto in &["tcp", "tcp6"] {
+        if let Ok(table) = fs::read_to_string(format!("/proc/{}/net/{}", tid, proto)) {
+            // First line is a header
+            peers.extend(table.lines().skip(1).filter_map(parse_tcp_line));
+        }
+    }
+    peers
+}
+
+/// Parse a line of /proc/{tid}/net/tcp{,6} into (inode, remote address)
+fn parse_tcp_line(line: &str) -> Option<(u64, SocketAddr)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+    let rem_address = fields.get(2)?;
+    let inode = fields.get(9)?.parse::<u64>().ok()?;
+
+    let mut it = rem_address.split(':');
+    let addr_s = it.next()?;
+    let port = u16::from_str_radix(it.next()?, 16).ok()?;
+    // The kernel prints the address as a sequence of native endian 32 bit words
+    let mut addr_bytes = Vec::with_capacity(16);
+    for i in (0..addr_s.len()).step_by(8) {
+        let word = u32::from_str_radix(addr_s.get(i..i + 8)?, 16).ok()?;
+        addr_bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    let addr = match addr_bytes.len() {
+        4 => Ipv4Addr::new(addr_bytes[0], addr_bytes[1], addr_bytes[2], addr_bytes[3]).into(),
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addr_bytes);
+            Ipv6Addr::from(octets).into()
+        }
+        _ => return None,
+    };
+    Some((inode, SocketAddr::new(addr, port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::unistd::{close, getpid, pipe};
+    use std::{fs::File, os::unix::io::AsRawFd};
+
+    #[test]
+    fn from_link_test() {
+        assert_eq!(FdType::File, FdType::from_link(b"/dev/null"));
+        assert_eq!(FdType::Socket, FdType::from_link(b"socket:[1234]"));
+        assert_eq!(FdType::Pipe, FdType::from_link(b"pipe:[5678]"));
+        assert_eq!(FdType::EventFd, FdType::from_link(b"anon_inode:[eventfd]"));
+        assert_eq!(FdType::Epoll, FdType::from_link(b"anon_inode:[eventpoll]"));
+        assert_eq!(FdType::Other, FdType::from_link(b"anon_inode:inotify"));
+        assert_eq!(Some(1234), socket_inode(b"socket:[1234]"));
+        assert_eq!(None, socket_inode(b"pipe:[1234]"));
+    }
+
+    #[test]
+    fn parse_fdinfo_test() {
+        let fdinfo = "pos:\t42\nflags:\t0100002\nmnt_id:\t25\n";
+        assert_eq!((0o100002, 42), parse_fdinfo(fdinfo));
+        assert_eq!((0, 0), parse_fdinfo(""));
+    }
+
+    #[test]
+    fn parse_tcp_line_test() {
+        let line = "   0: 0100007F:1F90 0100007F:D431 01 00000000:00000000 00:00000000 00000000  \
+                    1000        0 31337 1 0000000000000000 20 4 30 10 -1";
+        let (inode, peer) = parse_tcp_line(line).unwrap();
+        assert_eq!(31337, inode);
+        if cfg!(target_endian = "little") {
+            assert_eq!("127.0.0.1:54321".parse::<SocketAddr>().unwrap(), peer);
+        }
+        assert!(parse_tcp_line("  sl  local_address rem_address   st tx_queue").is_none());
+    }
+
+    #[test]
+    fn read_fd_table_test() {
+        let file = File::open("/proc/self/maps").unwrap();
+        let (read_fd, write_fd) = pipe().unwrap();
+        let fds = read_fd_table(getpid().as_raw());
+
+        let info = &fds[&file.as_raw_fd()];
+        assert_eq!(FdType::File, info.fd_type);
+        assert_eq!(0, info.pos);
+        assert_eq!(FdType::Pipe, fds[&read_fd].fd_type);
+        assert_eq!(FdType::Pipe, fds[&write_fd].fd_type);
+        assert!(read_fd_table(-1).is_empty());
+
+        close(read_fd).unwrap();
+        close(write_fd).unwrap();
+    }
+}
@@ -2654,6 +2654,7 @@ impl RecordTask {
             maybe_record_registers.as_ref(),
             maybe_extra_registers.as_ref(),
         );
+        self.session().accumulate_event();
         log!(LogDebug, "Wrote event {} for time {}", ev, current_time);
 
         if !ev.has_ticks_slop() && reset == AllowSyscallbufReset::AllowResetSyscallbuf {
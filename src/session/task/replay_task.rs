@@ -30,7 +30,7 @@ use crate::{
         syscall_number_for_openat,
         SupportedArch,
     },
-    log::LogLevel::LogWarn,
+    log::LogLevel::{LogError, LogWarn},
     preload_interface::syscallbuf_record,
     preload_interface_arch::rdcall_init_buffers_params,
     rd::RD_RESERVED_ROOT_DIR_FD,
@@ -65,7 +65,7 @@ use crate::{
         trace_reader::{RawData, TraceReader},
         trace_stream::MappedData,
     },
-    util::page_size,
+    util::{page_size, xcr0},
     wait_status::WaitStatus,
 };
 use libc::{pid_t, O_CLOEXEC, O_RDONLY};
@@ -162,13 +162,23 @@ impl ReplayTask {
 
     /// Assert that the current register values match the values in the
     ///  current trace record.
-    pub fn validate_regs(&self, flags: ReplayTaskIgnore) {
+    /// The comparison is done by `Registers::diff()`, so the comparison masks
+    /// apply and orig_rax/orig_eax is only compared when both values are
+    /// syscall numbers, i.e. not when the kernel was entered via an interrupt.
+    /// DIFF NOTE: If the frame recorded extra registers, differences in the x87/SSE
+    /// state are logged too, see `validate_extra_regs()`. Takes `&mut self` because
+    /// of that.
+    pub fn validate_regs(&mut self, flags: ReplayTaskIgnore) {
         // don't validate anything before execve is done as the actual
         // *process did not start prior to this point
         if !self.session().done_initial_exec() {
             return;
         }
 
+        if !self.current_trace_frame().extra_regs_ref().is_empty() {
+            self.validate_extra_regs();
+        }
+
         // @TODO When this `if` triggers trace frame may already be borrowed.
         // This could run into a borrow mut error.
         if flags == ReplayTaskIgnore::IgnoreEsi {
@@ -189,6 +199,34 @@ impl ReplayTask {
         // TODO: add perf counter validations (hw int, page faults, insts)
         let trace_frame = self.current_trace_frame();
         let rec_regs = trace_frame.regs_ref();
+        if Registers::compare_register_files(
+            Some(self),
+            "replaying",
+            self.regs_ref(),
+            "recorded",
+            rec_regs,
+            MismatchBehavior::ExpectMismatches,
+        ) {
+            return;
+        }
+
+        // DIFF NOTE: rr only logs the mismatching registers. Log the full
+        // register files too, it's often the other registers that explain
+        // a divergence.
+        let mut replaying: Vec<u8> = Vec::new();
+        let mut recorded: Vec<u8> = Vec::new();
+        self.regs_ref()
+            .write_register_file_compact(&mut replaying)
+            .unwrap();
+        rec_regs.write_register_file_compact(&mut recorded).unwrap();
+        log!(
+            LogError,
+            "Register mismatch at frame {}:\n  replaying: {}\n  recorded:  {}",
+            trace_frame.time(),
+            String::from_utf8_lossy(&replaying),
+            String::from_utf8_lossy(&recorded)
+        );
+        // Logs every mismatching register, then bails
         Registers::compare_register_files(
             Some(self),
             "replaying",
@@ -199,6 +237,34 @@ impl ReplayTask {
         );
     }
 
+    /// Log the differences between the x87/SSE state in the legacy FXSAVE area and
+    /// the one recorded in the current trace frame. Only the state components
+    /// enabled in both the trace's and our XCR0 are compared. The rest of the XSAVE
+    /// area may legitimately differ between the recording and the replay machine, see
+    /// `check_xsave_compatibility()`.
+    /// DIFF NOTE: Not present in rr
+    fn validate_extra_regs(&mut self) {
+        let live = self.extra_regs_ref().clone();
+        let features = self.trace_reader().xcr0() & xcr0();
+        let trace_frame = self.current_trace_frame();
+        let diffs = match (live.fpu(), trace_frame.extra_regs_ref().fpu()) {
+            (Some(live_fpu), Some(recorded_fpu)) => {
+                live_fpu.compare_features(&recorded_fpu, features)
+            }
+            _ => return,
+        };
+        for diff in &diffs {
+            log!(
+                LogWarn,
+                "FPU register {} {:?} != {:?} (replaying vs. recorded) at frame {}",
+                diff.regno,
+                diff.value1,
+                diff.value2,
+                trace_frame.time()
+            );
+        }
+    }
+
     pub fn current_trace_frame(&self) -> OwningHandle<SessionSharedPtr, Ref<'_, TraceFrame>> {
         let sess = self.session();
         let owning_handle = OwningHandle::new_with_fn(sess, |o| {
@@ -242,7 +308,7 @@ impl ReplayTask {
                     .vm_shr_ptr()
                     .maybe_update_breakpoints(other, buf.addr, buf.data.len());
             } else {
-                let t = self.session().find_task_from_rec_tid(buf.rec_tid).unwrap();
+                let t = self.raw_data_task(buf.rec_tid, buf.addr);
 
                 t.borrow_mut()
                     .write_bytes_helper(buf.addr, &buf.data, None, WriteFlags::empty());
@@ -258,6 +324,22 @@ impl ReplayTask {
         buf.data.len()
     }
 
+    /// The task that raw data recorded for `rec_tid` needs to be written to,
+    /// when that is not this task.
+    /// DIFF NOTE: rr asserts that the task exists. Fail with a message that
+    /// says which record is affected instead of a bare unwrap.
+    fn raw_data_task(&self, rec_tid: pid_t, addr: RemotePtr<Void>) -> TaskSharedPtr {
+        match self.session().find_task_from_rec_tid(rec_tid) {
+            Some(t) => t,
+            None => fatal!(
+                "No task with rec_tid {} to write raw data at {} to (frame {})",
+                rec_tid,
+                addr,
+                self.current_frame_time()
+            ),
+        }
+    }
+
     pub fn trace_reader(&self) -> OwningHandle<SessionSharedPtr, Ref<'_, TraceReader>> {
         let sess = self.session();
         let owning_handle = OwningHandle::new_with_fn(sess, |o| {
@@ -289,7 +371,7 @@ impl ReplayTask {
                                 buf.data.len(),
                             );
                         } else {
-                            let t = self.session().find_task_from_rec_tid(buf.rec_tid).unwrap();
+                            let t = self.raw_data_task(buf.rec_tid, buf.addr);
                             t.borrow_mut().write_bytes_helper(
                                 buf.addr,
                                 &buf.data,
@@ -0,0 +1,472 @@
+//! Best-effort call stack reconstruction for a stopped task. Intended for diagnostics e.g. when
+//! replay diverges at a specific instruction.
+//!
+//! DIFF NOTE: Not present in rr.
+//!
+//! Each frame is unwound using the `.eh_frame` CFI of the ELF file the instruction pointer falls
+//! in. If there is no CFI for the address (or the file can't be read) we fall back to walking the
+//! frame pointer chain. The walk stops (rather than aborting) at the first address that can't be
+//! read or at the first step that does not move the stack pointer up.
+
+use crate::{
+    kernel_abi::SupportedArch,
+    log::LogLevel::LogDebug,
+    remote_code_ptr::RemoteCodePtr,
+    remote_ptr::RemotePtr,
+    session::{address_space::kernel_mapping::KernelMapping, task::Task},
+    util::word_size,
+};
+use gimli::{
+    BaseAddresses,
+    CfaRule,
+    EhFrame,
+    EndianRcSlice,
+    Reader,
+    RegisterRule,
+    RunTimeEndian,
+    UninitializedUnwindContext,
+    UnwindSection,
+    X86,
+    X86_64,
+};
+use object::{Object, ObjectSection, ObjectSegment, SymbolMap, SymbolMapEntry};
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs,
+    os::unix::ffi::OsStrExt,
+    rc::Rc,
+};
+
+/// Guard against cyclic or corrupt stacks
+const MAX_FRAMES: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct StackFrame {
+    pub ip: RemoteCodePtr,
+    pub sp: RemotePtr<u8>,
+    /// From the ELF symbol table
+    pub function_name: Option<String>,
+    /// From the DWARF line tables, if the file has debug info
+    pub source_location: Option<(OsString, u32)>,
+}
+
+/// Return the call stack of `task`, innermost frame first.
+///
+/// Needs the task's registers and memory to be available i.e. the task must be stopped.
+pub fn stack_frames(task: &mut dyn Task) -> Vec<StackFrame> {
+    let arch = task.arch();
+    let maps = match task.memory_maps() {
+        Ok(it) => it.filter_map(Result::ok).collect(),
+        Err(e) => {
+            log!(LogDebug, "Could not read maps of {}: {:?}", task.tid, e);
+            Vec::new()
+        }
+    };
+    let mut modules = Modules {
+        maps,
+        files: HashMap::new(),
+    };
+
+    let regs = task.regs_ref();
+    let start = UnwindRegs {
+        ip: regs.ip().as_usize() as u64,
+        sp: regs.sp().as_usize() as u64,
+        bp: regs.bp() as u64,
+    };
+
+    let mut read_mem = |addr| read_word(task, arch, addr);
+    unwind(&mut modules, start, |modules, lookup_ip, cur| {
+        modules
+            .lookup(lookup_ip)
+            .and_then(|(elf, svma)| cfi_step(&mut read_mem, arch, elf, svma, cur))
+            .or_else(|| frame_pointer_step(&mut read_mem, arch, cur))
+    })
+}
+
+/// Walk the stack from `start`. `step` unwinds a single frame given the (adjusted) ip to look
+/// up the unwind info for.
+fn unwind<F>(modules: &mut Modules, start: UnwindRegs, mut step: F) -> Vec<StackFrame>
+where
+    F: FnMut(&mut Modules, u64, &UnwindRegs) -> Option<UnwindRegs>,
+{
+    let mut cur = start;
+    let mut frames = Vec::new();
+    while frames.len() < MAX_FRAMES && cur.ip != 0 {
+        // Return addresses point just after the call instruction, which may be the first
+        // instruction of a different function (or line). Look up the call instruction itself.
+        let lookup_ip = if frames.is_empty() {
+            cur.ip
+        } else {
+            cur.ip - 1
+        };
+        let (function_name, source_location) = modules.symbolize(lookup_ip);
+        frames.push(StackFrame {
+            ip: RemoteCodePtr::from_val(cur.ip as usize),
+            sp: RemotePtr::from(cur.sp as usize),
+            function_name,
+            source_location,
+        });
+
+        match step(modules, lookup_ip, &cur) {
+            // The stack pointer must strictly increase as we unwind or we may loop forever
+            Some(next) if next.sp > cur.sp => cur = next,
+            _ => break,
+        }
+    }
+
+    frames
+}
+
+/// The registers we track while unwinding. Everything else is assumed to be unchanged between
+/// frames, which is good enough for finding the CFA and return address in practice.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct UnwindRegs {
+    ip: u64,
+    sp: u64,
+    bp: u64,
+}
+
+/// An owned `object::SymbolMapName`
+struct ElfSymbol {
+    address: u64,
+    name: String,
+}
+
+impl SymbolMapEntry for ElfSymbol {
+    fn address(&self) -> u64 {
+        self.address
+    }
+}
+
+/// What we need of an ELF file for unwinding and symbolizing. The file is parsed only once, in
+/// `ElfFile::load()`.
+struct ElfFile {
+    /// (file offset, file size, virtual address) of each segment
+    segments: Vec<(u64, u64, u64)>,
+    symbols: SymbolMap<ElfSymbol>,
+    /// The `.eh_frame` section and the base addresses needed to decode it
+    eh_frame: Option<(EhFrame<EndianRcSlice<RunTimeEndian>>, BaseAddresses)>,
+    dwarf: Option<addr2line::Context<EndianRcSlice<RunTimeEndian>>>,
+}
+
+impl ElfFile {
+    fn load(path: &OsStr) -> Option<ElfFile> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                log!(LogDebug, "Could not read {:?}: {:?}", path, e);
+                return None;
+            }
+        };
+        let file = match object::File::parse(&data) {
+            Ok(file) => file,
+            Err(e) => {
+                log!(LogDebug, "Could not parse {:?}: {:?}", path, e);
+                return None;
+            }
+        };
+        let segments = file
+            .segments()
+            .map(|segment| {
+                let (offset, size) = segment.file_range();
+                (offset, size, segment.address())
+            })
+            .collect();
+        let symbols = SymbolMap::new(
+            file.symbol_map()
+                .symbols()
+                .iter()
+                .map(|s| ElfSymbol {
+                    address: s.address(),
+                    name: s.name().to_owned(),
+                })
+                .collect(),
+        );
+        let endian = if file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let eh_frame = file.section_by_name(".eh_frame").and_then(|section| {
+            let section_data = EndianRcSlice::new(Rc::from(section.data().ok()?), endian);
+            let mut bases = BaseAddresses::default().set_eh_frame(section.address());
+            if let Some(text) = file.section_by_name(".text") {
+                bases = bases.set_text(text.address());
+            }
+            Some((EhFrame::from(section_data), bases))
+        });
+        let dwarf = addr2line::Context::new(&file).ok();
+        Some(ElfFile {
+            segments,
+            symbols,
+            eh_frame,
+            dwarf,
+        })
+    }
+}
+
+/// Lazily loaded ELF files backing the task's mappings. Lives for a single `stack_frames()` call.
+struct Modules {
+    maps: Vec<KernelMapping>,
+    files: HashMap<OsString, Option<ElfFile>>,
+}
+
+impl Modules {
+    /// Return the ELF file `ip` lies in and `ip` translated to that file's (unrelocated)
+    /// virtual addresses.
+    fn lookup(&mut self, ip: u64) -> Option<(&ElfFile, u64)> {
+        let km = self
+            .maps
+            .iter()
+            .find(|km| km.start().as_usize() as u64 <= ip && ip < km.end().as_usize() as u64)?;
+        let fsname = km.fsname();
+        // Anonymous mappings and pseudo-files like [vdso], [stack] etc.
+        if fsname.is_empty() || fsname.as_bytes()[0] == b'[' {
+            return None;
+        }
+
+        let offset = ip - km.start().as_usize() as u64 + km.file_offset_bytes();
+        let elf = self
+            .files
+            .entry(fsname.to_owned())
+            .or_insert_with(|| ElfFile::load(fsname))
+            .as_ref()?;
+        let svma = elf
+            .segments
+            .iter()
+            .find_map(|&(segment_offset, segment_size, address)| {
+                if segment_offset <= offset && offset < segment_offset + segment_size {
+                    Some(offset - segment_offset + address)
+                } else {
+                    None
+                }
+            })?;
+        Some((elf, svma))
+    }
+
+    fn symbolize(&mut self, ip: u64) -> (Option<String>, Option<(OsString, u32)>) {
+        let (elf, svma) = match self.lookup(ip) {
+            Some(found) => found,
+            None => return (None, None),
+        };
+        let function_name = elf.symbols.get(svma).map(|s| s.name.clone());
+        let source_location = elf
+            .dwarf
+            .as_ref()
+            .and_then(|dwarf| dwarf.find_location(svma).ok().flatten())
+            .and_then(|loc| Some((OsString::from(loc.file?), loc.line?)));
+        (function_name, source_location)
+    }
+}
+
+/// Unwind one frame using the `.eh_frame` CFI for `svma` in `elf`. `read_mem` reads a word of
+/// the task's memory.
+fn cfi_step(
+    read_mem: &mut dyn FnMut(u64) -> Option<u64>,
+    arch: SupportedArch,
+    elf: &ElfFile,
+    svma: u64,
+    cur: &UnwindRegs,
+) -> Option<UnwindRegs> {
+    let (eh_frame, bases) = elf.eh_frame.as_ref()?;
+    let mut ctx = UninitializedUnwindContext::new();
+    let row = eh_frame
+        .unwind_info_for_address(bases, &mut ctx, svma, EhFrame::cie_from_offset)
+        .ok()?;
+
+    let (sp_reg, bp_reg, ra_reg) = match arch {
+        SupportedArch::X86 => (X86::ESP, X86::EBP, X86::RA),
+        SupportedArch::X64 => (X86_64::RSP, X86_64::RBP, X86_64::RA),
+    };
+    let cfa = match *row.cfa() {
+        CfaRule::RegisterAndOffset { register, offset } if register == sp_reg => {
+            cur.sp.wrapping_add(offset as u64)
+        }
+        CfaRule::RegisterAndOffset { register, offset } if register == bp_reg => {
+            cur.bp.wrapping_add(offset as u64)
+        }
+        // @TODO DWARF expressions and CFAs based on other registers are not supported
+        _ => return None,
+    };
+
+    let ip = apply_register_rule(read_mem, cfa, row.register(ra_reg), None)?;
+    let bp = apply_register_rule(read_mem, cfa, row.register(bp_reg), Some(cur.bp))?;
+    Some(UnwindRegs { ip, sp: cfa, bp })
+}
+
+/// `current` is the register's value in the frame being unwound, if known.
+fn apply_register_rule<R: Reader>(
+    read_mem: &mut dyn FnMut(u64) -> Option<u64>,
+    cfa: u64,
+    rule: RegisterRule<R>,
+    current: Option<u64>,
+) -> Option<u64> {
+    match rule {
+        RegisterRule::Undefined | RegisterRule::SameValue => current,
+        RegisterRule::Offset(offset) => read_mem(cfa.wrapping_add(offset as u64)),
+        RegisterRule::ValOffset(offset) => Some(cfa.wrapping_add(offset as u64)),
+        // @TODO Register and expression rules are not supported
+        _ => None,
+    }
+}
+
+/// Unwind one frame assuming the code maintains a frame pointer.
+fn frame_pointer_step(
+    read_mem: &mut dyn FnMut(u64) -> Option<u64>,
+    arch: SupportedArch,
+    cur: &UnwindRegs,
+) -> Option<UnwindRegs> {
+    let word = word_size(arch) as u64;
+    if cur.bp == 0 || cur.bp % word != 0 || cur.bp < cur.sp {
+        return None;
+    }
+    // [bp] is the caller's bp and [bp + word] is the return address
+    let bp = read_mem(cur.bp)?;
+    let ip = read_mem(cur.bp.checked_add(word)?)?;
+    Some(UnwindRegs {
+        ip,
+        sp: cur.bp.checked_add(2 * word)?,
+        bp,
+    })
+}
+
+/// Like `read_typed()` but returns None instead of aborting if the memory is not readable.
+fn read_word(task: &mut dyn Task, arch: SupportedArch, addr: u64) -> Option<u64> {
+    let len = word_size(arch);
+    let mut buf = [0u8; 8];
+    match task.read_bytes_fallible(RemotePtr::from(addr as usize), &mut buf[0..len]) {
+        Ok(nread) if nread == len => Some(u64::from_le_bytes(buf)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::address_space::kernel_map_iterator::MemoryMapIterator;
+    use nix::unistd::getpid;
+
+    /// Read a word of this process' memory
+    fn read_own_word(addr: u64) -> Option<u64> {
+        Some(unsafe { std::ptr::read(addr as *const u64) })
+    }
+
+    fn own_modules() -> Modules {
+        Modules {
+            maps: MemoryMapIterator::new_from_tid(getpid().as_raw())
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect(),
+            files: HashMap::new(),
+        }
+    }
+
+    #[inline(never)]
+    fn unwind_target() -> u64 {
+        42
+    }
+
+    #[test]
+    fn frame_pointer_step_test() {
+        // A frame record as pushed by `push rbp; mov rbp, rsp`: the caller's bp, then the
+        // return address
+        let stack: [u64; 4] = [0, 0x5000, 0x1234, 0];
+        let sp = &stack[0] as *const u64 as u64;
+        let bp = &stack[1] as *const u64 as u64;
+        let cur = UnwindRegs { ip: 1, sp, bp };
+        assert_eq!(
+            Some(UnwindRegs {
+                ip: 0x1234,
+                sp: bp + 16,
+                bp: 0x5000
+            }),
+            frame_pointer_step(&mut read_own_word, SupportedArch::X64, &cur)
+        );
+
+        for &bad_bp in &[0, bp + 1, sp - 8] {
+            let cur = UnwindRegs { bp: bad_bp, ..cur };
+            assert_eq!(
+                None,
+                frame_pointer_step(&mut read_own_word, SupportedArch::X64, &cur)
+            );
+        }
+    }
+
+    #[test]
+    fn unwind_requires_increasing_sp_test() {
+        let mut modules = Modules {
+            maps: Vec::new(),
+            files: HashMap::new(),
+        };
+        let start = UnwindRegs {
+            ip: 0x1000,
+            sp: 0x2000,
+            bp: 0,
+        };
+
+        for &delta in &[0i64, -8] {
+            let frames = unwind(&mut modules, start.clone(), |_, _, cur| {
+                Some(UnwindRegs {
+                    ip: cur.ip + 1,
+                    sp: (cur.sp as i64 + delta) as u64,
+                    bp: 0,
+                })
+            });
+            assert_eq!(1, frames.len());
+        }
+
+        let frames = unwind(&mut modules, start.clone(), |_, _, cur| {
+            if cur.sp < 0x2020 {
+                Some(UnwindRegs {
+                    ip: cur.ip + 1,
+                    sp: cur.sp + 8,
+                    bp: 0,
+                })
+            } else {
+                None
+            }
+        });
+        assert_eq!(5, frames.len());
+        assert_eq!(0x1004, frames[4].ip.as_usize());
+        assert_eq!(0x2020, frames[4].sp.as_usize());
+
+        let frames = unwind(&mut modules, start, |_, _, cur| {
+            Some(UnwindRegs {
+                ip: cur.ip + 1,
+                sp: cur.sp + 8,
+                bp: 0,
+            })
+        });
+        assert_eq!(MAX_FRAMES, frames.len());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn cfi_step_test() {
+        let mut modules = own_modules();
+        let entry = unwind_target as usize as u64;
+        assert_eq!(42, unwind_target());
+        let (function_name, _) = modules.symbolize(entry);
+        assert!(function_name.unwrap().contains("unwind_target"));
+
+        // At the first instruction of a function the CFA is sp + 8 and the return address is
+        // at sp. bp hasn't been touched yet.
+        let stack: [u64; 2] = [0x1234, 0];
+        let sp = stack.as_ptr() as u64;
+        let (elf, svma) = modules.lookup(entry).unwrap();
+        let cur = UnwindRegs {
+            ip: entry,
+            sp,
+            bp: 7,
+        };
+        assert_eq!(
+            Some(UnwindRegs {
+                ip: 0x1234,
+                sp: sp + 8,
+                bp: 7
+            }),
+            cfi_step(&mut read_own_word, SupportedArch::X64, elf, svma, &cur)
+        );
+    }
+}
@@ -129,6 +129,7 @@ use crate::{
     },
     wait_status::WaitStatus,
 };
+use bytemuck::{bytes_of, bytes_of_mut, Pod};
 use file_monitor::LazyOffset;
 use libc::{
     pid_t,
@@ -560,6 +561,18 @@ pub fn read_val_with_default_mem<D: Default>(
     v
 }
 
+/// NOT Forwarded method definition
+///
+/// Like `read_val_mem()` but the `Pod` bound guarantees at compile time that
+/// any bytes read from the tracee form a valid `T`, so no unsafe code is
+/// needed at the call site or here.
+/// DIFF NOTE: Not present in rr
+pub fn read_typed<T: Pod>(task: &mut dyn Task, child_addr: RemotePtr<T>) -> T {
+    let mut v = T::zeroed();
+    task.read_bytes_helper(RemotePtr::cast(child_addr), bytes_of_mut(&mut v), None);
+    v
+}
+
 /// NOT Forwarded method definition
 ///
 /// Read `count` values from `child_addr`.
@@ -652,6 +665,19 @@ pub fn write_val_mem_with_flags<D: 'static>(
     task.write_bytes_helper(RemotePtr::cast(child_addr), data_slice, ok, flags);
 }
 
+/// NOT Forwarded method definition
+///
+/// Write `val` to `child_addr`. See `read_typed()`.
+/// DIFF NOTE: Not present in rr
+pub fn write_typed<T: Pod>(task: &mut dyn Task, child_addr: RemotePtr<T>, val: T) {
+    task.write_bytes_helper(
+        RemotePtr::cast(child_addr),
+        bytes_of(&val),
+        None,
+        WriteFlags::empty(),
+    );
+}
+
 /// NOT Forwarded method definition
 ///
 /// Write array of `val`s to `child_addr`.
@@ -1452,6 +1478,7 @@ pub(super) fn post_exec_for_exe_common<T: Task>(t: &mut T, exe_file: &OsStr) {
     t.fds = Some(t.fd_table_shr_ptr().clone_into_task(t));
     let prname = prname_from_exe_image(t.vm().exe_image());
     t.prname = prname.to_owned();
+    t.thread_name_cache = None;
 }
 
 fn prname_from_exe_image(exe_image: &OsStr) -> &OsStr {
@@ -2300,6 +2327,7 @@ pub(super) fn reset_syscallbuf_common<T: Task>(t: &mut T) {
 pub(in super::super) fn copy_state(t: &mut dyn Task, state: &CapturedState) {
     t.set_regs(&state.regs);
     t.set_extra_regs(&state.extra_regs);
+    t.write_debug_registers(&state.debug_regs);
     {
         let mut remote = AutoRemoteSyscalls::new(t);
         {
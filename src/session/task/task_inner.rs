@@ -37,6 +37,7 @@ use crate::{
         signal::siginfo_t,
     },
     cpuid_bug_detector::CPUIDBugDetector,
+    debug_registers::DebugRegisters,
     extra_registers::{ExtraRegisters, Format},
     fd_table::{FdTable, FdTableSharedPtr},
     file_monitor::{
@@ -61,13 +62,20 @@ use crate::{
     session::{
         address_space::{
             address_space::{AddressSpace, AddressSpaceSharedPtr},
+            kernel_map_iterator::MemoryMapIterator,
             MappingFlags,
             Traced,
             WatchConfig,
             WatchType,
         },
         session_inner::SessionInner,
-        task::{task_common::set_thread_area_core, Task, TaskSharedPtr, TaskSharedWeakPtr},
+        task::{
+            fd_info::{read_fd_table, FileDescriptorInfo},
+            task_common::set_thread_area_core,
+            Task,
+            TaskSharedPtr,
+            TaskSharedWeakPtr,
+        },
         Session,
         SessionSharedPtr,
         SessionSharedWeakPtr,
@@ -138,7 +146,11 @@ use owning_ref::OwningHandle;
 use std::{
     cell::{Cell, Ref, RefCell},
     cmp::{max, min},
+    collections::HashMap,
     ffi::{c_void, CStr, CString, OsStr, OsString},
+    fs::File,
+    io,
+    io::Read,
     mem::{size_of, size_of_val},
     ops::Deref,
     os::{raw::c_int, unix::ffi::OsStrExt},
@@ -427,6 +439,9 @@ pub struct TaskInner {
     /// DIFF NOTE: Not present in rr
     /// Slightly different from `serial` which is liable to change on an exec
     pub stable_serial: u32,
+    /// DIFF NOTE: Not present in rr
+    /// Last name read from /proc/{tid}/comm. See `thread_name()`
+    pub(in super::super) thread_name_cache: Option<OsString>,
 }
 
 pub type DebugRegs = Vec<WatchConfig>;
@@ -442,6 +457,8 @@ pub struct CapturedState {
     pub ticks: Ticks,
     pub regs: Registers,
     pub extra_regs: ExtraRegisters,
+    /// DIFF NOTE: Not present in rr
+    pub debug_regs: DebugRegisters,
     pub prname: OsString,
     pub thread_areas: Vec<user_desc>,
     pub syscallbuf_child: RemotePtr<syscallbuf_hdr>,
@@ -897,12 +914,24 @@ impl TaskInner {
     }
 
     /// Set the tracee's registers to `regs`. Lazy.
+    /// DIFF NOTE: Setting registers identical to the (non-dirty) cached ones
+    /// does not mark them dirty so the PTRACE_SETREGS in flush_regs() is
+    /// skipped.
     pub fn set_regs(&mut self, regs: &Registers) {
         ed_assert!(self, self.is_stopped);
+        if !self.registers_dirty && self.registers.identical(regs) {
+            return;
+        }
         self.registers = regs.clone();
         self.registers_dirty = true;
     }
 
+    /// Do the cached registers need to be written back to the tracee?
+    /// DIFF NOTE: Not present in rr
+    pub fn registers_dirty(&self) -> bool {
+        self.registers_dirty
+    }
+
     /// Ensure registers are flushed back to the underlying task.
     pub fn flush_regs(&mut self) {
         if self.registers_dirty {
@@ -1036,6 +1065,73 @@ impl TaskInner {
         errno() == 0 || errno() == ESRCH
     }
 
+    /// Read all the debug registers DR0-DR7 of this task.
+    /// DIFF NOTE: Not present in rr
+    pub fn read_debug_registers(&self) -> DebugRegisters {
+        let mut regs = DebugRegisters::default();
+        for i in 0..NUM_X86_DEBUG_REGS {
+            // DR4 and DR5 are reserved
+            if i != 4 && i != 5 {
+                regs.set(i as u8, self.get_debug_reg(i));
+            }
+        }
+        regs
+    }
+
+    /// Write DR0-DR3, DR6 and DR7 of this task. DR7 is cleared first so that no
+    /// breakpoint is briefly enabled with a stale address.
+    /// DIFF NOTE: Not present in rr
+    pub fn write_debug_registers(&self, regs: &DebugRegisters) -> bool {
+        if !self.set_debug_reg(7, 0) {
+            return false;
+        }
+        for i in [0u8, 1, 2, 3, 6].iter() {
+            if !self.set_debug_reg(*i as usize, regs.get(*i)) {
+                return false;
+            }
+        }
+        self.set_debug_reg(7, regs.get(7))
+    }
+
+    /// Read the current memory mappings of this task from /proc/{tid}/maps.
+    /// The result is not cached: each call re-reads the file, so it reflects
+    /// the kernel's view at the time of iteration rather than our
+    /// `AddressSpace` bookkeeping. Malformed lines are reported as
+    /// `io::ErrorKind::InvalidData` errors.
+    /// DIFF NOTE: Not present in rr
+    pub fn memory_maps(&self) -> io::Result<MemoryMapIterator> {
+        MemoryMapIterator::new_from_tid(self.tid)
+    }
+
+    /// Snapshot of this task's open file descriptors, read from /proc/{tid}/fd and
+    /// /proc/{tid}/fdinfo. Does not use ptrace. Returns an empty map if the fd table
+    /// can't be read e.g. because the task has exited.
+    /// DIFF NOTE: Not present in rr
+    pub fn file_descriptors(&self) -> HashMap<i32, FileDescriptorInfo> {
+        read_fd_table(self.tid)
+    }
+
+    /// The thread name as set by e.g. prctl(PR_SET_NAME), read from /proc/{tid}/comm.
+    /// The result is cached until the task execs or changes its name.
+    /// Returns None if the name could not be read.
+    /// DIFF NOTE: Not present in rr
+    pub fn thread_name(&mut self) -> Option<OsString> {
+        if self.thread_name_cache.is_none() {
+            // The kernel limits names to 16 bytes (TASK_COMM_LEN) including the terminating nul,
+            // which is printed as a newline here.
+            let mut buf = [0u8; 16];
+            let nread = File::open(format!("/proc/{}/comm", self.tid))
+                .and_then(|mut f| f.read(&mut buf))
+                .ok()?;
+            let mut name = &buf[0..nread];
+            if name.last() == Some(&b'\n') {
+                name = &name[0..nread - 1];
+            }
+            self.thread_name_cache = Some(OsStr::from_bytes(name).to_owned());
+        }
+        self.thread_name_cache.clone()
+    }
+
     /// Set the thread area at index `idx` to desc and reflect this
     /// into the OS task. Returns 0 on success, errno otherwise
     /// DIFF NOTE: idx is a i32 in rr
@@ -1401,6 +1497,7 @@ impl TaskInner {
             pending_siginfo: Default::default(),
             weak_self: Weak::new(),
             stopping_breakpoint_table: Default::default(),
+            thread_name_cache: None,
         }
     }
 
@@ -1417,6 +1514,7 @@ impl TaskInner {
             serial: self.serial,
             regs: self.regs_ref().clone(),
             extra_regs: self.extra_regs_ref().clone(),
+            debug_regs: self.read_debug_registers(),
             prname: self.prname.clone(),
             thread_areas: self.thread_areas_.clone(),
             desched_fd_child: self.desched_fd_child,
@@ -2101,3 +2199,33 @@ fn setup_preload_thread_locals_from_clone_arch<Arch: Architecture>(
         None => (),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        session::{test_util::start_recording, Session},
+        trace::test_util::TempTraceDir,
+    };
+
+    #[test]
+    fn set_regs_dirty_test() {
+        let dir = TempTraceDir::new("set-regs");
+        let session = start_recording(&dir, &["/bin/true"]);
+        let task = session.tasks().values().next().unwrap().clone();
+        let mut t = task.borrow_mut();
+        t.flush_regs();
+        assert!(!t.registers_dirty());
+
+        let regs = t.regs_ref().clone();
+        t.set_regs(&regs);
+        assert!(!t.registers_dirty());
+
+        let mut changed = regs.clone();
+        changed.set_syscall_result(regs.syscall_result().wrapping_add(1));
+        t.set_regs(&changed);
+        assert!(t.registers_dirty());
+        // Once dirty the cached registers stay dirty until flushed
+        t.set_regs(&changed);
+        assert!(t.registers_dirty());
+    }
+}
@@ -0,0 +1,37 @@
+//! Helpers for tests that need real tracees.
+
+use crate::{
+    commands::{rd_options::RdOptions, record_command::RecordCommand},
+    perf_counters::init_pmu,
+    session::{
+        record_session::{RecordResult, RecordSession},
+        SessionSharedPtr,
+    },
+    trace::test_util::TempTraceDir,
+};
+use structopt::StructOpt;
+
+/// Start recording `args` to a trace in `dir`, as `rd record -n` would. The
+/// syscall buffer is disabled so that every syscall of the tracees stops in rd.
+///
+/// The initial tracee is left in the SIGSTOP it raises before its exec.
+pub fn start_recording(dir: &TempTraceDir, args: &[&str]) -> SessionSharedPtr {
+    init_pmu();
+    let trace_dir = dir.join("trace").into_os_string().into_string().unwrap();
+    let mut rd_args = vec!["rd", "record", "-n", "-o", trace_dir.as_str(), "--"];
+    rd_args.extend_from_slice(args);
+    RecordSession::create(&RecordCommand::new(&RdOptions::from_iter(rd_args)))
+}
+
+/// Record until all the tracees have exited and close the trace.
+/// Returns the result of the last `record_step()`.
+pub fn finish_recording(session: &SessionSharedPtr) -> RecordResult {
+    let rec_session = session.as_record().unwrap();
+    loop {
+        let step_result = rec_session.record_step();
+        if step_result != RecordResult::StepContinue {
+            rec_session.terminate_recording();
+            return step_result;
+        }
+    }
+}
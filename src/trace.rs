@@ -1,7 +1,11 @@
 pub mod compressed_reader;
 pub mod compressed_writer;
 pub mod trace_frame;
+pub mod trace_migration;
 pub mod trace_reader;
 pub mod trace_stream;
 pub mod trace_task_event;
 pub mod trace_writer;
+
+#[cfg(test)]
+pub mod test_util;
@@ -1,13 +1,20 @@
 use crate::{
     scoped_fd::{ScopedFd, ScopedFdSharedPtr},
-    trace::compressed_writer::BlockHeader,
+    trace::compressed_writer::{
+        block_checksum,
+        BlockHeader,
+        Compression,
+        StreamPosition,
+        BLOCK_CHECKSUM_SIZE,
+        ZSTD_EXTENSION,
+    },
     util::read_to_end,
 };
 use brotli_sys::{BrotliDecoderDecompress, BROTLI_DECODER_RESULT_SUCCESS};
 use nix::{
     fcntl::OFlag,
     sys::uio::pread,
-    unistd::{lseek, Whence},
+    unistd::{dup, lseek, Whence},
 };
 use std::{
     cell::RefCell,
@@ -17,13 +24,16 @@ use std::{
     io,
     io::{BufRead, ErrorKind, Read},
     mem::{size_of, transmute},
+    path::Path,
     ptr::copy_nonoverlapping,
     rc::Rc,
+    sync::mpsc::{sync_channel, Receiver},
+    thread,
 };
 
 /// CompressedReader opens an input file written by CompressedWriter
-/// and reads data from it. Currently data is decompressed by the thread that
-/// calls read().
+/// and reads data from it. Data is decompressed by the thread that calls
+/// read() unless prefetching is enabled, see `set_prefetch_depth()`.
 #[derive(Clone)]
 pub struct CompressedReader {
     /// Our fd might be the dup of another fd, so we can't rely on its current file position.
@@ -33,6 +43,10 @@ pub struct CompressedReader {
     eof: bool,
     buffer: Vec<u8>,
     buffer_read_pos: usize,
+    /// DIFF NOTE: Not present in rr. The level of `Compression::Zstd` is unused.
+    compression: Compression,
+    /// DIFF NOTE: Not present in rr
+    prefetcher: Prefetcher,
     // Note that the struct members for saving state are not here as we have a separate struct
     // to handle that
 }
@@ -72,6 +86,17 @@ impl Read for CompressedReader {
     }
 }
 
+/// A block whose stored checksum does not match its compressed data.
+/// See `CompressedReader::checksum_mismatches()`
+/// DIFF NOTE: Not present in rr
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BlockChecksumMismatch {
+    /// Index of the block in the file, starting from 0
+    pub block: u64,
+    pub expected: [u8; BLOCK_CHECKSUM_SIZE],
+    pub actual: [u8; BLOCK_CHECKSUM_SIZE],
+}
+
 pub struct CompressedReaderState {
     saved_fd_offset: u64,
     saved_buffer: Vec<u8>,
@@ -95,8 +120,8 @@ impl Drop for CompressedReader {
 }
 
 /// CompressedReader opens an input file written by CompressedWriter
-/// and reads data from it. Currently data is decompressed by the thread that
-/// calls read().
+/// and reads data from it. Data is decompressed by the thread that calls
+/// read() unless prefetching is enabled, see `set_prefetch_depth()`.
 impl CompressedReader {
     pub fn new(filename: &OsStr) -> CompressedReader {
         let fd = ScopedFd::open_path(
@@ -119,12 +144,19 @@ impl CompressedReader {
             }
         }
         let buffer_read_pos = 0;
+        let compression = if Path::new(filename).extension() == Some(OsStr::new(ZSTD_EXTENSION)) {
+            Compression::Zstd(0)
+        } else {
+            Compression::Brotli
+        };
         CompressedReader {
             fd_offset: 0,
             fd: Some(Rc::new(RefCell::new(fd))),
             eof,
             buffer: Vec::new(),
             buffer_read_pos,
+            compression,
+            prefetcher: Prefetcher::default(),
         }
     }
 
@@ -153,14 +185,44 @@ impl CompressedReader {
         Ok(())
     }
 
-    pub fn rewind(&mut self) {
-        self.fd_offset = 0;
-        self.buffer_read_pos = 0;
+    /// DIFF NOTE: rr sets `eof` to false here, which makes an empty stream look
+    /// non-empty after a rewind. We recompute it like `new()` does.
+    pub fn rewind(&mut self) -> io::Result<()> {
+        self.seek(StreamPosition::default())
+    }
+    /// Move the read position to `pos`. See `CompressedWriter::stream_position()`
+    /// DIFF NOTE: Not present in rr
+    pub fn seek(&mut self, pos: StreamPosition) -> io::Result<()> {
+        self.fd_offset = pos.block_offset;
         self.buffer.clear();
-        self.eof = false;
+        self.buffer_read_pos = 0;
+        let ch: u8 = 0;
+        self.eof = match pread(
+            self.fd.as_ref().unwrap().borrow().as_raw(),
+            &mut ch.to_le_bytes(),
+            self.fd_offset.try_into().unwrap(),
+        ) {
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(e) => return Err(io::Error::new(ErrorKind::Other, e)),
+        };
+        if !self.eof {
+            self.refill_buffer()?;
+        }
+
+        if pos.offset_in_block as usize > self.buffer.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid position {:?} in CompressedReader", pos),
+            ));
+        }
+        self.buffer_read_pos = pos.offset_in_block as usize;
+        Ok(())
     }
+
     pub fn close(&mut self) {
         self.fd.take();
+        self.prefetcher.receiver.take();
     }
 
     /// Get the current state of the CompressedReader.
@@ -197,11 +259,53 @@ impl CompressedReader {
         )? {
             let header: BlockHeader = unsafe { transmute(header_arr.clone()) };
             uncompressed_bytes += header.uncompressed_length as u64;
-            offset += header.compressed_length as u64;
+            offset += header.stored_len() as u64;
         }
         Ok(uncompressed_bytes)
     }
 
+    /// Walks all the blocks of the file and checks the checksum of every block
+    /// that has one. Like `uncompressed_bytes()` this is independent of what's
+    /// actually been read.
+    /// DIFF NOTE: Not present in rr
+    pub fn checksum_mismatches(&self) -> io::Result<Vec<BlockChecksumMismatch>> {
+        let fd = self.fd.as_ref().unwrap().borrow();
+        let mut offset: u64 = 0;
+        let mut block: u64 = 0;
+        let mut mismatches = Vec::new();
+        let mut header_arr = [0u8; size_of::<BlockHeader>()];
+        while read_all(&fd, &mut header_arr, &mut offset)? {
+            let header: BlockHeader = unsafe { transmute(header_arr.clone()) };
+            if !header.has_checksum() {
+                offset += header.compressed_len() as u64;
+                block += 1;
+                continue;
+            }
+
+            let mut stored = vec![0u8; header.stored_len()];
+            if !read_all(&fd, &mut stored, &mut offset)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("Block {} is truncated", block),
+                ));
+            }
+            let (compressed, checksum) = stored.split_at(header.compressed_len());
+            let actual = block_checksum(compressed);
+            if actual != checksum {
+                let mut expected = [0u8; BLOCK_CHECKSUM_SIZE];
+                expected.copy_from_slice(checksum);
+                mismatches.push(BlockChecksumMismatch {
+                    block,
+                    expected,
+                    actual,
+                });
+            }
+            block += 1;
+        }
+
+        Ok(mismatches)
+    }
+
     pub fn compressed_bytes(&self) -> io::Result<u64> {
         let result = lseek(
             self.fd.as_ref().unwrap().borrow().as_raw(),
@@ -214,69 +318,208 @@ impl CompressedReader {
         }
     }
 
+    /// Prefetch up to `depth` blocks ahead of the read position on a
+    /// background thread. Blocks are still handed out strictly in file order,
+    /// so reads return the same data as without prefetching. A `depth` of 0
+    /// (the default) disables prefetching.
+    /// DIFF NOTE: Not present in rr
+    pub fn set_prefetch_depth(&mut self, depth: usize) {
+        self.prefetcher = Prefetcher {
+            depth,
+            ..Default::default()
+        };
+    }
+
     fn refill_buffer(&mut self) -> io::Result<()> {
-        let mut header_vec: Vec<u8> = Vec::with_capacity(size_of::<BlockHeader>());
-        header_vec.resize(size_of::<BlockHeader>(), 0u8);
-        if false
-            == read_all(
-                &self.fd.as_ref().unwrap().borrow(),
-                &mut header_vec,
-                &mut self.fd_offset,
-            )?
-        {
-            return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "Unexpected EOF encountered while doing read_all() on header in CompressedReader",
-            ));
+        let fd = self.fd.as_ref().unwrap().borrow();
+        let block = if self.prefetcher.depth > 0 {
+            self.prefetcher
+                .next_block(&fd, self.fd_offset, self.compression)?
+        } else {
+            read_block(&fd, self.fd_offset, self.compression)?
+        };
+        drop(fd);
+
+        self.fd_offset = block.next_offset;
+        self.eof = block.eof;
+        self.buffer = block.data;
+        self.buffer_read_pos = 0;
+        Ok(())
+    }
+}
+
+/// A decompressed block and what follows it in the file.
+/// DIFF NOTE: Not present in rr
+struct DecodedBlock {
+    data: Vec<u8>,
+    /// Offset of the header of the next block
+    next_offset: u64,
+    /// There is no next block
+    eof: bool,
+}
+
+/// Reads and decompresses the blocks that follow the read position on a
+/// background thread. See `CompressedReader::set_prefetch_depth()`
+/// DIFF NOTE: Not present in rr
+#[derive(Default)]
+struct Prefetcher {
+    /// Max number of decompressed blocks queued up
+    depth: usize,
+    /// Offset of the block that `receiver` will produce next
+    next_offset: u64,
+    /// `None` if there is no background thread. Dropping the receiver makes
+    /// the thread exit.
+    receiver: Option<Receiver<io::Result<DecodedBlock>>>,
+}
+
+impl Clone for Prefetcher {
+    /// The clone starts its own thread when it is first used, as it may be
+    /// read from a different position.
+    fn clone(&self) -> Self {
+        Prefetcher {
+            depth: self.depth,
+            ..Default::default()
         }
+    }
+}
 
-        let mut header: BlockHeader = Default::default();
-        unsafe {
-            copy_nonoverlapping(
-                header_vec.as_ptr(),
-                &raw mut header as *mut u8,
-                size_of::<BlockHeader>(),
-            );
+impl Prefetcher {
+    /// The block at `offset`. If the background thread is not producing the
+    /// block at `offset` next (e.g. after a seek) it is replaced by a new one
+    /// that starts at `offset`.
+    fn next_block(
+        &mut self,
+        fd: &ScopedFd,
+        offset: u64,
+        compression: Compression,
+    ) -> io::Result<DecodedBlock> {
+        if self.receiver.is_none() || self.next_offset != offset {
+            self.start(fd, offset, compression)?;
         }
 
-        let mut compressed_buf: Vec<u8> = Vec::with_capacity(header.compressed_length as usize);
-        compressed_buf.resize(header.compressed_length as usize, 0);
-        if false
-            == read_all(
-                &self.fd.as_ref().unwrap().borrow(),
-                &mut compressed_buf,
-                &mut self.fd_offset,
-            )?
-        {
-            return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "Unexpected EOF encountered while doing read_all() on compressed data in CompressedReader",
-            ));
+        let result = match self.receiver.as_ref().unwrap().recv() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                ErrorKind::Other,
+                "CompressedReader prefetch thread exited unexpectedly",
+            )),
+        };
+        match &result {
+            Ok(block) if !block.eof => self.next_offset = block.next_offset,
+            // The thread has exited or is about to
+            _ => self.receiver = None,
         }
+        result
+    }
 
-        let ch: u8 = 0;
-        self.eof = match pread(
-            self.fd.as_ref().unwrap().borrow().as_raw(),
-            &mut ch.to_le_bytes(),
-            // On x86 off_t is an i32 and on x86_64 off_t is an i64
-            self.fd_offset.try_into().unwrap(),
-        ) {
-            Ok(0) => true,
-            Ok(_) => false,
+    fn start(&mut self, fd: &ScopedFd, offset: u64, compression: Compression) -> io::Result<()> {
+        // The thread gets its own fd so it is unaffected by `fd` being closed
+        let thread_fd = match dup(fd.as_raw()) {
+            Ok(raw) => ScopedFd::from_raw(raw),
             Err(e) => return Err(io::Error::new(ErrorKind::Other, e)),
         };
+        let (sender, receiver) = sync_channel(self.depth);
+        thread::spawn(move || {
+            let mut offset = offset;
+            loop {
+                let result = read_block(&thread_fd, offset, compression);
+                let next_offset = match &result {
+                    Ok(block) if !block.eof => Some(block.next_offset),
+                    _ => None,
+                };
+                // An error here means the receiver was dropped
+                if sender.send(result).is_err() {
+                    break;
+                }
+                match next_offset {
+                    Some(next) => offset = next,
+                    None => break,
+                }
+            }
+        });
+        self.receiver = Some(receiver);
+        self.next_offset = offset;
+        Ok(())
+    }
+}
 
-        self.buffer.resize(header.uncompressed_length as usize, 0);
-        self.buffer_read_pos = 0;
-        if !do_decompress(compressed_buf.as_slice(), &mut self.buffer) {
+/// Read, verify and decompress the block whose header is at `offset` in `fd`.
+/// DIFF NOTE: Not present in rr. Extracted from `CompressedReader::refill_buffer()`
+fn read_block(
+    fd: &ScopedFd,
+    mut offset: u64,
+    compression: Compression,
+) -> io::Result<DecodedBlock> {
+    let mut header_vec: Vec<u8> = Vec::with_capacity(size_of::<BlockHeader>());
+    header_vec.resize(size_of::<BlockHeader>(), 0u8);
+    if false == read_all(fd, &mut header_vec, &mut offset)? {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Unexpected EOF encountered while doing read_all() on header in CompressedReader",
+        ));
+    }
+
+    let mut header: BlockHeader = Default::default();
+    unsafe {
+        copy_nonoverlapping(
+            header_vec.as_ptr(),
+            &raw mut header as *mut u8,
+            size_of::<BlockHeader>(),
+        );
+    }
+
+    let block_offset = offset - size_of::<BlockHeader>() as u64;
+    let mut compressed_buf: Vec<u8> = Vec::with_capacity(header.stored_len());
+    compressed_buf.resize(header.stored_len(), 0);
+    if false == read_all(fd, &mut compressed_buf, &mut offset)? {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Unexpected EOF encountered while doing read_all() on compressed data in CompressedReader",
+        ));
+    }
+
+    if header.has_checksum() {
+        let checksum = compressed_buf.split_off(header.compressed_len());
+        if block_checksum(&compressed_buf) != checksum.as_slice() {
             return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "There was a Decompression Error",
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Checksum mismatch in block at offset {} in CompressedReader",
+                    block_offset
+                ),
             ));
         }
+    }
 
-        Ok(())
+    let ch: u8 = 0;
+    let eof = match pread(
+        fd.as_raw(),
+        &mut ch.to_le_bytes(),
+        // On x86 off_t is an i32 and on x86_64 off_t is an i64
+        offset.try_into().unwrap(),
+    ) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => return Err(io::Error::new(ErrorKind::Other, e)),
+    };
+
+    let mut data = vec![0u8; header.uncompressed_length as usize];
+    let ok = match compression {
+        Compression::Brotli => do_decompress(compressed_buf.as_slice(), &mut data),
+        Compression::Zstd(_) => do_decompress_zstd(compressed_buf.as_slice(), &mut data),
+    };
+    if !ok {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "There was a Decompression Error",
+        ));
     }
+
+    Ok(DecodedBlock {
+        data,
+        next_offset: offset,
+        eof,
+    })
 }
 
 pub fn read_all(fd: &ScopedFd, data: &mut [u8], offset: &mut u64) -> io::Result<bool> {
@@ -306,6 +549,17 @@ pub fn do_decompress(compressed: &[u8], uncompressed: &mut [u8]) -> bool {
     decompress_result == BROTLI_DECODER_RESULT_SUCCESS && out_size == uncompressed.len()
 }
 
+/// DIFF NOTE: Not present in rr
+pub fn do_decompress_zstd(compressed: &[u8], uncompressed: &mut [u8]) -> bool {
+    match zstd::stream::decode_all(compressed) {
+        Ok(data) if data.len() == uncompressed.len() => {
+            uncompressed.copy_from_slice(&data);
+            true
+        }
+        _ => false,
+    }
+}
+
 impl BufRead for CompressedReader {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
         // If available to read bytes are "empty" and we have not yet reached EOF
@@ -9,6 +9,7 @@ use brotli_sys::{
     BROTLI_PARAM_QUALITY,
 };
 use nix::{fcntl::OFlag, sys::stat::Mode, unistd::fsync};
+use sha2::{Digest, Sha256};
 use std::{
     cmp::min,
     convert::TryInto,
@@ -36,12 +37,81 @@ pub enum WaitFlag {
     NoWait,
 }
 
+/// How each block is compressed.
+/// DIFF NOTE: rr always uses brotli
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Compression {
+    Brotli,
+    /// zstd with the given compression level
+    Zstd(i32),
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Brotli
+    }
+}
+
+/// Extension of files written with `Compression::Zstd`. CompressedReader
+/// uses this to pick the decompressor.
+pub const ZSTD_EXTENSION: &str = "zst";
+
 #[derive(Copy, Clone, Default)]
 pub struct BlockHeader {
     pub compressed_length: u32,
     pub uncompressed_length: u32,
 }
 
+/// Size of the SHA-256 checksum that follows the compressed data of a block
+/// when `BlockHeader::has_checksum()` is true.
+pub const BLOCK_CHECKSUM_SIZE: usize = 32;
+
+impl BlockHeader {
+    /// Set in `compressed_length` when the compressed data is followed by a
+    /// SHA-256 checksum of it. Compressed blocks are never anywhere near 2GB
+    /// so the top bit is free.
+    /// DIFF NOTE: Not present in rr
+    pub const CHECKSUM_FLAG: u32 = 1 << 31;
+
+    /// The size of the compressed data, excluding any checksum
+    pub fn compressed_len(&self) -> usize {
+        (self.compressed_length & !BlockHeader::CHECKSUM_FLAG) as usize
+    }
+
+    pub fn has_checksum(&self) -> bool {
+        self.compressed_length & BlockHeader::CHECKSUM_FLAG != 0
+    }
+
+    /// The size of the block on disk following the header
+    pub fn stored_len(&self) -> usize {
+        self.compressed_len()
+            + if self.has_checksum() {
+                BLOCK_CHECKSUM_SIZE
+            } else {
+                0
+            }
+    }
+}
+
+/// A position in the uncompressed data of a file written by CompressedWriter,
+/// expressed so that CompressedReader can seek to it without decompressing
+/// the blocks before it. See `CompressedReader::seek()`
+/// DIFF NOTE: Not present in rr
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct StreamPosition {
+    /// File offset of the header of the block containing the position
+    pub block_offset: u64,
+    /// Offset of the position in the uncompressed data of that block
+    pub offset_in_block: u64,
+}
+
+/// The checksum stored after the compressed data of a block
+pub fn block_checksum(compressed: &[u8]) -> [u8; BLOCK_CHECKSUM_SIZE] {
+    let mut checksum = [0u8; BLOCK_CHECKSUM_SIZE];
+    checksum.copy_from_slice(&Sha256::digest(compressed));
+    checksum
+}
+
 /// CompressedWriter opens an output file and writes compressed blocks to it.
 /// Blocks of a fixed but unspecified size (currently 1MB) are compressed.
 /// Each block of compressed data is written to the file preceded by two
@@ -54,7 +124,9 @@ pub struct BlockHeader {
 /// 'write'. The producer thread may block in 'write' if 'buffer_size' bytes are
 /// being compressed.
 ///
-/// Each data block is compressed independently using brotli.
+/// Each data block is compressed independently using brotli (or zstd, see
+/// `with_compression()`). If checksums are enabled, the compressed data is
+/// followed by its SHA-256 checksum, see `BlockHeader::CHECKSUM_FLAG`.
 pub struct CompressedWriter {
     /// Immutable while threads are running
     fd: ScopedFd,
@@ -87,6 +159,13 @@ pub struct CompressedWriterData {
     next_thread_end_pos: u64,
     closing: bool,
     write_error: bool,
+    /// Size of the data written to the file so far
+    /// DIFF NOTE: Not present in rr
+    compressed_pos: u64,
+    /// (uncompressed stream position, file offset) of the start of each block
+    /// written so far, in order.
+    /// DIFF NOTE: Not present in rr
+    block_starts: Vec<(u64, u64)>,
 }
 
 struct SharedBuf(*mut u8, usize);
@@ -99,6 +178,24 @@ impl CompressedWriter {
     }
 
     pub fn new(filename: &OsStr, block_size: usize, num_threads: usize) -> CompressedWriter {
+        CompressedWriter::with_compression(
+            filename,
+            block_size,
+            num_threads,
+            Compression::Brotli,
+            false,
+        )
+    }
+
+    /// If `checksum` is true, a SHA-256 checksum is appended to each block.
+    /// DIFF NOTE: Not present in rr
+    pub fn with_compression(
+        filename: &OsStr,
+        block_size: usize,
+        num_threads: usize,
+        compression: Compression,
+        checksum: bool,
+    ) -> CompressedWriter {
         let fd = ScopedFd::open_path_with_mode(
             filename,
             OFlag::O_CLOEXEC
@@ -128,6 +225,8 @@ impl CompressedWriter {
                 next_thread_end_pos: 0,
                 closing: false,
                 write_error: false,
+                compressed_pos: 0,
+                block_starts: Vec::new(),
             })),
             cond_var: Arc::new(Condvar::new()),
             threads: Vec::new(),
@@ -168,7 +267,9 @@ impl CompressedWriter {
                             // Add slop for incompressible data
                             let mut outputbuf = Vec::<u8>::new();
                             outputbuf.resize(
-                                ((block_size as f64 * 1.1) as usize) + size_of::<BlockHeader>(),
+                                ((block_size as f64 * 1.1) as usize)
+                                    + size_of::<BlockHeader>()
+                                    + BLOCK_CHECKSUM_SIZE,
                                 0u8,
                             );
                             let mut header: BlockHeader = Default::default();
@@ -194,20 +295,44 @@ impl CompressedWriter {
 
                                     let offset_in_input_buf = g.thread_pos[thread_index].unwrap();
                                     drop(g);
-                                    let compressed_length: usize = unsafe {
-                                        do_compress(
+                                    let compressed_end = outputbuf.len() - BLOCK_CHECKSUM_SIZE;
+                                    let compressed_length: usize = match compression {
+                                        Compression::Brotli => unsafe {
+                                            do_compress(
+                                                buffer,
+                                                offset_in_input_buf,
+                                                header.uncompressed_length as usize,
+                                                &mut outputbuf
+                                                    [size_of::<BlockHeader>()..compressed_end],
+                                            )
+                                        },
+                                        Compression::Zstd(level) => do_compress_zstd(
                                             buffer,
                                             offset_in_input_buf,
                                             header.uncompressed_length as usize,
-                                            &mut outputbuf[size_of::<BlockHeader>()..],
-                                        )
+                                            &mut outputbuf
+                                                [size_of::<BlockHeader>()..compressed_end],
+                                            level,
+                                        ),
                                     };
+                                    let data_end = size_of::<BlockHeader>() + compressed_length;
+                                    let mut block_end = data_end;
+                                    if checksum {
+                                        let sum = block_checksum(
+                                            &outputbuf[size_of::<BlockHeader>()..data_end],
+                                        );
+                                        block_end += BLOCK_CHECKSUM_SIZE;
+                                        outputbuf[data_end..block_end].copy_from_slice(&sum);
+                                    }
                                     g = mutex.lock().unwrap();
 
                                     if 0 == compressed_length {
                                         g.write_error = true;
                                     } else {
                                         header.compressed_length = compressed_length as u32;
+                                        if checksum {
+                                            header.compressed_length |= BlockHeader::CHECKSUM_FLAG;
+                                        }
                                     }
 
                                     unsafe {
@@ -238,12 +363,12 @@ impl CompressedWriter {
                                     }
 
                                     if !g.write_error {
+                                        let block_start =
+                                            (g.thread_pos[thread_index].unwrap(), g.compressed_pos);
+                                        g.block_starts.push(block_start);
+                                        g.compressed_pos += block_end as u64;
                                         drop(g);
-                                        write_all(
-                                            fd_raw,
-                                            &outputbuf[0..size_of::<BlockHeader>()
-                                                + header.compressed_length as usize],
-                                        );
+                                        write_all(fd_raw, &outputbuf[0..block_end]);
                                         g = mutex.lock().unwrap();
                                     }
 
@@ -306,6 +431,34 @@ impl CompressedWriter {
         self.fd.close();
     }
 
+    /// The number of (uncompressed) bytes written so far
+    /// DIFF NOTE: Not present in rr
+    pub fn uncompressed_pos(&self) -> u64 {
+        self.producer_reserved_write_pos
+    }
+
+    /// Where the uncompressed stream position `pos` ended up in the file.
+    /// Only meaningful for data that has already been written out, so call
+    /// this after `close()`.
+    /// DIFF NOTE: Not present in rr
+    pub fn stream_position(&self, pos: u64) -> StreamPosition {
+        let g = self.mutex.lock().unwrap();
+        // The last block that starts at or before `pos`. If `pos` is the end of
+        // the stream this is the last block and `pos` is the end of it.
+        let i = g.block_starts.partition_point(|&(start, _)| start <= pos);
+        if i == 0 {
+            return StreamPosition {
+                block_offset: 0,
+                offset_in_block: pos,
+            };
+        }
+        let (start, block_offset) = g.block_starts[i - 1];
+        StreamPosition {
+            block_offset,
+            offset_in_block: pos - start,
+        }
+    }
+
     pub fn update_reservation(&mut self, wait_flag: WaitFlag) {
         let mut g = self.mutex.lock().unwrap();
 
@@ -437,3 +590,28 @@ unsafe fn do_compress(
     BrotliEncoderDestroyInstance(state);
     ret
 }
+
+/// Returns 0 on failure, like `do_compress()`.
+/// DIFF NOTE: Not present in rr
+fn do_compress_zstd(
+    shared_buf: &[u8],
+    stream_offset: u64,
+    uncompressed_len: usize,
+    output_buf: &mut [u8],
+    level: i32,
+) -> usize {
+    // The block may wrap around the end of the shared buffer
+    let mut input = Vec::with_capacity(uncompressed_len);
+    let shared_buf_offset: usize = (stream_offset % shared_buf.len() as u64) as usize;
+    let amount = min(uncompressed_len, shared_buf.len() - shared_buf_offset);
+    input.extend_from_slice(&shared_buf[shared_buf_offset..shared_buf_offset + amount]);
+    input.extend_from_slice(&shared_buf[0..uncompressed_len - amount]);
+
+    match zstd::stream::encode_all(input.as_slice(), level) {
+        Ok(compressed) if compressed.len() <= output_buf.len() => {
+            output_buf[0..compressed.len()].copy_from_slice(&compressed);
+            compressed.len()
+        }
+        _ => 0,
+    }
+}
@@ -0,0 +1,155 @@
+//! Fixtures shared by the trace tests.
+
+use crate::{
+    session::record_session::TraceUuid,
+    trace::{
+        compressed_writer::CompressedWriter,
+        trace_frame::FrameTime,
+        trace_stream::{
+            FrameIndexEntry,
+            Substream,
+            TraceStream,
+            SUBSTREAMS,
+            SUBSTREAM_COUNT,
+            TRACE_VERSION,
+        },
+    },
+    trace_capnp::{frame, header, m_map, task_event},
+};
+use capnp::{message, serialize_packed::write_message};
+use std::{
+    env,
+    ffi::OsStr,
+    fs::{create_dir_all, remove_dir_all, File},
+    io::Write,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// A fresh directory under the temp dir that is removed, with everything in
+/// it, when dropped. Named after the test so that tests running in parallel
+/// don't clash.
+pub struct TempTraceDir {
+    path: PathBuf,
+}
+
+impl TempTraceDir {
+    pub fn new(name: &str) -> TempTraceDir {
+        let path = env::temp_dir().join(format!("rd-{}-{}", name, process::id()));
+        // Left over from an earlier run that failed
+        remove_dir_all(&path).ok();
+        create_dir_all(&path).unwrap();
+        TempTraceDir { path }
+    }
+}
+
+impl Deref for TempTraceDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempTraceDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<OsStr> for TempTraceDir {
+    fn as_ref(&self) -> &OsStr {
+        self.path.as_os_str()
+    }
+}
+
+impl Drop for TempTraceDir {
+    fn drop(&mut self) {
+        remove_dir_all(&self.path).ok();
+    }
+}
+
+/// Write a trace with `frames` frames to `dir`. Frame `t` writes `t` bytes
+/// of value `t` and has an mmap and a task event. The substreams use blocks
+/// of `block_size` and a frame index entry is written every `index_interval`
+/// frames (none if 0).
+pub fn write_test_trace(dir: &Path, frames: u8, block_size: usize, index_interval: u64) {
+    create_dir_all(dir).unwrap();
+    let stream = TraceStream::new(dir.as_os_str(), 1);
+    // Indexed by `Substream as usize`
+    let mut writers: Vec<CompressedWriter> = SUBSTREAMS
+        .iter()
+        .map(|&s| CompressedWriter::new(&stream.path(s), block_size, 1))
+        .collect();
+    let mut index: Vec<(FrameTime, [u64; SUBSTREAM_COUNT])> = Vec::new();
+    for t in 1..=frames {
+        if index_interval > 0 && t > 1 && (t as u64 - 1) % index_interval == 0 {
+            let mut positions = [0u64; SUBSTREAM_COUNT];
+            for (i, w) in writers.iter().enumerate() {
+                positions[i] = w.uncompressed_pos();
+            }
+            index.push((t as FrameTime, positions));
+        }
+
+        let mut msg = message::Builder::new_default();
+        {
+            let mut frame = msg.init_root::<frame::Builder>();
+            frame.set_tid(100);
+            let mut w = frame.reborrow().init_mem_writes(1).get(0);
+            w.set_tid(100);
+            w.set_addr(0x1000);
+            w.set_size(t as u64);
+            frame.init_event().set_sched(());
+        }
+        write_message(&mut writers[Substream::Events as usize], &msg).unwrap();
+        writers[Substream::RawData as usize]
+            .write_all(&vec![t; t as usize])
+            .unwrap();
+
+        let mut msg = message::Builder::new_default();
+        msg.init_root::<m_map::Builder>().set_frame_time(t as i64);
+        write_message(&mut writers[Substream::Mmaps as usize], &msg).unwrap();
+
+        let mut msg = message::Builder::new_default();
+        {
+            let mut task = msg.init_root::<task_event::Builder>();
+            task.set_frame_time(t as i64);
+            task.set_tid(100);
+            task.init_exit().set_exit_status(0);
+        }
+        write_message(&mut writers[Substream::Tasks as usize], &msg).unwrap();
+    }
+    for w in writers.iter_mut() {
+        w.close(None);
+    }
+
+    if !index.is_empty() {
+        let entries: Vec<FrameIndexEntry> = index
+            .iter()
+            .map(|&(time, positions)| {
+                let mut entry = FrameIndexEntry {
+                    time,
+                    ..Default::default()
+                };
+                for (i, w) in writers.iter().enumerate() {
+                    entry.positions[i] = w.stream_position(positions[i]);
+                }
+                entry
+            })
+            .collect();
+        stream.write_frame_index(&entries).unwrap();
+    }
+
+    let mut header_msg = message::Builder::new_default();
+    {
+        let mut header = header_msg.init_root::<header::Builder>();
+        header.set_bind_to_cpu(0);
+        header.set_uuid(TraceUuid::generate_new().inner_bytes());
+    }
+    let mut version = File::create(stream.version_path()).unwrap();
+    version
+        .write_all(format!("{}\n", TRACE_VERSION).as_bytes())
+        .unwrap();
+    write_message(&mut version, &header_msg).unwrap();
+}
@@ -0,0 +1,207 @@
+//! Upgrading traces recorded with an older `TRACE_VERSION` so the current rd can replay them.
+//! See `TraceReader::open_with_migration()`
+//!
+//! DIFF NOTE: Not present in rr. rr refuses to open traces of any other version.
+//!
+//! Each `Migration` upgrades a trace directory in place by exactly one version. A trace is
+//! only touched if there is a migration for every step from its version to `TRACE_VERSION`.
+//!
+//! 85 -> 86 added the optional zstd marker to the version file and 86 -> 87 added optional
+//! per-block checksums. The reader handles traces without either, so both steps only bump
+//! the version number.
+
+use crate::{
+    error::{RdError, RdResult},
+    trace::trace_stream::{TraceStream, TRACE_VERSION},
+};
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+pub trait Migration {
+    /// The version of the traces this migration applies to. It produces traces of version
+    /// `from_version() + 1`.
+    fn from_version(&self) -> u32;
+
+    /// Rewrite the substreams and other files of the trace in `dir` in the format of the next
+    /// version. The version file is updated by the caller afterwards.
+    fn migrate(&self, dir: &Path) -> RdResult<()>;
+}
+
+/// A version step that didn't change anything the reader can't already handle
+struct VersionBumpMigration {
+    from: u32,
+}
+
+impl Migration for VersionBumpMigration {
+    fn from_version(&self) -> u32 {
+        self.from
+    }
+
+    fn migrate(&self, _dir: &Path) -> RdResult<()> {
+        Ok(())
+    }
+}
+
+/// All known migrations, in any order.
+pub fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        // Brotli compressed traces have no compression marker in the version file
+        Box::new(VersionBumpMigration { from: 85 }),
+        // Blocks without `BlockHeader::CHECKSUM_FLAG` are read without verification
+        Box::new(VersionBumpMigration { from: 86 }),
+    ]
+}
+
+/// The migrations that upgrade a trace from `found` to `required`, in the order they need to
+/// be applied. Fails if any step is missing or if `found` is newer than `required`.
+pub fn migration_chain(
+    found: u32,
+    required: u32,
+    migrations: &[Box<dyn Migration>],
+) -> RdResult<Vec<&dyn Migration>> {
+    if found > required {
+        return Err(RdError::IncompatibleTraceVersion { found, required });
+    }
+    (found..required)
+        .map(|version| {
+            migrations
+                .iter()
+                .find(|m| m.from_version() == version)
+                .map(|m| m.as_ref())
+                .ok_or(RdError::IncompatibleTraceVersion { found, required })
+        })
+        .collect()
+}
+
+/// Upgrade the trace in `dir` to `TRACE_VERSION`, if it is older. Traces that are already
+/// current or whose version can't be read are left alone; opening them reports any problem.
+pub fn migrate_trace(dir: &Path, migrations: &[Box<dyn Migration>]) -> RdResult<()> {
+    let version_path = TraceStream::new(dir.as_os_str(), 1).version_path();
+    let found = match read_version(Path::new(&version_path)) {
+        Some(version) => version,
+        None => return Ok(()),
+    };
+    for m in migration_chain(found, TRACE_VERSION, migrations)? {
+        m.migrate(dir)?;
+        set_version(Path::new(&version_path), m.from_version() + 1)?;
+    }
+    Ok(())
+}
+
+/// The version number at the start of the first line of the version file
+fn read_version(version_path: &Path) -> Option<u32> {
+    let mut line = String::new();
+    BufReader::new(fs::File::open(version_path).ok()?)
+        .read_line(&mut line)
+        .ok()?;
+    line.split_whitespace().next()?.parse::<u32>().ok()
+}
+
+/// Replace the version number in the version file, keeping the compression marker (if any)
+/// and the header that follows.
+fn set_version(version_path: &Path, version: u32) -> RdResult<()> {
+    let contents = fs::read(version_path)?;
+    let number_len = contents
+        .iter()
+        .position(|c| !c.is_ascii_digit())
+        .unwrap_or(contents.len());
+    let mut updated = version.to_string().into_bytes();
+    updated.extend_from_slice(&contents[number_len..]);
+    fs::write(version_path, updated)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::test_util::TempTraceDir;
+    use std::cell::RefCell;
+
+    thread_local! {
+        /// The `from_version()` of each `TestMigration` applied, in order
+        static APPLIED: RefCell<Vec<u32>> = RefCell::new(Vec::new());
+    }
+
+    struct TestMigration {
+        from: u32,
+    }
+
+    impl Migration for TestMigration {
+        fn from_version(&self) -> u32 {
+            self.from
+        }
+
+        fn migrate(&self, _dir: &Path) -> RdResult<()> {
+            APPLIED.with(|applied| applied.borrow_mut().push(self.from));
+            Ok(())
+        }
+    }
+
+    fn test_migrations(froms: &[u32]) -> Vec<Box<dyn Migration>> {
+        froms
+            .iter()
+            .map(|&from| Box::new(TestMigration { from }) as Box<dyn Migration>)
+            .collect()
+    }
+
+    #[test]
+    fn migration_chain_test() {
+        let migrations = test_migrations(&[12, 10, 11]);
+        let chain = migration_chain(10, 13, &migrations).unwrap();
+        assert_eq!(
+            vec![10, 11, 12],
+            chain.iter().map(|m| m.from_version()).collect::<Vec<_>>()
+        );
+        assert!(migration_chain(13, 13, &migrations).unwrap().is_empty());
+        assert!(matches!(
+            migration_chain(9, 13, &migrations),
+            Err(RdError::IncompatibleTraceVersion {
+                found: 9,
+                required: 13
+            })
+        ));
+        assert!(matches!(
+            migration_chain(14, 13, &migrations),
+            Err(RdError::IncompatibleTraceVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn registered_migrations_test() {
+        let migrations = migrations();
+        assert_eq!(
+            2,
+            migration_chain(85, TRACE_VERSION, &migrations)
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn migrate_trace_test() {
+        let dir = TempTraceDir::new("migrate");
+        let version_path = dir.join("version");
+        fs::write(&version_path, format!("{} zstd\nHEADER", TRACE_VERSION - 2)).unwrap();
+
+        // A missing step leaves the trace untouched
+        let migrations = test_migrations(&[TRACE_VERSION - 1]);
+        assert!(migrate_trace(&dir, &migrations).is_err());
+        APPLIED.with(|applied| assert!(applied.borrow().is_empty()));
+
+        let migrations = test_migrations(&[TRACE_VERSION - 1, TRACE_VERSION - 2]);
+        migrate_trace(&dir, &migrations).unwrap();
+        APPLIED.with(|applied| {
+            assert_eq!(
+                vec![TRACE_VERSION - 2, TRACE_VERSION - 1],
+                *applied.borrow()
+            )
+        });
+        assert_eq!(
+            format!("{} zstd\nHEADER", TRACE_VERSION),
+            fs::read_to_string(&version_path).unwrap()
+        );
+    }
+}
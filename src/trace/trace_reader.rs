@@ -1,5 +1,6 @@
 use crate::{
-    bindings::{signal::siginfo_t, sysexits::EX_DATAERR},
+    bindings::signal::siginfo_t,
+    error::{exit_on_error, RdError, RdResult},
     event::{
         Event,
         EventType,
@@ -21,11 +22,17 @@ use crate::{
     session::{address_space::kernel_mapping::KernelMapping, record_session::TraceUuid},
     trace::{
         compressed_reader::{CompressedReader, CompressedReaderState},
+        compressed_writer::{CompressedWriter, Compression},
         trace_frame::{FrameTime, TraceFrame},
+        trace_migration,
         trace_stream::{
+            from_trace_arch,
             latest_trace_symlink,
+            substream,
+            to_io_error,
             to_trace_arch,
             trace_save_dir,
+            FrameIndexEntry,
             MappedData,
             MappedDataSource::{SourceFile, SourceTrace, SourceZero},
             RawDataMetadata,
@@ -34,6 +41,7 @@ use crate::{
             TraceStream,
             SUBSTREAMS,
             TRACE_VERSION,
+            ZSTD_VERSION_MARKER,
         },
         trace_task_event::{
             TraceTaskEvent,
@@ -64,8 +72,11 @@ use crate::{
     },
     wait_status::WaitStatus,
 };
-use capnp::{message::ReaderOptions, serialize_packed::read_message};
-use libc::{ino_t, pid_t, time_t, ENOENT};
+use capnp::{
+    message::{self, ReaderOptions},
+    serialize_packed::{read_message, write_message},
+};
+use libc::{dev_t, ino_t, pid_t, time_t, ENOENT};
 use nix::{
     errno::errno,
     sys::{
@@ -74,19 +85,27 @@ use nix::{
     },
     unistd::{access, AccessFlags},
 };
+use serde::Serialize;
 use std::{
-    collections::HashMap,
+    cell::Cell,
+    cmp::max,
+    collections::{BTreeMap, HashMap, VecDeque},
     convert::{TryFrom, TryInto},
     ffi::{OsStr, OsString},
-    fs::File,
-    io::{BufRead, BufReader, Read},
+    fs::{create_dir, hard_link, read_link, rename, File},
+    io::{self, BufRead, BufReader, Read, Write},
     mem::size_of,
     ops::{Deref, DerefMut},
     os::unix::ffi::{OsStrExt, OsStringExt},
-    process::exit,
+    path::Path,
     ptr::copy_nonoverlapping,
+    time::{Duration, Instant},
 };
 
+/// Number of recently read frames used to estimate the replay rate.
+/// See `TraceReader::replay_wall_time_estimate()`
+const REPLAY_RATE_WINDOW: usize = 100;
+
 /// Read the next mapped region descriptor and return it.
 /// Also returns where to get the mapped data in `data`, if it's not `None`.
 /// If `found` is not `None`, set `found` to indicate whether a descriptor
@@ -113,6 +132,61 @@ pub struct RawData {
     pub rec_tid: pid_t,
 }
 
+/// A line of `TraceReader::export_jsonl()` output for a frame
+#[derive(Serialize)]
+struct FrameRecord {
+    kind: &'static str,
+    time: FrameTime,
+    tid: pid_t,
+    ticks: u64,
+    monotonic_sec: f64,
+    /// e.g. "SYSCALL"
+    event_type: String,
+    /// e.g. "SYSCALL: read"
+    event: String,
+    /// `None` if the event does not record registers
+    registers: Option<BTreeMap<&'static str, u64>>,
+    raw_data_sizes: Vec<usize>,
+}
+
+/// A line of `TraceReader::export_jsonl()` output for an mmap
+#[derive(Serialize)]
+struct MmapRecord {
+    kind: &'static str,
+    time: FrameTime,
+    start: usize,
+    end: usize,
+    prot: i32,
+    flags: i32,
+    file_offset: u64,
+    fsname: String,
+    device: dev_t,
+    inode: ino_t,
+    /// "zero", "trace" or "file"
+    source: &'static str,
+    data_file: String,
+}
+
+/// A line of `TraceReader::export_jsonl()` output for a task event
+#[derive(Serialize)]
+struct TaskRecord {
+    kind: &'static str,
+    time: FrameTime,
+    tid: pid_t,
+    /// "clone", "exec" or "exit"
+    event: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_tid: Option<pid_t>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clone_flags: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cmd_line: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_status: Option<i32>,
+}
+
 /// Create a copy of this stream that has exactly the same
 /// state as 'other', but for which mutations of this
 /// clone won't affect the state of 'other' (and vice versa).
@@ -128,6 +202,15 @@ pub struct TraceReader {
     uuid_: TraceUuid,
     trace_uses_cpuid_faulting: bool,
     preload_thread_locals_recorded_: bool,
+    /// DIFF NOTE: Not present in rr
+    /// When each of the last `REPLAY_RATE_WINDOW` frames was read.
+    frame_read_times: VecDeque<Instant>,
+    /// DIFF NOTE: Not present in rr
+    /// Number of frames in the trace. Computed lazily.
+    total_frame_count: Cell<Option<FrameTime>>,
+    /// DIFF NOTE: Not present in rr
+    /// Loaded on the first `seek_to_time()`. Empty if the trace has no index.
+    frame_index: Option<Vec<FrameIndexEntry>>,
 }
 
 impl Deref for TraceReader {
@@ -157,6 +240,11 @@ impl TraceReader {
 
         self.tick_time();
 
+        if self.frame_read_times.len() == REPLAY_RATE_WINDOW {
+            self.frame_read_times.pop_front();
+        }
+        self.frame_read_times.push_back(Instant::now());
+
         let mem_writes = frame.get_mem_writes().unwrap();
         self.raw_recs = Vec::new();
         let mut it = mem_writes.iter();
@@ -551,9 +639,11 @@ impl TraceReader {
 
     /// Return the next trace frame, without mutating any stream
     /// state.
+    /// DIFF NOTE: The unread raw data records of the current frame are kept too.
     pub fn peek_frame(&mut self) -> Option<TraceFrame> {
         if !self.at_end() {
             let saved_time = self.global_time;
+            let saved_raw_recs = self.raw_recs.clone();
             let state: CompressedReaderState;
             {
                 let events = self.reader_mut(Substream::Events);
@@ -565,6 +655,7 @@ impl TraceReader {
                 events.restore_state(state);
             }
             self.global_time = saved_time;
+            self.raw_recs = saved_raw_recs;
             Some(frame)
         } else {
             return None;
@@ -573,11 +664,401 @@ impl TraceReader {
 
     /// Restore the state of this to what it was just after
     /// `open()`.
-    pub fn rewind(&mut self) {
+    /// DIFF NOTE: Returns an error (rather than ignoring it) if a substream can't be read.
+    /// Also discards the raw data records of the current frame that weren't read yet.
+    pub fn rewind(&mut self) -> io::Result<()> {
         for w in self.readers.values_mut() {
-            w.rewind();
+            w.rewind()?;
         }
         self.global_time = 0;
+        self.raw_recs.clear();
+        self.frame_read_times.clear();
+        Ok(())
+    }
+
+    /// Position the reader so that the next frame read is the one at time `t`.
+    /// `t` may be before the current time.
+    ///
+    /// The frame index written during recording is binary searched for the
+    /// closest entry at or before `t` and all substreams are moved there, then
+    /// frames are read forward up to `t`. Without a usable entry this reads
+    /// forward from the current position or, if `t` is behind it, from the
+    /// start of the trace.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn seek_to_time(&mut self, t: FrameTime) -> io::Result<()> {
+        if t == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame times start at 1",
+            ));
+        }
+        if self.frame_index.is_none() {
+            let index = match self.read_frame_index() {
+                Ok(index) => index,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e),
+            };
+            self.frame_index = Some(index);
+        }
+
+        let index = self.frame_index.as_ref().unwrap();
+        let i = index.partition_point(|entry| entry.time <= t);
+        let maybe_entry = if i == 0 { None } else { Some(index[i - 1]) };
+        // The next frame to be read is at time() + 1. If the entry isn't past it
+        // reading forward from where we are is at least as quick.
+        let next_time = self.time() + 1;
+        let read_forward =
+            next_time <= t && maybe_entry.map_or(true, |entry| entry.time <= next_time);
+        if !read_forward {
+            match maybe_entry {
+                Some(entry) => {
+                    for &s in SUBSTREAMS.iter() {
+                        self.reader_mut(s).seek(entry.positions[s as usize])?;
+                    }
+                    self.global_time = entry.time - 1;
+                }
+                None => self.rewind()?,
+            }
+        }
+
+        self.raw_recs.clear();
+        while self.time() + 1 < t {
+            if self.at_end() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("The trace ends before frame {}", t),
+                ));
+            }
+            self.read_frame();
+            while self.read_raw_data_metadata_for_frame().is_some() {}
+        }
+        self.skip_records_before(Substream::Mmaps, t)?;
+        self.skip_records_before(Substream::Tasks, t)?;
+        // The frames read above say nothing about the replay rate
+        self.frame_read_times.clear();
+        Ok(())
+    }
+
+    /// Skip the records of the Mmaps or Tasks substream with a frame time before `t`
+    fn skip_records_before(&mut self, s: Substream, t: FrameTime) -> io::Result<()> {
+        let reader = self.reader_mut(s);
+        while !reader.at_end() {
+            let state = reader.get_state();
+            let msg = read_message(&mut *reader, ReaderOptions::new()).map_err(to_io_error)?;
+            let frame_time = match s {
+                Substream::Mmaps => msg.get_root::<m_map::Reader>().map(|m| m.get_frame_time()),
+                Substream::Tasks => msg
+                    .get_root::<task_event::Reader>()
+                    .map(|task| task.get_frame_time()),
+                _ => unreachable!("Only the Mmaps and Tasks substreams have frame times"),
+            }
+            .map_err(to_io_error)?;
+            if frame_time as u64 >= t {
+                reader.restore_state(state);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the whole trace to `out` as JSON lines, one object per line, for
+    /// analysis with external tools. All the frames come first, then all the
+    /// mmaps, then all the task events. The `kind` field of each object is
+    /// "frame", "mmap" or "task" respectively. Register values are keyed by
+    /// their names, e.g. "rip".
+    ///
+    /// Rewinds the reader first and processes one record at a time. Leaves the
+    /// reader at the end of the trace.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn export_jsonl(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.rewind()?;
+
+        while !self.at_end() {
+            let frame = self.read_frame();
+            let mut raw_data_sizes = Vec::new();
+            while let Some(d) = self.read_raw_data_metadata_for_frame() {
+                raw_data_sizes.push(d.size);
+            }
+            let registers = if frame.event().record_regs() {
+                Some(frame.regs_ref().iter().collect())
+            } else {
+                None
+            };
+            write_jsonl(
+                out,
+                &FrameRecord {
+                    kind: "frame",
+                    time: frame.time(),
+                    tid: frame.tid(),
+                    ticks: frame.ticks(),
+                    monotonic_sec: frame.monotonic_time(),
+                    event_type: frame.event().event_type().to_string(),
+                    event: frame.event().to_string(),
+                    registers,
+                    raw_data_sizes,
+                },
+            )?;
+        }
+
+        loop {
+            let mut data = MappedData::default();
+            let km = match self.read_mapped_region(
+                Some(&mut data),
+                Some(ValidateSourceFile::DontValidate),
+                Some(TimeConstraint::AnyTime),
+                None,
+                None,
+            ) {
+                Some(km) => km,
+                None => break,
+            };
+            write_jsonl(
+                out,
+                &MmapRecord {
+                    kind: "mmap",
+                    time: data.time,
+                    start: km.start().as_usize(),
+                    end: km.end().as_usize(),
+                    prot: km.prot().bits(),
+                    flags: km.flags().bits(),
+                    file_offset: km.file_offset_bytes(),
+                    fsname: km.fsname().to_string_lossy().into_owned(),
+                    device: km.device(),
+                    inode: km.inode(),
+                    source: match data.source {
+                        SourceZero => "zero",
+                        SourceTrace => "trace",
+                        SourceFile => "file",
+                    },
+                    data_file: data.filename.to_string_lossy().into_owned(),
+                },
+            )?;
+        }
+
+        let mut time: FrameTime = 0;
+        while let Some(task_event) = self.read_task_event(Some(&mut time)) {
+            let mut record = TaskRecord {
+                kind: "task",
+                time,
+                tid: task_event.tid(),
+                event: "",
+                parent_tid: None,
+                clone_flags: None,
+                file_name: None,
+                cmd_line: None,
+                exit_status: None,
+            };
+            match task_event.event_variant() {
+                TraceTaskEventVariant::Clone(e) => {
+                    record.event = "clone";
+                    record.parent_tid = Some(e.parent_tid());
+                    record.clone_flags = Some(e.clone_flags());
+                }
+                TraceTaskEventVariant::Exec(e) => {
+                    record.event = "exec";
+                    record.file_name = Some(e.file_name().to_string_lossy().into_owned());
+                    record.cmd_line = Some(
+                        e.cmd_line()
+                            .iter()
+                            .map(|arg| arg.to_string_lossy().into_owned())
+                            .collect(),
+                    );
+                }
+                TraceTaskEventVariant::Exit(e) => {
+                    record.event = "exit";
+                    record.exit_status = Some(e.exit_status().get());
+                }
+            }
+            write_jsonl(out, &record)?;
+        }
+
+        Ok(())
+    }
+
+    /// Estimate how long replaying the rest of the trace will take, based on
+    /// how quickly the last `REPLAY_RATE_WINDOW` frames were read.
+    /// Returns None until that many frames have been read.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn replay_wall_time_estimate(&self) -> Option<Duration> {
+        if self.frame_read_times.len() < REPLAY_RATE_WINDOW {
+            return None;
+        }
+        let total = match self.total_frame_count.get() {
+            Some(total) => total,
+            None => {
+                let total = self.count_frames().ok()?;
+                self.total_frame_count.set(Some(total));
+                total
+            }
+        };
+        estimate_remaining_time(&self.frame_read_times, total.saturating_sub(self.time()))
+    }
+
+    fn count_frames(&self) -> io::Result<FrameTime> {
+        let mut reader = CompressedReader::new(&self.path(Substream::Events));
+        let mut count: FrameTime = 0;
+        while !reader.at_end() {
+            read_message(&mut reader, ReaderOptions::new())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.description))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Write the frames in `[start, end]` of this trace, along with their raw
+    /// data, to a new trace in `output_dir`.
+    ///
+    /// The trimmed trace only supports inspection (e.g. `rd dump`, `rd export`).
+    /// It CANNOT be replayed: replay re-executes the tracees from the initial
+    /// exec and the tracee state at `start` is not part of the trace. The data
+    /// of `SourceTrace` mappings made before `start` is not copied either. For
+    /// the same reason the `latest-trace` symlink is left alone.
+    ///
+    /// Frames are renumbered so that `start` becomes time 1. All the mmaps and
+    /// task events up to `end` are kept so that the address spaces and tasks
+    /// at `start` are known. Those recorded before `start` are moved to time 1.
+    /// The trace metadata is copied as is. Does not move the position of this reader.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn trim(&self, output_dir: &OsStr, start: FrameTime, end: FrameTime) -> io::Result<()> {
+        if start == 0 || start > end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid frame range [{}, {}]", start, end),
+            ));
+        }
+
+        create_dir(output_dir)?;
+        let mut out = TraceStream::new(output_dir, 1);
+        if let Compression::Zstd(level) = self.compression() {
+            out = out.with_compression(level);
+        }
+        let mut writers: HashMap<Substream, CompressedWriter> = HashMap::new();
+        for &s in SUBSTREAMS.iter() {
+            writers.insert(
+                s,
+                CompressedWriter::with_compression(
+                    &out.path(s),
+                    substream(s).block_size,
+                    substream(s).threads,
+                    self.compression(),
+                    substream(s).checksum_enabled,
+                ),
+            );
+        }
+        let rebase = |frame_time: i64| (max(frame_time as u64, start) - start + 1) as i64;
+
+        // Frames don't record their time explicitly, see TraceStream::truncate_at_frame()
+        let mut events = CompressedReader::new(&self.path(Substream::Events));
+        let mut raw_data = CompressedReader::new(&self.path(Substream::RawData));
+        let mut time: FrameTime = 0;
+        while !events.at_end() && time < end {
+            let msg = read_message(&mut events, ReaderOptions::new()).map_err(to_io_error)?;
+            let frame = msg.get_root::<frame::Reader>().map_err(to_io_error)?;
+            time += 1;
+            let mut raw_size: usize = 0;
+            for w in frame.get_mem_writes().map_err(to_io_error)?.iter() {
+                raw_size += w.get_size() as usize;
+            }
+            if time < start {
+                raw_data.skip(raw_size)?;
+                continue;
+            }
+
+            let mut data = vec![0u8; raw_size];
+            raw_data.read_exact(&mut data)?;
+            writers
+                .get_mut(&Substream::RawData)
+                .unwrap()
+                .write_all(&data)?;
+            let mut copy = message::Builder::new_default();
+            copy.set_root(frame).map_err(to_io_error)?;
+            write_message(writers.get_mut(&Substream::Events).unwrap(), &copy)
+                .map_err(to_io_error)?;
+        }
+
+        let mut mmaps = CompressedReader::new(&self.path(Substream::Mmaps));
+        while !mmaps.at_end() {
+            let msg = read_message(&mut mmaps, ReaderOptions::new()).map_err(to_io_error)?;
+            let map = msg.get_root::<m_map::Reader>().map_err(to_io_error)?;
+            if map.get_frame_time() as u64 > end {
+                continue;
+            }
+            if let Ok(m_map::source::File(f)) = map.get_source().which() {
+                let backing_file_name = f.get_backing_file_name().map_err(to_io_error)?;
+                // Relative names refer to files stored in the trace directory
+                if backing_file_name[0] != b'/' {
+                    let name = OsStr::from_bytes(backing_file_name);
+                    let src = Path::new(&self.dir()).join(name);
+                    let dest = Path::new(output_dir).join(name);
+                    if !dest.exists() && hard_link(&src, &dest).is_err() {
+                        std::fs::copy(&src, &dest)?;
+                    }
+                }
+            }
+            let mut copy = message::Builder::new_default();
+            copy.set_root(map).map_err(to_io_error)?;
+            copy.get_root::<m_map::Builder>()
+                .map_err(to_io_error)?
+                .set_frame_time(rebase(map.get_frame_time()));
+            write_message(writers.get_mut(&Substream::Mmaps).unwrap(), &copy)
+                .map_err(to_io_error)?;
+        }
+
+        let mut tasks = CompressedReader::new(&self.path(Substream::Tasks));
+        while !tasks.at_end() {
+            let msg = read_message(&mut tasks, ReaderOptions::new()).map_err(to_io_error)?;
+            let task = msg.get_root::<task_event::Reader>().map_err(to_io_error)?;
+            if task.get_frame_time() as u64 > end {
+                continue;
+            }
+            let mut copy = message::Builder::new_default();
+            copy.set_root(task).map_err(to_io_error)?;
+            copy.get_root::<task_event::Builder>()
+                .map_err(to_io_error)?
+                .set_frame_time(rebase(task.get_frame_time()));
+            write_message(writers.get_mut(&Substream::Tasks).unwrap(), &copy)
+                .map_err(to_io_error)?;
+        }
+
+        for (s, mut w) in writers {
+            w.close(None);
+            if !w.good() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Unable to write {:?}", out.path(s)),
+                ));
+            }
+        }
+
+        // Same version line and header, but this is a different trace so it
+        // gets a new UUID.
+        let mut version = BufReader::new(File::open(self.version_path())?);
+        let mut version_line = String::new();
+        version.read_line(&mut version_line)?;
+        let header_msg = read_message(&mut version, ReaderOptions::new()).map_err(to_io_error)?;
+        let header = header_msg
+            .get_root::<header::Reader>()
+            .map_err(to_io_error)?;
+        let mut header_copy = message::Builder::new_default();
+        header_copy.set_root(header).map_err(to_io_error)?;
+        header_copy
+            .get_root::<header::Builder>()
+            .map_err(to_io_error)?
+            .set_uuid(TraceUuid::generate_new().inner_bytes());
+        let mut version_out = File::create(out.incomplete_version_path())?;
+        version_out.write_all(version_line.as_bytes())?;
+        write_message(&mut version_out, &header_copy).map_err(to_io_error)?;
+        rename(out.incomplete_version_path(), out.version_path())?;
+
+        // Annotations describe the recording, so they apply to the trimmed trace too
+        out.metadata = self.metadata.clone();
+        out.flush_metadata()?;
+
+        Ok(())
     }
 
     pub fn uncompressed_bytes(&self) -> u64 {
@@ -595,85 +1076,143 @@ impl TraceReader {
         total
     }
 
+    /// Open the trace pointed to by the `latest-trace` symlink in the trace
+    /// save dir. The symlink is followed at most one level.
+    ///
+    /// DIFF NOTE: Not present in rr. Unlike `TraceReader::new(None)` this returns
+    /// an error if the symlink is missing or dangling.
+    pub fn open_latest() -> io::Result<TraceReader> {
+        let sym = latest_trace_symlink();
+        let target = read_link(&sym).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Could not read latest trace symlink {:?}: {}", sym, e),
+            )
+        })?;
+        let dir = if target.is_absolute() {
+            target
+        } else {
+            // A relative symlink target is relative to the directory containing the symlink
+            Path::new(&trace_save_dir()).join(target)
+        };
+        if !dir_exists(dir.as_os_str()) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Latest trace symlink {:?} is dangling: {:?}", sym, dir),
+            ));
+        }
+
+        TraceReader::try_new(Some(&dir)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
     /// Open the trace in 'dir'. When 'dir' is the `None`, open the
     /// latest trace.
+    ///
+    /// Exits the process if the trace can't be opened. See `try_new()`.
     pub fn new<T: AsRef<OsStr>>(maybe_dir: Option<&T>) -> TraceReader {
-        let mut trace_stream = TraceStream::new(&resolve_trace_name(maybe_dir), 1);
+        exit_on_error(TraceReader::try_new(maybe_dir))
+    }
 
-        let mut readers: HashMap<Substream, CompressedReader> = HashMap::new();
-        for &s in SUBSTREAMS.iter() {
-            readers.insert(s, CompressedReader::new(&trace_stream.path(s)));
-        }
+    /// Like `try_new()` but first upgrades the trace in `dir` (in place) if it was recorded
+    /// with an older `TRACE_VERSION`. Fails with `RdError::IncompatibleTraceVersion` if
+    /// there is no migration path, in which case the trace is left unchanged.
+    ///
+    /// DIFF NOTE: Not present in rr. See `trace_migration`
+    pub fn open_with_migration<T: AsRef<OsStr>>(maybe_dir: Option<&T>) -> RdResult<TraceReader> {
+        let dir = resolve_trace_name(maybe_dir);
+        trace_migration::migrate_trace(Path::new(&dir), &trace_migration::migrations())?;
+        TraceReader::try_new(Some(&dir))
+    }
+
+    /// DIFF NOTE: Not present in rr. Like `new()` but returns an error instead
+    /// of exiting if the trace is missing, incomplete, of the wrong version or corrupt.
+    pub fn try_new<T: AsRef<OsStr>>(maybe_dir: Option<&T>) -> RdResult<TraceReader> {
+        let mut trace_stream = TraceStream::new(&resolve_trace_name(maybe_dir), 1);
 
         let path = trace_stream.version_path();
         let version_file: File = match File::open(&path) {
             Err(e) => {
-                if errno() == ENOENT {
+                let reason = if errno() == ENOENT {
                     let incomplete_path = trace_stream.incomplete_version_path();
                     if access(incomplete_path.as_os_str(), AccessFlags::F_OK).is_ok() {
-                        eprintln!(
-                            "\nrd: Trace file {:?} found.\n\
-                             rd recording terminated abnormally and the trace is incomplete: {:?}.\n",
+                        format!(
+                            "Trace file {:?} found.\n\
+                             rd recording terminated abnormally and the trace is incomplete: {:?}.",
                             incomplete_path, e
-                        );
+                        )
                     } else {
-                        eprintln!(
-                            "\nrd: Trace file {:?} not found. There is no trace there: {:?}.\n",
-                            path, e
-                        );
+                        format!("Trace file not found. There is no trace there: {:?}.", e)
                     }
                 } else {
-                    eprintln!("\nrd: Trace file {:?} not readable: {:?}\n", path, e);
-                }
-                exit(EX_DATAERR as i32);
+                    format!("Trace file not readable: {:?}", e)
+                };
+                return Err(RdError::trace_corrupted(path, reason));
             }
             Ok(f) => f,
         };
         let mut version_str = String::new();
         let mut buf_reader = BufReader::new(version_file);
-        let res = buf_reader.read_line(&mut version_str);
-        match res {
-            Err(e) => {
-                eprintln!("Could not read from the version file {:?}: {:?}", path, e);
-                exit(EX_DATAERR as i32);
-            }
-            Ok(_) => (),
+        if let Err(e) = buf_reader.read_line(&mut version_str) {
+            return Err(RdError::trace_corrupted(
+                path,
+                format!("Could not read from the version file: {:?}", e),
+            ));
         }
 
-        let maybe_version = version_str.trim().parse::<u32>();
+        // DIFF NOTE: rr only has the version on this line
+        let mut version_words = version_str.split_whitespace();
+        let maybe_version = version_words.next().unwrap_or("").parse::<u32>();
         let version: u32 = match maybe_version {
             Ok(ver) => ver,
             Err(e) => {
-                fatal!(
-                    "Could not successfully parse version file {:?}: {:?}",
+                return Err(RdError::trace_corrupted(
                     path,
-                    e
-                );
+                    format!("Could not successfully parse version file: {:?}", e),
+                ));
             }
         };
 
         if TRACE_VERSION != version {
-            eprintln!(
-                "\nrd: error: Recorded trace {:?} has an incompatible version {}; expected\n\
-                 {}.  Did you record {:?} with an older version of rd?  If so,\n\
-                 you'll need to replay {:?} with that older version.  Otherwise,\n\
-                 your trace is likely corrupted.\n",
-                path, version, TRACE_VERSION, path, path
-            );
-            exit(EX_DATAERR as i32);
+            return Err(RdError::IncompatibleTraceVersion {
+                found: version,
+                required: TRACE_VERSION,
+            });
+        }
+
+        if version_words.next() == Some(ZSTD_VERSION_MARKER) {
+            // The level only matters when compressing
+            trace_stream = trace_stream.with_compression(0);
+        }
+
+        let mut readers: HashMap<Substream, CompressedReader> = HashMap::new();
+        for &s in SUBSTREAMS.iter() {
+            let mut reader = CompressedReader::new(&trace_stream.path(s));
+            if s == Substream::RawData {
+                // Raw data is read in the same order as the frames that refer
+                // to it, so decompress the next few blocks while the current
+                // frame is being replayed.
+                reader.set_prefetch_depth(substream(s).threads);
+            }
+            readers.insert(s, reader);
         }
 
         let maybe_res = read_message(&mut buf_reader, ReaderOptions::new());
         let header_msg = match maybe_res {
             Ok(res) => res,
             Err(e) => {
-                fatal!("Could not read version file {:?}: {:?}", path, e);
+                return Err(RdError::trace_corrupted(
+                    path,
+                    format!("Could not read version file: {:?}", e),
+                ));
             }
         };
+        let corrupt_header =
+            |e: capnp::Error| RdError::trace_corrupted(&path, format!("Invalid header: {:?}", e));
 
-        let header = header_msg.get_root::<header::Reader>().unwrap();
+        let header = header_msg
+            .get_root::<header::Reader>()
+            .map_err(corrupt_header)?;
         let bind_to_cpu = header.get_bind_to_cpu();
-        debug_assert!(bind_to_cpu >= 0);
         // DIFF NOTE: In rd the bound cpu is Option<u32>.
         // In rr it is signed with -1 denoting unbound.
         trace_stream.bind_to_cpu = if bind_to_cpu == -1 {
@@ -681,13 +1220,19 @@ impl TraceReader {
         } else if bind_to_cpu >= 0 {
             Some(bind_to_cpu as u32)
         } else {
-            fatal!("Unexpected value of `{}` for bound cpu", bind_to_cpu);
+            return Err(RdError::trace_corrupted(
+                &path,
+                format!("Unexpected value of `{}` for bound cpu", bind_to_cpu),
+            ));
         };
         let trace_uses_cpuid_faulting = header.get_has_cpuid_faulting();
-        let cpuid_records_bytes = header.get_cpuid_records().unwrap();
+        let cpuid_records_bytes = header.get_cpuid_records().map_err(corrupt_header)?;
         let len = cpuid_records_bytes.len() / size_of::<CPUIDRecord>();
         if cpuid_records_bytes.len() != len * size_of::<CPUIDRecord>() {
-            fatal!("Invalid CPUID records length");
+            return Err(RdError::trace_corrupted(
+                &path,
+                "Invalid CPUID records length",
+            ));
         }
         let mut cpuid_records_: Vec<CPUIDRecord> = Vec::with_capacity(len);
         cpuid_records_.resize(len, Default::default());
@@ -700,18 +1245,22 @@ impl TraceReader {
         }
         let xcr0_ = header.get_xcr0();
         let preload_thread_locals_recorded_ = header.get_preload_thread_locals_recorded();
-        let ticks_semantics_ = from_trace_ticks_semantics(header.get_ticks_semantics().unwrap());
-        let uuid_from_trace = header.get_uuid().unwrap();
+        let ticks_semantics_ = from_trace_ticks_semantics(
+            header
+                .get_ticks_semantics()
+                .map_err(|e| RdError::trace_corrupted(&path, format!("{:?}", e)))?,
+        );
+        let uuid_from_trace = header.get_uuid().map_err(corrupt_header)?;
         let mut uuid_ = TraceUuid::zero();
         if uuid_from_trace.len() != uuid_.bytes.len() {
-            fatal!("Invalid UUID length");
+            return Err(RdError::trace_corrupted(&path, "Invalid UUID length"));
         }
         uuid_.bytes = uuid_from_trace.try_into().unwrap();
 
         // Set the global time at 0, so that when we tick it for the first
         // event, it matches the initial global time at recording, 1.
         trace_stream.global_time = 0;
-        TraceReader {
+        Ok(TraceReader {
             trace_stream,
             xcr0_,
             readers,
@@ -722,7 +1271,10 @@ impl TraceReader {
             preload_thread_locals_recorded_,
             monotonic_time_: 0.0,
             raw_recs: vec![],
-        }
+            frame_read_times: VecDeque::with_capacity(REPLAY_RATE_WINDOW),
+            total_frame_count: Cell::new(None),
+            frame_index: None,
+        })
     }
 
     pub fn cpuid_records(&self) -> &[CPUIDRecord] {
@@ -777,11 +1329,9 @@ impl TraceReader {
     }
 }
 
-fn from_trace_arch(arch: TraceArch) -> SupportedArch {
-    match arch {
-        TraceArch::X86 => SupportedArch::X86,
-        TraceArch::X8664 => SupportedArch::X64,
-    }
+fn write_jsonl<T: Serialize>(out: &mut dyn Write, record: &T) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, record)?;
+    out.write_all(b"\n")
 }
 
 fn from_trace_disposition(disposition: TraceSignalDisposition) -> SignalResolvedDisposition {
@@ -847,6 +1397,22 @@ fn from_trace_ticks_semantics(semantics: TraceTicksSemantics) -> TicksSemantics
     }
 }
 
+/// `frame_read_times` are the times at which consecutive frames were read.
+fn estimate_remaining_time(
+    frame_read_times: &VecDeque<Instant>,
+    remaining_frames: FrameTime,
+) -> Option<Duration> {
+    let (first, last) = (frame_read_times.front()?, frame_read_times.back()?);
+    if frame_read_times.len() < 2 {
+        return None;
+    }
+    let secs_per_frame =
+        last.duration_since(*first).as_secs_f64() / (frame_read_times.len() - 1) as f64;
+    Some(Duration::from_secs_f64(
+        secs_per_frame * remaining_frames as f64,
+    ))
+}
+
 fn i32_to_tid(tid: i32) -> pid_t {
     if tid <= 0 {
         fatal!("Invalid tid");
@@ -877,3 +1443,266 @@ fn resolve_trace_name<T: AsRef<OsStr>>(maybe_trace_name: Option<&T>) -> OsString
 
     trace_name.to_os_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::test_util::{write_test_trace, TempTraceDir};
+
+    #[test]
+    fn estimate_remaining_time_test() {
+        let start = Instant::now();
+        let times: VecDeque<Instant> = (0..11u64)
+            .map(|i| start + Duration::from_millis(10 * i))
+            .collect();
+        let estimate = estimate_remaining_time(&times, 50).unwrap();
+        // 10ms per frame
+        assert!(estimate > Duration::from_millis(499));
+        assert!(estimate < Duration::from_millis(501));
+
+        assert_eq!(
+            Some(Duration::from_secs(0)),
+            estimate_remaining_time(&times, 0)
+        );
+        assert_eq!(None, estimate_remaining_time(&VecDeque::new(), 50));
+    }
+
+    #[test]
+    fn trim_test() {
+        let base = TempTraceDir::new("trim");
+        let src = base.join("src");
+        write_test_trace(&src, 5, substream(Substream::Events).block_size, 0);
+        let reader = TraceReader::new(Some(&src));
+        let dest = base.join("dest");
+        assert!(reader.trim(dest.as_os_str(), 3, 2).is_err());
+        reader.trim(dest.as_os_str(), 2, 4).unwrap();
+
+        let mut trimmed = TraceReader::new(Some(&dest));
+        assert_ne!(reader.uuid().bytes, trimmed.uuid().bytes);
+        for t in 2..=4u8 {
+            let frame = trimmed.read_frame();
+            assert_eq!((t - 1) as FrameTime, frame.time());
+            assert_eq!(vec![t; t as usize], trimmed.read_raw_data().data);
+        }
+        assert!(trimmed.at_end());
+
+        // Mmaps and task events up to time 4 are kept, rebased to the new start
+        let mut mmaps = CompressedReader::new(&trimmed.path(Substream::Mmaps));
+        let mut times = Vec::new();
+        while !mmaps.at_end() {
+            let msg = read_message(&mut mmaps, ReaderOptions::new()).unwrap();
+            times.push(msg.get_root::<m_map::Reader>().unwrap().get_frame_time());
+        }
+        assert_eq!(vec![1, 1, 2, 3], times);
+        let mut time = 0;
+        let mut task_times = Vec::new();
+        while trimmed.read_task_event(Some(&mut time)).is_some() {
+            task_times.push(time);
+        }
+        assert_eq!(vec![1, 1, 2, 3], task_times);
+    }
+
+    #[test]
+    fn diff_test() {
+        let dir = TempTraceDir::new("diff");
+        let block_size = substream(Substream::Events).block_size;
+        write_test_trace(&dir.join("a"), 3, block_size, 0);
+        write_test_trace(&dir.join("b"), 3, block_size, 0);
+        write_test_trace(&dir.join("c"), 5, block_size, 0);
+
+        let mut a = TraceReader::new(Some(&dir.join("a")));
+        let mut b = TraceReader::new(Some(&dir.join("b")));
+        let mut c = TraceReader::new(Some(&dir.join("c")));
+        let mut out = Vec::new();
+        assert_eq!(None, TraceStream::diff(&mut a, &mut b, &mut out).unwrap());
+        assert_eq!(
+            "traces are identical (3 frames)\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        assert_eq!(
+            Some(4),
+            TraceStream::diff(&mut a, &mut c, &mut out).unwrap()
+        );
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("frame 4: first trace ended, second trace continues with"));
+        // Readers are rewound so the result doesn't depend on earlier reads
+        let mut out = Vec::new();
+        assert_eq!(
+            Some(4),
+            TraceStream::diff(&mut c, &mut b, &mut out).unwrap()
+        );
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("frame 4: second trace ended"));
+    }
+
+    #[test]
+    fn export_jsonl_test() {
+        let dir = TempTraceDir::new("export");
+        write_test_trace(&dir, 3, substream(Substream::Events).block_size, 0);
+
+        let mut reader = TraceReader::new(Some(&dir));
+        let mut out = Vec::new();
+        reader.export_jsonl(&mut out).unwrap();
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let kinds: Vec<&str> = lines.iter().map(|l| l["kind"].as_str().unwrap()).collect();
+        assert_eq!(
+            vec!["frame", "frame", "frame", "mmap", "mmap", "mmap", "task", "task", "task"],
+            kinds
+        );
+
+        assert_eq!(2, lines[1]["time"]);
+        assert_eq!(100, lines[1]["tid"]);
+        assert_eq!("SCHED", lines[1]["event_type"]);
+        assert_eq!(serde_json::json!([2]), lines[1]["raw_data_sizes"]);
+        assert!(lines[1]["registers"]["eip"].is_u64());
+        assert_eq!(3, lines[5]["time"]);
+        assert_eq!("zero", lines[5]["source"]);
+        assert_eq!("exit", lines[8]["event"]);
+        assert_eq!(0, lines[8]["exit_status"]);
+        assert!(lines[8].get("parent_tid").is_none());
+    }
+
+    #[test]
+    fn peek_frame_keeps_raw_data_test() {
+        let dir = TempTraceDir::new("peek-raw");
+        write_test_trace(&dir, 3, substream(Substream::Events).block_size, 0);
+
+        let mut reader = TraceReader::new(Some(&dir));
+        reader.read_frame();
+        // Peeking must not lose the raw data of the current frame
+        assert_eq!(2, reader.peek_frame().unwrap().time());
+        assert_eq!(vec![1u8], reader.read_raw_data().data);
+        assert!(reader.read_raw_data_for_frame().is_none());
+        reader.read_frame();
+        assert_eq!(vec![2u8; 2], reader.read_raw_data().data);
+    }
+
+    #[test]
+    fn seek_to_time_test() {
+        let dir = TempTraceDir::new("seek");
+        // Small blocks so that the index entries point into different blocks
+        write_test_trace(&dir, 20, 64, 4);
+
+        let mut reader = TraceReader::new(Some(&dir));
+        let index = reader.read_frame_index().unwrap();
+        assert_eq!(
+            vec![5, 9, 13, 17],
+            index.iter().map(|entry| entry.time).collect::<Vec<_>>()
+        );
+        assert!(index[3].positions[Substream::RawData as usize].block_offset > 0);
+
+        for &t in &[11, 3, 20, 17, 1, 18] {
+            reader.seek_to_time(t).unwrap();
+            let frame = reader.read_frame();
+            assert_eq!(t, frame.time());
+            assert_eq!(vec![t as u8; t as usize], reader.read_raw_data().data);
+            let mut time = 0;
+            reader.read_task_event(Some(&mut time)).unwrap();
+            assert_eq!(t, time);
+        }
+
+        reader.seek_to_time(21).unwrap();
+        assert!(reader.at_end());
+        assert!(reader.seek_to_time(22).is_err());
+        assert!(reader.seek_to_time(0).is_err());
+    }
+
+    #[test]
+    fn prefetch_raw_data_test() {
+        let dir = TempTraceDir::new("prefetch");
+        // Many small blocks so the prefetch queue fills up
+        write_test_trace(&dir, 30, 16, 0);
+
+        let mut reader = TraceReader::new(Some(&dir));
+        for t in 1..=10u8 {
+            reader.read_frame();
+            assert_eq!(vec![t; t as usize], reader.read_raw_data().data);
+        }
+
+        // A clone continues from the same position independently
+        let mut clone = reader.clone();
+        for t in 11..=30u8 {
+            reader.read_frame();
+            assert_eq!(vec![t; t as usize], reader.read_raw_data().data);
+        }
+        assert!(reader.at_end());
+        clone.read_frame();
+        assert_eq!(vec![11; 11], clone.read_raw_data().data);
+
+        reader.rewind().unwrap();
+        reader.read_frame();
+        assert_eq!(vec![1], reader.read_raw_data().data);
+    }
+
+    #[test]
+    fn rewind_test() {
+        let dir = TempTraceDir::new("rewind");
+        write_test_trace(&dir, 8, 32, 0);
+
+        let read_all = |reader: &mut TraceReader| {
+            let mut records = Vec::new();
+            while !reader.at_end() {
+                let time = reader.read_frame().time();
+                let data = reader.read_raw_data().data;
+                let mut task_time = 0;
+                reader.read_task_event(Some(&mut task_time)).unwrap();
+                records.push((time, data, task_time));
+            }
+            records
+        };
+
+        let mut reader = TraceReader::new(Some(&dir));
+        let first = read_all(&mut reader);
+        assert_eq!(8, first.len());
+        reader.rewind().unwrap();
+        assert_eq!(0, reader.time());
+        assert_eq!(first, read_all(&mut reader));
+
+        // The raw data of a partially read frame must not leak past a rewind
+        reader.rewind().unwrap();
+        reader.read_frame();
+        reader.rewind().unwrap();
+        assert!(reader.read_raw_data_metadata_for_frame().is_none());
+        assert_eq!(first, read_all(&mut reader));
+    }
+
+    #[test]
+    fn open_with_migration_test() {
+        let dir = TempTraceDir::new("old-version");
+        write_test_trace(&dir, 2, 64, 0);
+        assert!(TraceReader::open_with_migration(Some(&dir)).is_ok());
+
+        let version_path = dir.join("version");
+        let contents = std::fs::read(&version_path).unwrap();
+        let header = &contents[find(&contents, b"\n").unwrap()..];
+        let with_version = |version: u32| {
+            let mut bytes = format!("{}", version).into_bytes();
+            bytes.extend_from_slice(header);
+            bytes
+        };
+
+        // 85 and 86 traces only differ in what the reader already handles
+        std::fs::write(&version_path, with_version(85)).unwrap();
+        assert!(TraceReader::open_with_migration(Some(&dir)).is_ok());
+        assert_eq!(contents, std::fs::read(&version_path).unwrap());
+
+        let old = with_version(84);
+        std::fs::write(&version_path, &old).unwrap();
+        match TraceReader::open_with_migration(Some(&dir)) {
+            Err(RdError::IncompatibleTraceVersion { found, required }) => {
+                assert_eq!(84, found);
+                assert_eq!(TRACE_VERSION, required);
+            }
+            _ => panic!("Expected IncompatibleTraceVersion"),
+        }
+        assert_eq!(old, std::fs::read(&version_path).unwrap());
+    }
+}
@@ -1,27 +1,59 @@
 use crate::{
     kernel_abi::SupportedArch,
+    log::LogLevel::LogWarn,
+    registers::Registers,
     remote_ptr::{RemotePtr, Void},
     taskish_uid::TaskUid,
-    trace::trace_frame::FrameTime,
-    trace_capnp::Arch as TraceArch,
+    trace::{
+        compressed_reader::{BlockChecksumMismatch, CompressedReader},
+        compressed_writer::{CompressedWriter, Compression, StreamPosition, ZSTD_EXTENSION},
+        trace_frame::FrameTime,
+        trace_reader::TraceReader,
+        trace_task_event::TraceTaskEvent,
+    },
+    trace_capnp::{frame, m_map, task_event, Arch as TraceArch, SyscallState as TraceSyscallState},
     util::{dir_exists, ensure_dir, get_num_cpus, real_path},
 };
-use libc::{pid_t, EEXIST};
+use capnp::{
+    message::{self, ReaderOptions},
+    serialize_packed::{read_message, write_message},
+};
+use crc32fast::Hasher;
+use libc::{pid_t, rusage, timeval, EEXIST};
 use nix::{errno::errno, sys::stat::Mode, unistd::mkdir};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    cmp::min,
+    cmp::{max, min},
+    collections::HashMap,
     env,
     ffi::{OsStr, OsString},
-    io::Write,
+    fs::{create_dir_all, read, read_dir, read_to_string, remove_file, rename, write, File},
+    io,
+    io::{Read, Seek, SeekFrom, Write},
+    iter,
     os::unix::ffi::{OsStrExt, OsStringExt},
-    path::Path,
+    path::{Path, PathBuf},
     slice::Iter,
 };
 
-pub const TRACE_VERSION: u32 = 85;
+/// DIFF NOTE: 86 adds the optional compression marker in the version file.
+/// 87 adds per-block checksums to the substreams.
+pub const TRACE_VERSION: u32 = 87;
+
+/// Follows TRACE_VERSION in the version file when the substreams are
+/// compressed with zstd.
+pub const ZSTD_VERSION_MARKER: &str = "zstd";
 
 pub const SUBSTREAM_COUNT: usize = 4;
 
+/// By default a frame index entry is written for every this many frames.
+/// See `TraceWriter::set_frame_index_interval()`
+pub const DEFAULT_FRAME_INDEX_INTERVAL: u64 = 1000;
+
+/// Size of a `FrameIndexEntry` in the frame index file
+const FRAME_INDEX_ENTRY_SIZE: usize = 8 + SUBSTREAM_COUNT * 16;
+
 /// Update `substreams` and TRACE_VERSION when you update this list.
 #[repr(usize)]
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
@@ -51,22 +83,26 @@ pub(super) static mut SUBSTREAMS_DATA: [SubstreamData; SUBSTREAM_COUNT] = [
         name: "events",
         block_size: 1024 * 1024,
         threads: 1,
+        checksum_enabled: true,
     },
     SubstreamData {
         name: "data",
         block_size: 1024 * 1024,
         // Will be set later. See the substream() fn.
         threads: 0,
+        checksum_enabled: true,
     },
     SubstreamData {
         name: "mmaps",
         block_size: 64 * 1024,
         threads: 1,
+        checksum_enabled: true,
     },
     SubstreamData {
         name: "tasks",
         block_size: 64 * 1024,
         threads: 1,
+        checksum_enabled: true,
     },
 ];
 
@@ -89,6 +125,9 @@ pub(super) struct SubstreamData {
     pub(super) name: &'static str,
     pub(super) block_size: usize,
     pub(super) threads: usize,
+    /// Append a SHA-256 checksum to every block. See `TraceStream::verify_integrity()`
+    /// DIFF NOTE: Not present in rr
+    pub(super) checksum_enabled: bool,
 }
 
 /// For REMAP_MAPPING maps, the memory contents are preserved so we don't
@@ -106,6 +145,135 @@ impl Default for MappedDataSource {
     }
 }
 
+/// Per-task resource usage stored by `TraceStream::write_resource_usage()`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub utime_us: u64,
+    pub stime_us: u64,
+    pub maxrss_kb: u64,
+    pub inblock: u64,
+    pub oublock: u64,
+}
+
+impl From<&rusage> for ResourceUsage {
+    fn from(usage: &rusage) -> Self {
+        let to_us = |tv: &timeval| tv.tv_sec as u64 * 1_000_000 + tv.tv_usec as u64;
+        ResourceUsage {
+            utime_us: to_us(&usage.ru_utime),
+            stime_us: to_us(&usage.ru_stime),
+            maxrss_kb: usage.ru_maxrss as u64,
+            inblock: usage.ru_inblock as u64,
+            oublock: usage.ru_oublock as u64,
+        }
+    }
+}
+
+/// A file-backed mapping whose contents differ from those at record time.
+/// See `TraceStream::validate_mmap_checksums()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MmapChecksumMismatch {
+    pub filename: OsString,
+    pub expected: [u8; 32],
+    pub actual: [u8; 32],
+}
+
+/// A block of a substream whose checksum does not match its contents.
+/// See `TraceStream::verify_integrity()`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceIntegrityError {
+    pub substream: &'static str,
+    /// Index of the block in the substream file, starting from 0
+    pub block: u64,
+    pub expected: [u8; 32],
+    pub actual: [u8; 32],
+}
+
+impl std::fmt::Display for TraceIntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Block {} of substream `{}` is corrupt: expected checksum {:02x?}, got {:02x?}",
+            self.block, self.substream, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for TraceIntegrityError {}
+
+/// Key-value annotations attached to a trace, e.g. a description of the bug
+/// being investigated or the environment the trace was recorded in.
+///
+/// Stored as a flat JSON object of strings in the "metadata.json" file of the
+/// trace directory. Unlike the substreams this format does not depend on
+/// `TRACE_VERSION`, so any version of rd can read (and add to) the metadata
+/// of any trace. Keys starting with "rd." are reserved for rd itself, see
+/// `TraceWriter::close()`.
+/// DIFF NOTE: Not present in rr
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TraceMetadata {
+    entries: HashMap<String, String>,
+}
+
+impl TraceMetadata {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(|value| value.as_str())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.entries.insert(key.to_owned(), value.to_owned());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// An empty `TraceMetadata` if `path` does not exist.
+    fn read(path: &Path) -> io::Result<TraceMetadata> {
+        match read_to_string(path) {
+            Ok(serialized) => Ok(serde_json::from_str(&serialized)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(TraceMetadata::default()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// An entry of the "frame_index" file of the trace directory.
+/// See `TraceReader::seek_to_time()`
+/// DIFF NOTE: Not present in rr
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FrameIndexEntry {
+    /// The frame that comes next when the substreams are at `positions`
+    pub time: FrameTime,
+    /// The position of each substream, indexed by `Substream as usize`
+    pub positions: [StreamPosition; SUBSTREAM_COUNT],
+}
+
+/// An entry in the "mmap_checksums.json" file of the trace directory.
+/// `filename` is the backing file name as stored in the Mmaps substream.
+/// `offset` and `size` are the part of the file that is mapped.
+#[derive(Serialize, Deserialize)]
+struct MmapChecksum {
+    filename: Vec<u8>,
+    offset: u64,
+    size: usize,
+    sha256: [u8; 32],
+}
+
+/// The checksums `TraceWriter` collects while recording, keyed by
+/// (backing file name, file offset, mapped size).
+/// See `TraceStream::write_mmap_checksums()`
+pub type MmapChecksums = HashMap<(OsString, u64, usize), [u8; 32]>;
+
+/// Result of `TraceStream::compare_substreams()`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SubstreamComparison {
+    pub identical: bool,
+    /// Offset of the first byte that differs. If one file is a prefix of the
+    /// other this is the length of the shorter file.
+    pub first_diff_offset: Option<u64>,
+}
+
 /// TraceStream stores all the data common to both recording and
 /// replay.  TraceWriter deals with recording-specific logic, and
 /// TraceReader handles replay-specific details.
@@ -135,17 +303,763 @@ impl TraceStream {
         OsString::from_vec(ss)
     }
 
+    /// Determine the architecture a trace was recorded with from the arch of
+    /// the first frame in its Events substream.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn infer_arch(trace_dir: &OsStr) -> io::Result<SupportedArch> {
+        let mut events_path = Path::new(trace_dir).join(substream(Substream::Events).name);
+        if !events_path.is_file() {
+            events_path.set_extension(ZSTD_EXTENSION);
+        }
+        if !events_path.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No events file found at {:?}", events_path),
+            ));
+        }
+
+        let mut reader = CompressedReader::new(events_path.as_os_str());
+        if reader.at_end() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Trace {:?} does not contain any frames", trace_dir),
+            ));
+        }
+        let msg = read_message(&mut reader, ReaderOptions::new()).map_err(to_io_error)?;
+        let frame = msg.get_root::<frame::Reader>().map_err(to_io_error)?;
+        let arch = frame.get_arch().map_err(|e| to_io_error(e.into()))?;
+        Ok(from_trace_arch(arch))
+    }
+
+    /// Record the resource usage of task `tid` in the "rusage.json" file of
+    /// the trace directory. Any earlier entry for `tid` is replaced.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn write_resource_usage(&mut self, tid: pid_t, usage: &rusage) -> io::Result<()> {
+        let mut all_usage = self.read_resource_usage()?;
+        all_usage.insert(tid, ResourceUsage::from(usage));
+        let serialized = serde_json::to_string(&all_usage)?;
+        write(self.resource_usage_path(), serialized)
+    }
+
+    /// Resource usage of each task written by `write_resource_usage()`.
+    /// Returns an empty map if none was written.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn read_resource_usage(&self) -> io::Result<HashMap<pid_t, ResourceUsage>> {
+        match read_to_string(self.resource_usage_path()) {
+            Ok(serialized) => Ok(serde_json::from_str(&serialized)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save a copy of the contents of mapped file `filename` in the trace
+    /// directory so the mapping can be replayed even if the file is deleted.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn write_mmap_file_contents(&mut self, filename: &OsStr, data: &[u8]) -> io::Result<()> {
+        write(self.mmap_file_contents_path(filename), data)
+    }
+
+    /// Contents of `filename` saved by `write_mmap_file_contents()`, if any.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn retrieve_mmap_file_contents(&self, filename: &OsStr) -> io::Result<Option<Vec<u8>>> {
+        match read(self.mmap_file_contents_path(filename)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The copy is keyed by a hash of the full path of `filename`. The file
+    /// name is kept too, to make the trace directory easier to inspect.
+    fn mmap_file_contents_path(&self, filename: &OsStr) -> PathBuf {
+        let mut hasher = Hasher::new();
+        hasher.update(filename.as_bytes());
+        let mut name: Vec<u8> = Vec::new();
+        write!(name, "mmap_contents_{:08x}_", hasher.finalize()).unwrap();
+        if let Some(file_name) = Path::new(filename).file_name() {
+            name.extend_from_slice(file_name.as_bytes());
+        }
+        Path::new(&self.trace_dir).join(OsStr::from_bytes(&name))
+    }
+
+    /// SHA-256 of the `size` bytes at `offset` of the mapped file
+    /// `backing_file_name`, i.e. of the part of the file a mapping covers. A
+    /// relative `backing_file_name` is relative to the trace directory, like in
+    /// the Mmaps substream. Bytes past the end of the file are not hashed.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn mmap_checksum(
+        &self,
+        backing_file_name: &OsStr,
+        offset: u64,
+        size: usize,
+    ) -> io::Result<[u8; 32]> {
+        sha256_of_file_region(
+            &self.resolve_backing_file_name(backing_file_name.as_bytes()),
+            offset,
+            size,
+        )
+    }
+
+    /// Store `checksums` (see `mmap_checksum()`) so `validate_mmap_checksums()`
+    /// can later detect whether the mapped files changed. Replaces any
+    /// checksums stored before.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn write_mmap_checksums(&self, checksums: &MmapChecksums) -> io::Result<()> {
+        let mut entries: Vec<MmapChecksum> = checksums
+            .iter()
+            .map(|((filename, offset, size), &sha256)| MmapChecksum {
+                filename: filename.as_bytes().to_vec(),
+                offset: *offset,
+                size: *size,
+                sha256,
+            })
+            .collect();
+        entries
+            .sort_by(|a, b| (&a.filename, a.offset, a.size).cmp(&(&b.filename, b.offset, b.size)));
+        let serialized = serde_json::to_string(&entries)?;
+        write(self.mmap_checksums_path(), serialized)
+    }
+
+    /// Check every file-backed mapping in the Mmaps substream that has a
+    /// checksum stored by `write_mmap_checksums()` against the current contents
+    /// of the part of its file that it maps. Mappings without a stored checksum
+    /// are not checked.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn validate_mmap_checksums(&self) -> io::Result<Vec<MmapChecksumMismatch>> {
+        let checksums: HashMap<(Vec<u8>, u64, usize), [u8; 32]> = self
+            .read_mmap_checksums()?
+            .into_iter()
+            .map(|c| ((c.filename, c.offset, c.size), c.sha256))
+            .collect();
+        let mut mismatches = Vec::new();
+        if checksums.is_empty() {
+            return Ok(mismatches);
+        }
+
+        let mut reader = CompressedReader::new(&self.path(Substream::Mmaps));
+        while !reader.at_end() {
+            let msg = read_message(&mut reader, ReaderOptions::new()).map_err(to_io_error)?;
+            let map = msg.get_root::<m_map::Reader>().map_err(to_io_error)?;
+            let file = match map.get_source().which() {
+                Ok(m_map::source::File(file)) => file,
+                _ => continue,
+            };
+            let backing_file_name = file.get_backing_file_name().map_err(to_io_error)?;
+            // The data offset of a file-backed mapping is its file offset.
+            // See TraceReader::read_mapped_region()
+            let offset = max(map.get_file_offset_bytes(), 0) as u64;
+            let size = map.get_end().saturating_sub(map.get_start()) as usize;
+            let expected = match checksums.get(&(backing_file_name.to_vec(), offset, size)) {
+                Some(&expected) => expected,
+                None => continue,
+            };
+            let filename = self.resolve_backing_file_name(backing_file_name);
+            let actual = sha256_of_file_region(&filename, offset, size)?;
+            if actual != expected {
+                mismatches.push(MmapChecksumMismatch {
+                    filename: filename.into_os_string(),
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        Ok(mismatches)
+    }
+
+    /// Walk every block of every substream and check it against the checksum
+    /// stored after it. Blocks written without a checksum are not checked.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn verify_integrity(&self) -> io::Result<Vec<TraceIntegrityError>> {
+        let mut errors = Vec::new();
+        for &s in Substream::iter() {
+            let path = self.path(s);
+            if !Path::new(&path).exists() {
+                continue;
+            }
+            let reader = CompressedReader::new(&path);
+            for BlockChecksumMismatch {
+                block,
+                expected,
+                actual,
+            } in reader.checksum_mismatches()?
+            {
+                errors.push(TraceIntegrityError {
+                    substream: substream(s).name,
+                    block,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        Ok(errors)
+    }
+
+    /// The frame index file is a sequence of `FrameIndexEntry`s in increasing
+    /// time order, each stored as little-endian u64s: the time followed by the
+    /// block offset and offset in block of each substream.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub(super) fn write_frame_index(&self, entries: &[FrameIndexEntry]) -> io::Result<()> {
+        let mut data: Vec<u8> = Vec::with_capacity(entries.len() * FRAME_INDEX_ENTRY_SIZE);
+        for entry in entries {
+            data.extend_from_slice(&entry.time.to_le_bytes());
+            for pos in &entry.positions {
+                data.extend_from_slice(&pos.block_offset.to_le_bytes());
+                data.extend_from_slice(&pos.offset_in_block.to_le_bytes());
+            }
+        }
+        write(self.frame_index_path(), data)
+    }
+
+    /// See `write_frame_index()`.
+    /// Returns an error of kind `NotFound` if the trace has no frame index.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn read_frame_index(&self) -> io::Result<Vec<FrameIndexEntry>> {
+        let data = read(self.frame_index_path())?;
+        if data.len() % FRAME_INDEX_ENTRY_SIZE != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Truncated frame index {:?}", self.frame_index_path()),
+            ));
+        }
+
+        let u64_at = |bytes: &[u8], i: usize| {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&bytes[8 * i..8 * i + 8]);
+            u64::from_le_bytes(word)
+        };
+        let mut entries = Vec::with_capacity(data.len() / FRAME_INDEX_ENTRY_SIZE);
+        for bytes in data.chunks_exact(FRAME_INDEX_ENTRY_SIZE) {
+            let mut entry = FrameIndexEntry {
+                time: u64_at(bytes, 0),
+                ..Default::default()
+            };
+            for (i, pos) in entry.positions.iter_mut().enumerate() {
+                pos.block_offset = u64_at(bytes, 1 + 2 * i);
+                pos.offset_in_block = u64_at(bytes, 2 + 2 * i);
+            }
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    fn frame_index_path(&self) -> PathBuf {
+        Path::new(&self.trace_dir).join("frame_index")
+    }
+
+    /// The value stored under `key` in the trace metadata, if any.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn get_metadata(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key)
+    }
+
+    /// Store `value` under `key` in the trace metadata, replacing any earlier
+    /// value. Only written to the trace directory by `flush_metadata()`
+    /// (which `TraceWriter::close()` calls).
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn set_metadata(&mut self, key: &str, value: &str) {
+        self.metadata.set(key, value);
+    }
+
+    /// DIFF NOTE: Not present in rr
+    pub fn metadata(&self) -> &TraceMetadata {
+        &self.metadata
+    }
+
+    /// DIFF NOTE: Not present in rr
+    pub fn metadata_mut(&mut self) -> &mut TraceMetadata {
+        &mut self.metadata
+    }
+
+    /// Write the trace metadata to the "metadata.json" file of the trace
+    /// directory. The file is replaced atomically so readers never see a
+    /// partially written file.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn flush_metadata(&self) -> io::Result<()> {
+        let path = self.metadata_path();
+        let tmp_path = path.with_extension("json.tmp");
+        write(&tmp_path, serde_json::to_string_pretty(&self.metadata)?)?;
+        rename(&tmp_path, &path)
+    }
+
+    fn metadata_path(&self) -> PathBuf {
+        Path::new(&self.trace_dir).join("metadata.json")
+    }
+
+    fn read_mmap_checksums(&self) -> io::Result<Vec<MmapChecksum>> {
+        match read_to_string(self.mmap_checksums_path()) {
+            Ok(serialized) => Ok(serde_json::from_str(&serialized)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn resolve_backing_file_name(&self, backing_file_name: &[u8]) -> PathBuf {
+        // Path::join() keeps an absolute `backing_file_name` as is.
+        Path::new(&self.trace_dir).join(OsStr::from_bytes(backing_file_name))
+    }
+
+    fn mmap_checksums_path(&self) -> PathBuf {
+        Path::new(&self.trace_dir).join("mmap_checksums.json")
+    }
+
+    /// Store `data` under `key` in the "metadata" directory of the trace as
+    /// `{key}.{type_tag}.bin`. Replaces any blob previously stored under `key`.
+    /// Neither `key` nor `type_tag` may contain '.' or '/'.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn write_binary_metadata(
+        &mut self,
+        key: &str,
+        type_tag: &str,
+        data: &[u8],
+    ) -> io::Result<()> {
+        for s in &[key, type_tag] {
+            if s.is_empty() || s.contains(|c| c == '.' || c == '/') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid binary metadata key or type tag: {:?}", s),
+                ));
+            }
+        }
+        let dir = self.binary_metadata_dir();
+        create_dir_all(&dir)?;
+        if let Some((old_path, _)) = self.find_binary_metadata(key)? {
+            remove_file(old_path)?;
+        }
+        write(dir.join(format!("{}.{}.bin", key, type_tag)), data)
+    }
+
+    /// The type tag and data stored under `key` by `write_binary_metadata()`.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn read_binary_metadata(&self, key: &str) -> io::Result<Option<(String, Vec<u8>)>> {
+        match self.find_binary_metadata(key)? {
+            Some((path, type_tag)) => Ok(Some((type_tag, read(path)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn find_binary_metadata(&self, key: &str) -> io::Result<Option<(PathBuf, String)>> {
+        let entries = match read_dir(self.binary_metadata_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let prefix = format!("{}.", key);
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let maybe_type_tag = file_name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|rest| rest.strip_suffix(".bin"))
+                .filter(|type_tag| !type_tag.is_empty() && !type_tag.contains('.'));
+            if let Some(type_tag) = maybe_type_tag {
+                return Ok(Some((entry.path(), type_tag.to_owned())));
+            }
+        }
+        Ok(None)
+    }
+
+    fn binary_metadata_dir(&self) -> PathBuf {
+        Path::new(&self.trace_dir).join("metadata")
+    }
+
+    fn resource_usage_path(&self) -> PathBuf {
+        Path::new(&self.trace_dir).join("rusage.json")
+    }
+
+    /// Byte-compare the (compressed) files of substream `s` in traces `a` and `b`.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn compare_substreams(
+        a: &TraceStream,
+        b: &TraceStream,
+        s: Substream,
+    ) -> io::Result<SubstreamComparison> {
+        const BLOCK_SIZE: usize = 64 * 1024;
+        let mut file_a = File::open(a.path(s))?;
+        let mut file_b = File::open(b.path(s))?;
+        let mut buf_a = vec![0u8; BLOCK_SIZE];
+        let mut buf_b = vec![0u8; BLOCK_SIZE];
+        let mut offset: u64 = 0;
+        loop {
+            let nread_a = read_block(&mut file_a, &mut buf_a)?;
+            let nread_b = read_block(&mut file_b, &mut buf_b)?;
+            let common = min(nread_a, nread_b);
+            if let Some(i) = (0..common).find(|&i| buf_a[i] != buf_b[i]) {
+                return Ok(SubstreamComparison {
+                    identical: false,
+                    first_diff_offset: Some(offset + i as u64),
+                });
+            }
+            if nread_a != nread_b {
+                return Ok(SubstreamComparison {
+                    identical: false,
+                    first_diff_offset: Some(offset + common as u64),
+                });
+            }
+            if nread_a == 0 {
+                return Ok(SubstreamComparison {
+                    identical: true,
+                    first_diff_offset: None,
+                });
+            }
+            offset += nread_a as u64;
+        }
+    }
+
+    /// Step through the frames of traces `a` and `b` from their start and write
+    /// the differences in the first frame where they diverge to `out`, one per
+    /// line, e.g. "frame 1234: rip differs: 0x4005a0 vs 0x4005b0".
+    /// Frames diverge if their event type, tid or ticks differ or, when both
+    /// frames have registers, `Registers::diff()` reports a difference (so the
+    /// comparison masks of the registers apply). If one trace ends first,
+    /// that is reported as the divergence.
+    ///
+    /// Returns the time of the divergent frame or `None` if the traces have the
+    /// same frames. Both readers are rewound first and are left after the
+    /// divergent frame.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn diff(
+        a: &mut TraceReader,
+        b: &mut TraceReader,
+        out: &mut dyn Write,
+    ) -> io::Result<Option<FrameTime>> {
+        a.rewind()?;
+        b.rewind()?;
+        loop {
+            match (a.at_end(), b.at_end()) {
+                (true, true) => {
+                    writeln!(out, "traces are identical ({} frames)", a.time())?;
+                    return Ok(None);
+                }
+                (true, false) | (false, true) => {
+                    let (ended, other, frame) = if a.at_end() {
+                        ("first", "second", b.read_frame())
+                    } else {
+                        ("second", "first", a.read_frame())
+                    };
+                    writeln!(
+                        out,
+                        "frame {}: {} trace ended, {} trace continues with {}",
+                        frame.time(),
+                        ended,
+                        other,
+                        frame.event()
+                    )?;
+                    return Ok(Some(frame.time()));
+                }
+                (false, false) => (),
+            }
+
+            let frame_a = a.read_frame();
+            let frame_b = b.read_frame();
+            let mut differences: Vec<String> = Vec::new();
+            if frame_a.event().event_type() != frame_b.event().event_type() {
+                differences.push(format!(
+                    "event differs: {} vs {}",
+                    frame_a.event(),
+                    frame_b.event()
+                ));
+            }
+            if frame_a.tid() != frame_b.tid() {
+                differences.push(format!(
+                    "tid differs: {} vs {}",
+                    frame_a.tid(),
+                    frame_b.tid()
+                ));
+            }
+            if frame_a.ticks() != frame_b.ticks() {
+                differences.push(format!(
+                    "ticks differ: {} vs {}",
+                    frame_a.ticks(),
+                    frame_b.ticks()
+                ));
+            }
+            if frame_a.event().record_regs() && frame_b.event().record_regs() {
+                let regs_a = frame_a.regs_ref();
+                let regs_b = frame_b.regs_ref();
+                if regs_a.arch() != regs_b.arch() {
+                    differences.push(format!(
+                        "arch differs: {:?} vs {:?}",
+                        regs_a.arch(),
+                        regs_b.arch()
+                    ));
+                } else {
+                    for d in Registers::diff(regs_a, regs_b) {
+                        differences.push(format!(
+                            "{} differs: {:#x} vs {:#x}",
+                            d.name, d.recorded, d.live
+                        ));
+                    }
+                }
+            }
+
+            if !differences.is_empty() {
+                for d in &differences {
+                    writeln!(out, "frame {}: {}", frame_a.time(), d)?;
+                }
+                return Ok(Some(frame_a.time()));
+            }
+        }
+    }
+
+    /// Return the host path the data for `m` should be read from during replay.
+    /// - `SourceTrace`: The RawData substream file in the trace directory
+    /// - `SourceFile`: `m.filename`, resolved relative to the trace directory
+    ///    if it is not absolute
+    /// - `SourceZero`: `None`, there is no backing data
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn map_data_source_to_file(&self, m: &MappedData) -> Option<PathBuf> {
+        match m.source {
+            MappedDataSource::SourceTrace => Some(PathBuf::from(self.path(Substream::RawData))),
+            MappedDataSource::SourceFile => Some(Path::new(&self.trace_dir).join(&m.filename)),
+            MappedDataSource::SourceZero => None,
+        }
+    }
+
     pub fn mmaps_block_size() -> usize {
         substream(Substream::Mmaps).block_size
     }
 
+    /// Shorten the trace so that its last event is the one at `max_time`.
+    /// The Events, Mmaps and Tasks substreams are rewritten to drop everything
+    /// recorded after `max_time`. Returns the number of frames removed.
+    ///
+    /// The RawData substream is left alone: data belonging to dropped frames is
+    /// simply never read during replay. The frame index is deleted because its
+    /// positions don't survive the rewrite, so seeking falls back to reading
+    /// forward from the start.
+    ///
+    /// Must not be called on a trace that is still being recorded.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn truncate_at_frame(&mut self, max_time: FrameTime) -> io::Result<u64> {
+        // Frames don't record their time explicitly. The first frame in a trace
+        // is at time 1 and every subsequent frame ticks the time by 1.
+        let mut time: FrameTime = 0;
+        let mut frames_removed: u64 = 0;
+        self.rewrite_substream(Substream::Events, |msg| {
+            msg.get_root::<frame::Reader>()?;
+            time += 1;
+            if time > max_time {
+                frames_removed += 1;
+                Ok(false)
+            } else {
+                Ok(true)
+            }
+        })?;
+
+        self.rewrite_substream(Substream::Mmaps, |msg| {
+            let map = msg.get_root::<m_map::Reader>()?;
+            Ok(map.get_frame_time() as u64 <= max_time)
+        })?;
+
+        self.rewrite_substream(Substream::Tasks, |msg| {
+            let task = msg.get_root::<task_event::Reader>()?;
+            Ok(task.get_frame_time() as u64 <= max_time)
+        })?;
+
+        match remove_file(self.frame_index_path()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+
+        if self.global_time > max_time {
+            self.global_time = max_time;
+        }
+
+        Ok(frames_removed)
+    }
+
+    /// Return the time of the frame in which syscall `syscallno` was entered
+    /// for the `nth` time (1-based), or `None` if the trace contains fewer
+    /// than `nth` such entries.
+    ///
+    /// DIFF NOTE: Not present in rr. The syscall arch is not checked so
+    /// `syscallno` should be for the arch the tracee was recorded with.
+    pub fn frame_time_for_syscall_invocation(
+        &self,
+        syscallno: i32,
+        nth: u64,
+    ) -> io::Result<Option<FrameTime>> {
+        if nth == 0 {
+            return Ok(None);
+        }
+
+        let mut reader = CompressedReader::new(&self.path(Substream::Events));
+        let mut time: FrameTime = 0;
+        let mut count: u64 = 0;
+        while !reader.at_end() {
+            let msg = read_message(&mut reader, ReaderOptions::new()).map_err(to_io_error)?;
+            let frame = msg.get_root::<frame::Reader>().map_err(to_io_error)?;
+            // See comment in truncate_at_frame()
+            time += 1;
+            let event = frame.get_event();
+            if let Ok(frame::event::Syscall(sys)) = event.which() {
+                if sys.get_number() == syscallno
+                    && sys.get_state().ok() == Some(TraceSyscallState::Entering)
+                {
+                    count += 1;
+                    if count == nth {
+                        return Ok(Some(time));
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Iterate over the clone and exec events of the Tasks substream from the
+    /// beginning. Exit events are skipped.
+    /// Unlike `TraceReader::read_task_event()` this does not move the position
+    /// of the trace reader.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn stream_tasks_events(&self) -> impl Iterator<Item = io::Result<TraceTaskEvent>> {
+        let mut reader = CompressedReader::new(&self.path(Substream::Tasks));
+        let mut failed = false;
+        iter::from_fn(move || loop {
+            if failed || reader.at_end() {
+                return None;
+            }
+            let res = read_message(&mut reader, ReaderOptions::new())
+                .map_err(to_io_error)
+                .and_then(|msg| read_clone_or_exec_event(&msg));
+            match res {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => continue,
+                Err(e) => {
+                    failed = true;
+                    return Some(Err(e));
+                }
+            }
+        })
+    }
+
+    /// Number of invocations of each syscall number in the trace i.e. the
+    /// number of syscall frames in the Entering state.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn frame_histogram(&self) -> io::Result<HashMap<i32, u64>> {
+        let mut histogram = HashMap::new();
+        let mut reader = CompressedReader::new(&self.path(Substream::Events));
+        while !reader.at_end() {
+            let msg = read_message(&mut reader, ReaderOptions::new()).map_err(to_io_error)?;
+            let frame = msg.get_root::<frame::Reader>().map_err(to_io_error)?;
+            if let Ok(frame::event::Syscall(sys)) = frame.get_event().which() {
+                if sys.get_state().ok() == Some(TraceSyscallState::Entering) {
+                    *histogram.entry(sys.get_number()).or_insert(0) += 1;
+                }
+            }
+        }
+        Ok(histogram)
+    }
+
+    /// The `n` most frequently invoked syscall numbers in the trace along
+    /// with their count, most frequent first. Ties are ordered by syscall number.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn top_n_syscalls(&self, n: usize) -> io::Result<Vec<(i32, u64)>> {
+        let mut counts: Vec<(i32, u64)> = self.frame_histogram()?.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts.truncate(n);
+        Ok(counts)
+    }
+
+    /// Copy every message in substream `s` for which `keep` returns true into a
+    /// fresh file and then atomically replace the substream with it.
+    fn rewrite_substream<F>(&self, s: Substream, mut keep: F) -> io::Result<()>
+    where
+        F: FnMut(&message::Reader<capnp::serialize::OwnedSegments>) -> capnp::Result<bool>,
+    {
+        let path = self.path(s);
+        let mut tmp_path: Vec<u8> = path.clone().into_vec();
+        tmp_path.extend_from_slice(b".truncated");
+        let tmp_path = OsString::from_vec(tmp_path);
+
+        let mut reader = CompressedReader::new(&path);
+        let mut writer = CompressedWriter::with_compression(
+            &tmp_path,
+            substream(s).block_size,
+            substream(s).threads,
+            self.compression,
+            substream(s).checksum_enabled,
+        );
+        while !reader.at_end() {
+            let msg = read_message(&mut reader, ReaderOptions::new()).map_err(to_io_error)?;
+            if !keep(&msg).map_err(to_io_error)? {
+                continue;
+            }
+            let mut copy = message::Builder::new_default();
+            match s {
+                Substream::Events => copy.set_root(msg.get_root::<frame::Reader>().unwrap()),
+                Substream::Mmaps => copy.set_root(msg.get_root::<m_map::Reader>().unwrap()),
+                Substream::Tasks => copy.set_root(msg.get_root::<task_event::Reader>().unwrap()),
+                Substream::RawData => unreachable!("RawData is not a message stream"),
+            }
+            .map_err(to_io_error)?;
+            write_message(&mut writer, &copy).map_err(to_io_error)?;
+        }
+        writer.close(None);
+        if !writer.good() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Unable to write {:?}", tmp_path),
+            ));
+        }
+
+        rename(&tmp_path, &path)
+    }
+
     pub(super) fn new(trace_dir: &OsStr, initial_time: FrameTime) -> TraceStream {
-        TraceStream {
+        let mut stream = TraceStream {
             trace_dir: real_path(trace_dir),
             // @TODO Is this what we want?
             bind_to_cpu: Some(0),
             global_time: initial_time,
+            compression: Compression::Brotli,
+            metadata: TraceMetadata::default(),
+        };
+        let path = stream.metadata_path();
+        match TraceMetadata::read(&path) {
+            Ok(metadata) => stream.metadata = metadata,
+            Err(e) => log!(LogWarn, "Unable to read trace metadata {:?}: {:?}", path, e),
         }
+        stream
+    }
+
+    /// Compress (or expect) all substreams with zstd at `level` instead of
+    /// brotli. The substream files get a ".zst" extension.
+    /// DIFF NOTE: Not present in rr
+    pub fn with_compression(mut self, level: i32) -> Self {
+        self.compression = Compression::Zstd(level);
+        self
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
     }
 
     /// Return the path of the file for the given substream.
@@ -153,6 +1067,10 @@ impl TraceStream {
         let mut path_vec: Vec<u8> = Vec::from(self.trace_dir.as_bytes());
         path_vec.extend_from_slice(b"/");
         path_vec.extend_from_slice(substream(s).name.as_bytes());
+        if let Compression::Zstd(_) = self.compression {
+            path_vec.push(b'.');
+            path_vec.extend_from_slice(ZSTD_EXTENSION.as_bytes());
+        }
         OsString::from_vec(path_vec)
     }
 
@@ -193,6 +1111,11 @@ pub struct TraceStream {
     /// Arbitrary notion of trace time, ticked on the recording of
     /// each event (trace frame).
     pub(super) global_time: FrameTime,
+    /// DIFF NOTE: Not present in rr
+    pub(super) compression: Compression,
+    /// DIFF NOTE: Not present in rr
+    /// See `get_metadata()` and `set_metadata()`
+    pub(super) metadata: TraceMetadata,
 }
 
 #[derive(Clone, Default)]
@@ -337,9 +1260,495 @@ pub(super) fn latest_trace_symlink() -> OsString {
     OsString::from_vec(sym)
 }
 
+/// Fill `buf` as far as possible. Only returns less than `buf.len()` at EOF.
+fn read_block(f: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut nread = 0;
+    while nread < buf.len() {
+        match f.read(&mut buf[nread..])? {
+            0 => break,
+            n => nread += n,
+        }
+    }
+    Ok(nread)
+}
+
+fn sha256_of_file_region(path: &Path, offset: u64, size: usize) -> io::Result<[u8; 32]> {
+    let mut f = File::open(path)?;
+    f.seek(SeekFrom::Start(offset))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut f.take(size as u64), &mut hasher)?;
+    let mut sha256 = [0u8; 32];
+    sha256.copy_from_slice(&hasher.finalize());
+    Ok(sha256)
+}
+
+fn read_clone_or_exec_event(
+    msg: &message::Reader<capnp::serialize::OwnedSegments>,
+) -> io::Result<Option<TraceTaskEvent>> {
+    let task = msg.get_root::<task_event::Reader>().map_err(to_io_error)?;
+    let tid = task.get_tid();
+    match task.which().map_err(|e| to_io_error(e.into()))? {
+        task_event::Clone(r) => Ok(Some(TraceTaskEvent::for_clone(
+            tid,
+            r.get_parent_tid(),
+            r.get_own_ns_tid(),
+            r.get_flags(),
+        ))),
+        task_event::Exec(r) => {
+            let file_name = r.get_file_name().map_err(to_io_error)?;
+            let mut cmd_line: Vec<OsString> = Vec::new();
+            for cmd in r.get_cmd_line().map_err(to_io_error)?.iter() {
+                cmd_line.push(OsStr::from_bytes(cmd.map_err(to_io_error)?).to_os_string());
+            }
+            let mut event = TraceTaskEvent::for_exec(tid, OsStr::from_bytes(file_name), &cmd_line);
+            event
+                .exec_variant_mut()
+                .set_exe_base(r.get_exe_base().into());
+            Ok(Some(event))
+        }
+        task_event::Exit(_) => Ok(None),
+    }
+}
+
+pub(super) fn to_io_error(e: capnp::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.description)
+}
+
+pub(super) fn from_trace_arch(arch: TraceArch) -> SupportedArch {
+    match arch {
+        TraceArch::X86 => SupportedArch::X86,
+        TraceArch::X8664 => SupportedArch::X64,
+    }
+}
+
 pub(super) fn to_trace_arch(arch: SupportedArch) -> TraceArch {
     match arch {
         SupportedArch::X86 => TraceArch::X86,
         SupportedArch::X64 => TraceArch::X8664,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::{
+        test_util::{write_test_trace, TempTraceDir},
+        trace_reader::TraceReader,
+        trace_task_event::TraceTaskEventType,
+    };
+
+    #[test]
+    fn map_data_source_to_file_test() {
+        let stream = TraceStream::new(OsStr::new("/tmp"), 1);
+        let dir = PathBuf::from(stream.dir());
+        let mut m = MappedData {
+            source: MappedDataSource::SourceTrace,
+            ..Default::default()
+        };
+        assert_eq!(Some(dir.join("data")), stream.map_data_source_to_file(&m));
+
+        m.source = MappedDataSource::SourceFile;
+        m.filename = OsString::from("/usr/lib/libc.so.6");
+        assert_eq!(
+            Some(PathBuf::from("/usr/lib/libc.so.6")),
+            stream.map_data_source_to_file(&m)
+        );
+        m.filename = OsString::from("mmap_copy_1_libfoo.so");
+        assert_eq!(
+            Some(dir.join("mmap_copy_1_libfoo.so")),
+            stream.map_data_source_to_file(&m)
+        );
+
+        m.source = MappedDataSource::SourceZero;
+        assert_eq!(None, stream.map_data_source_to_file(&m));
+    }
+
+    #[test]
+    fn infer_arch_missing_trace_test() {
+        let err = TraceStream::infer_arch(OsStr::new("/nonexistent-rd-trace")).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+    }
+
+    #[test]
+    fn compare_substreams_test() {
+        let dir = TempTraceDir::new("compare");
+        let make_trace = |name: &str, data: &[u8]| {
+            let dir = dir.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("data"), data).unwrap();
+            TraceStream::new(dir.as_os_str(), 1)
+        };
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let mut modified = data.clone();
+        modified[150_000] ^= 0x1;
+        let a = make_trace("a", &data);
+        let b = make_trace("b", &data);
+        let c = make_trace("c", &modified);
+
+        let res = TraceStream::compare_substreams(&a, &b, Substream::RawData).unwrap();
+        assert!(res.identical);
+        assert_eq!(None, res.first_diff_offset);
+
+        let res = TraceStream::compare_substreams(&a, &c, Substream::RawData).unwrap();
+        assert!(!res.identical);
+        assert_eq!(Some(150_000), res.first_diff_offset);
+    }
+
+    #[test]
+    fn truncate_at_frame_test() {
+        let dir = TempTraceDir::new("truncate");
+        write_test_trace(&dir, 20, 64, 4);
+        let mut stream = TraceStream::new(dir.as_os_str(), 1);
+        assert_eq!(10, stream.truncate_at_frame(10).unwrap());
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            stream.read_frame_index().unwrap_err().kind()
+        );
+
+        // Seeking must not follow stale index entries past the new end
+        let mut reader = TraceReader::new(Some(&dir));
+        reader.seek_to_time(8).unwrap();
+        assert_eq!(8, reader.read_frame().time());
+        assert_eq!(vec![8u8; 8], reader.read_raw_data().data);
+        assert!(reader.seek_to_time(12).is_err());
+        let mut time = 0;
+        reader.rewind().unwrap();
+        while reader.read_task_event(Some(&mut time)).is_some() {}
+        assert_eq!(10, time);
+    }
+
+    #[test]
+    fn resource_usage_test() {
+        let dir = TempTraceDir::new("rusage");
+        let mut stream = TraceStream::new(dir.as_os_str(), 1);
+        assert!(stream.read_resource_usage().unwrap().is_empty());
+
+        let mut usage1: rusage = unsafe { std::mem::zeroed() };
+        usage1.ru_utime.tv_sec = 1;
+        usage1.ru_utime.tv_usec = 500;
+        usage1.ru_maxrss = 2048;
+        let mut usage2: rusage = unsafe { std::mem::zeroed() };
+        usage2.ru_stime.tv_usec = 42;
+        usage2.ru_inblock = 7;
+        usage2.ru_oublock = 9;
+        stream.write_resource_usage(100, &usage1).unwrap();
+        stream.write_resource_usage(101, &usage2).unwrap();
+
+        let all_usage = stream.read_resource_usage().unwrap();
+        assert_eq!(2, all_usage.len());
+        assert_eq!(ResourceUsage::from(&usage1), all_usage[&100]);
+        assert_eq!(1_000_500, all_usage[&100].utime_us);
+        assert_eq!(2048, all_usage[&100].maxrss_kb);
+        assert_eq!(ResourceUsage::from(&usage2), all_usage[&101]);
+        assert_eq!(7, all_usage[&101].inblock);
+    }
+
+    #[test]
+    fn mmap_file_contents_test() {
+        let dir = TempTraceDir::new("mmap-contents");
+        let mut stream = TraceStream::new(dir.as_os_str(), 1);
+
+        let mapped_file = env::temp_dir().join(format!("rd-mapped-{}.so", std::process::id()));
+        std::fs::write(&mapped_file, b"contents of mapped file").unwrap();
+        let contents = std::fs::read(&mapped_file).unwrap();
+        stream
+            .write_mmap_file_contents(mapped_file.as_os_str(), &contents)
+            .unwrap();
+        std::fs::remove_file(&mapped_file).unwrap();
+
+        assert_eq!(
+            Some(b"contents of mapped file".to_vec()),
+            stream
+                .retrieve_mmap_file_contents(mapped_file.as_os_str())
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            stream
+                .retrieve_mmap_file_contents(OsStr::new("/never/mapped"))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn validate_mmap_checksums_test() {
+        let dir = TempTraceDir::new("mmap-checksums");
+        let stream = TraceStream::new(dir.as_os_str(), 1);
+
+        // Only the second half of the file is mapped
+        let mapped_file = dir.join("libmapped.so");
+        std::fs::write(&mapped_file, b"unmapped header.original library contents").unwrap();
+        let offset = "unmapped header.".len() as u64;
+        let size = "original library contents".len();
+        let mut checksums = MmapChecksums::new();
+        checksums.insert(
+            (OsString::from("libmapped.so"), offset, size),
+            stream
+                .mmap_checksum(OsStr::new("libmapped.so"), offset, size)
+                .unwrap(),
+        );
+        stream.write_mmap_checksums(&checksums).unwrap();
+
+        let mut writer = CompressedWriter::new(
+            &stream.path(Substream::Mmaps),
+            TraceStream::mmaps_block_size(),
+            1,
+        );
+        let mut msg = message::Builder::new_default();
+        {
+            let mut map = msg.init_root::<m_map::Builder>();
+            map.set_start(0x10000);
+            map.set_end(0x10000 + size as u64);
+            map.set_file_offset_bytes(offset as i64);
+            map.set_stat_size(offset as i64 + size as i64);
+            map.get_source()
+                .init_file()
+                .set_backing_file_name(b"libmapped.so");
+        }
+        write_message(&mut writer, &msg).unwrap();
+        writer.close(None);
+
+        assert!(stream.validate_mmap_checksums().unwrap().is_empty());
+
+        // Changes outside of the mapped range don't matter
+        std::fs::write(&mapped_file, b"UNMAPPED HEADER.original library contents").unwrap();
+        assert!(stream.validate_mmap_checksums().unwrap().is_empty());
+
+        std::fs::write(&mapped_file, b"unmapped header.modified library contents").unwrap();
+        let mismatches = stream.validate_mmap_checksums().unwrap();
+        assert_eq!(1, mismatches.len());
+        assert_eq!(mapped_file.into_os_string(), mismatches[0].filename);
+        assert_ne!(mismatches[0].expected, mismatches[0].actual);
+    }
+
+    #[test]
+    fn stream_tasks_events_test() {
+        let dir = TempTraceDir::new("tasks-events");
+        let stream = TraceStream::new(dir.as_os_str(), 1);
+
+        let mut writer = CompressedWriter::new(
+            &stream.path(Substream::Tasks),
+            substream(Substream::Tasks).block_size,
+            1,
+        );
+        let mut write_event = |f: &dyn Fn(task_event::Builder)| {
+            let mut msg = message::Builder::new_default();
+            f(msg.init_root::<task_event::Builder>());
+            write_message(&mut writer, &msg).unwrap();
+        };
+        write_event(&|mut task| {
+            task.set_tid(101);
+            let mut clone = task.init_clone();
+            clone.set_parent_tid(100);
+            clone.set_own_ns_tid(101);
+        });
+        write_event(&|mut task| {
+            task.set_tid(101);
+            let mut exec = task.init_exec();
+            exec.set_file_name(b"/bin/true");
+            exec.init_cmd_line(1).set(0, b"true");
+        });
+        write_event(&|mut task| {
+            task.set_tid(101);
+            task.init_exit().set_exit_status(0);
+        });
+        writer.close(None);
+
+        let events: Vec<TraceTaskEvent> =
+            stream.stream_tasks_events().map(|e| e.unwrap()).collect();
+        assert_eq!(2, events.len());
+        assert!(events[0].event_type() == TraceTaskEventType::Clone);
+        assert_eq!(101, events[0].tid());
+        assert_eq!(100, events[0].clone_variant().parent_tid());
+        assert!(events[1].event_type() == TraceTaskEventType::Exec);
+        assert_eq!(101, events[1].tid());
+        assert_eq!(
+            OsStr::new("/bin/true"),
+            events[1].exec_variant().file_name()
+        );
+        assert_eq!(
+            &[OsString::from("true")],
+            events[1].exec_variant().cmd_line()
+        );
+    }
+
+    #[test]
+    fn binary_metadata_test() {
+        let dir = TempTraceDir::new("binary-metadata");
+        let mut stream = TraceStream::new(dir.as_os_str(), 1);
+        assert_eq!(None, stream.read_binary_metadata("config").unwrap());
+
+        let blob: Vec<u8> = (0..1024u32).map(|i| (i * 7) as u8).collect();
+        stream
+            .write_binary_metadata("config", "protobuf", &blob)
+            .unwrap();
+        assert_eq!(
+            Some(("protobuf".to_owned(), blob.clone())),
+            stream.read_binary_metadata("config").unwrap()
+        );
+
+        // Overwriting with a different type tag replaces the old blob
+        stream
+            .write_binary_metadata("config", "json", b"{}")
+            .unwrap();
+        assert_eq!(
+            Some(("json".to_owned(), b"{}".to_vec())),
+            stream.read_binary_metadata("config").unwrap()
+        );
+        assert_eq!(None, stream.read_binary_metadata("other").unwrap());
+        assert!(stream.write_binary_metadata("a.b", "bin", &blob).is_err());
+    }
+
+    #[test]
+    fn metadata_test() {
+        let dir = TempTraceDir::new("metadata");
+        let mut stream = TraceStream::new(dir.as_os_str(), 1);
+        assert_eq!(None, stream.get_metadata("bug"));
+
+        stream.set_metadata("bug", "crash in parser");
+        stream.set_metadata("commit", "abc123");
+        stream.set_metadata("commit", "def456");
+        assert_eq!(Some("def456"), stream.get_metadata("commit"));
+        // Nothing is written until the metadata is flushed
+        let unflushed = TraceStream::new(dir.as_os_str(), 1);
+        assert_eq!(None, unflushed.get_metadata("bug"));
+
+        stream.flush_metadata().unwrap();
+        let reopened = TraceStream::new(dir.as_os_str(), 1);
+        assert_eq!(Some("crash in parser"), reopened.get_metadata("bug"));
+        assert_eq!(Some("def456"), reopened.get_metadata("commit"));
+        assert_eq!(2, reopened.metadata().iter().count());
+
+        // The file is a plain JSON object of strings
+        let serialized = read_to_string(dir.join("metadata.json")).unwrap();
+        let parsed: HashMap<String, String> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!("crash in parser", parsed["bug"]);
+    }
+
+    #[test]
+    fn zstd_substream_test() {
+        let dir = TempTraceDir::new("zstd");
+        let stream = TraceStream::new(dir.as_os_str(), 1).with_compression(3);
+        let path = stream.path(Substream::Events);
+        assert!(path.as_bytes().ends_with(b"/events.zst"));
+
+        let mut writer = CompressedWriter::with_compression(
+            &path,
+            substream(Substream::Events).block_size,
+            1,
+            stream.compression(),
+            false,
+        );
+        for _ in 0..3 {
+            let mut msg = message::Builder::new_default();
+            {
+                let frame = msg.init_root::<frame::Builder>();
+                let mut sys = frame.init_event().init_syscall();
+                sys.set_number(libc::SYS_read as i32);
+                sys.set_state(TraceSyscallState::Entering);
+            }
+            write_message(&mut writer, &msg).unwrap();
+        }
+        writer.close(None);
+        assert!(writer.good());
+
+        let histogram = stream.frame_histogram().unwrap();
+        assert_eq!(3, histogram[&(libc::SYS_read as i32)]);
+        assert_eq!(
+            SupportedArch::X86,
+            TraceStream::infer_arch(dir.as_os_str()).unwrap()
+        );
+    }
+
+    #[test]
+    fn verify_integrity_test() {
+        let dir = TempTraceDir::new("verify-integrity");
+        let stream = TraceStream::new(dir.as_os_str(), 1);
+        let path = stream.path(Substream::Events);
+
+        let mut writer = CompressedWriter::with_compression(
+            &path,
+            substream(Substream::Events).block_size,
+            1,
+            Compression::Brotli,
+            true,
+        );
+        let mut msg = message::Builder::new_default();
+        {
+            let frame = msg.init_root::<frame::Builder>();
+            let mut sys = frame.init_event().init_syscall();
+            sys.set_number(libc::SYS_read as i32);
+            sys.set_state(TraceSyscallState::Entering);
+        }
+        write_message(&mut writer, &msg).unwrap();
+        writer.close(None);
+        assert!(writer.good());
+
+        assert!(stream.verify_integrity().unwrap().is_empty());
+        assert_eq!(
+            1,
+            stream.frame_histogram().unwrap()[&(libc::SYS_read as i32)]
+        );
+
+        // Flip a byte of the compressed data just after the block header
+        let mut data = read(&path).unwrap();
+        data[8] ^= 0xff;
+        remove_file(&path).unwrap();
+        write(&path, &data).unwrap();
+
+        let errors = stream.verify_integrity().unwrap();
+        assert_eq!(1, errors.len());
+        assert_eq!("events", errors[0].substream);
+        assert_eq!(0, errors[0].block);
+        assert_ne!(errors[0].expected, errors[0].actual);
+        assert!(stream.frame_histogram().is_err());
+    }
+
+    #[test]
+    fn frame_histogram_test() {
+        let dir = TempTraceDir::new("frame-histogram");
+        let stream = TraceStream::new(dir.as_os_str(), 1);
+
+        let mut writer = CompressedWriter::new(
+            &stream.path(Substream::Events),
+            substream(Substream::Events).block_size,
+            1,
+        );
+        let syscalls = [
+            libc::SYS_write,
+            libc::SYS_read,
+            libc::SYS_write,
+            libc::SYS_write,
+            libc::SYS_read,
+            libc::SYS_write,
+            libc::SYS_read,
+            libc::SYS_write,
+        ];
+        for &syscallno in syscalls.iter() {
+            for &state in &[TraceSyscallState::Entering, TraceSyscallState::Exiting] {
+                let mut msg = message::Builder::new_default();
+                {
+                    let frame = msg.init_root::<frame::Builder>();
+                    let mut sys = frame.init_event().init_syscall();
+                    sys.set_number(syscallno as i32);
+                    sys.set_state(state);
+                }
+                write_message(&mut writer, &msg).unwrap();
+            }
+        }
+        writer.close(None);
+
+        let histogram = stream.frame_histogram().unwrap();
+        assert_eq!(2, histogram.len());
+        assert_eq!(5, histogram[&(libc::SYS_write as i32)]);
+        assert_eq!(3, histogram[&(libc::SYS_read as i32)]);
+        assert_eq!(
+            vec![(libc::SYS_write as i32, 5)],
+            stream.top_n_syscalls(1).unwrap()
+        );
+        assert_eq!(
+            vec![(libc::SYS_write as i32, 5), (libc::SYS_read as i32, 3)],
+            stream.top_n_syscalls(10).unwrap()
+        );
+    }
+}
@@ -3,9 +3,9 @@ use crate::extra_registers::ExtraRegisters;
 use crate::{
     bindings::signal::siginfo_t,
     event::{Event, EventType, SignalDeterministic, SignalResolvedDisposition, SyscallState},
-    kernel_abi::{syscall_number_for_restart_syscall, RD_NATIVE_ARCH},
+    kernel_abi::{syscall_number_for_restart_syscall, SupportedArch, RD_NATIVE_ARCH},
     kernel_supplement::{btrfs_ioctl_clone_range_args, BTRFS_IOC_CLONE_, BTRFS_IOC_CLONE_RANGE_},
-    log::LogLevel::LogDebug,
+    log::LogLevel::{LogDebug, LogWarn},
     perf_counters::{PerfCounters, TicksSemantics},
     preload_interface::{mprotect_record, SYSCALLBUF_PROTOCOL_VERSION},
     registers::Registers,
@@ -17,21 +17,27 @@ use crate::{
         task::record_task::RecordTask,
     },
     trace::{
-        compressed_writer::CompressedWriter,
+        compressed_writer::{CompressedWriter, Compression},
         trace_stream::{
             latest_trace_symlink,
             make_trace_dir,
             substream,
             to_trace_arch,
+            FrameIndexEntry,
             MappedData,
             MappedDataSource,
+            MmapChecksums,
             RawDataMetadata,
             Substream,
             TraceRemoteFd,
             TraceStream,
+            DEFAULT_FRAME_INDEX_INTERVAL,
             SUBSTREAMS,
+            SUBSTREAM_COUNT,
             TRACE_VERSION,
+            ZSTD_VERSION_MARKER,
         },
+        trace_frame::FrameTime,
         trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
     },
     trace_capnp::{
@@ -83,6 +89,7 @@ use std::{
     },
     path::Path,
     slice,
+    time::SystemTime,
 };
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -158,6 +165,18 @@ pub struct TraceWriter {
     mmap_count: u32,
     has_cpuid_faulting_: bool,
     supports_file_data_cloning_: bool,
+    /// DIFF NOTE: Not present in rr
+    /// See `set_frame_index_interval()`
+    frame_index_interval: u64,
+    /// DIFF NOTE: Not present in rr
+    /// The time of each frame index entry and the (uncompressed) position of each
+    /// substream at the start of that frame. Turned into `FrameIndexEntry`s
+    /// once the substreams are closed, see `write_frame_index()`
+    frame_index: Vec<(FrameTime, [u64; SUBSTREAM_COUNT])>,
+    /// DIFF NOTE: Not present in rr
+    /// Checksums of the mapped parts of the files assumed to be immutable.
+    /// Written to the trace by `close()`, see `note_mmap_checksum()`
+    mmap_checksums: MmapChecksums,
 }
 
 impl Deref for TraceWriter {
@@ -321,7 +340,52 @@ impl TraceWriter {
             Ok(_) => (),
         }
 
-        self.tick_time()
+        self.tick_time();
+
+        // Everything written from now on belongs to the frame at `time()`
+        if self.frame_index_interval > 0 && (self.time() - 1) % self.frame_index_interval == 0 {
+            let mut positions = [0u64; SUBSTREAM_COUNT];
+            for &s in SUBSTREAMS.iter() {
+                positions[s as usize] = self.writer(s).uncompressed_pos();
+            }
+            self.frame_index.push((self.time(), positions));
+        }
+    }
+
+    /// Record a frame index entry every `interval` frames so that replay can
+    /// seek quickly, see `TraceReader::seek_to_time()`. An interval of 0 turns the
+    /// index off. Defaults to `DEFAULT_FRAME_INDEX_INTERVAL`.
+    ///
+    /// DIFF NOTE: Not present in rr
+    pub fn set_frame_index_interval(&mut self, interval: u64) {
+        self.frame_index_interval = interval;
+    }
+
+    /// Remember the checksum of the part of `backing_file_name` that `km` maps so
+    /// that replay can tell whether it changed, see
+    /// `TraceStream::validate_mmap_checksums()`. Each range is hashed only once.
+    ///
+    /// DIFF NOTE: Not present in rr
+    fn note_mmap_checksum(&mut self, backing_file_name: &OsStr, km: &KernelMapping) {
+        let key = (
+            backing_file_name.to_owned(),
+            km.file_offset_bytes(),
+            km.size(),
+        );
+        if self.mmap_checksums.contains_key(&key) {
+            return;
+        }
+        match self.mmap_checksum(backing_file_name, key.1, key.2) {
+            Ok(sha256) => {
+                self.mmap_checksums.insert(key, sha256);
+            }
+            Err(e) => log!(
+                LogWarn,
+                "Could not checksum {:?}: {:?}",
+                backing_file_name,
+                e
+            ),
+        }
     }
 
     /// Write mapped-region record to the trace.
@@ -390,9 +454,11 @@ impl TraceWriter {
                     .get(&(stat.st_dev, stat.st_ino));
 
                 if assumed_immutable.is_some() {
+                    let backing_file_name = assumed_immutable.unwrap().clone();
                     src.reborrow()
                         .init_file()
-                        .set_backing_file_name(assumed_immutable.unwrap().as_bytes());
+                        .set_backing_file_name(backing_file_name.as_bytes());
+                    self.note_mmap_checksum(&backing_file_name, km);
                 } else if km.flags().contains(MapFlags::MAP_PRIVATE)
                     && self.try_clone_file(t, &file_name, &mut backing_file_name)
                 {
@@ -435,6 +501,9 @@ impl TraceWriter {
                             // contains the pid of a recorded process and will not work!
                             backing_file_name = km.fsname().to_owned();
                         }
+                        // The file may still be modified in place, so remember its
+                        // checksum to be able to tell at replay.
+                        self.note_mmap_checksum(&backing_file_name, km);
                         self.files_assumed_immutable
                             .insert((stat.st_dev, stat.st_ino), backing_file_name.clone());
                     }
@@ -576,9 +645,14 @@ impl TraceWriter {
         bind_to_cpu: Option<u32>,
         output_trace_dir: Option<&OsStr>,
         ticks_semantics_: TicksSemantics,
+        compression: Compression,
     ) -> TraceWriter {
+        let mut trace_stream = TraceStream::new(&make_trace_dir(file_name, output_trace_dir), 1);
+        if let Compression::Zstd(level) = compression {
+            trace_stream = trace_stream.with_compression(level);
+        }
         let mut tw = TraceWriter {
-            trace_stream: TraceStream::new(&make_trace_dir(file_name, output_trace_dir), 1),
+            trace_stream,
             ticks_semantics_,
             mmap_count: 0,
             has_cpuid_faulting_: false,
@@ -588,14 +662,29 @@ impl TraceWriter {
             cpuid_records: vec![],
             version_fd: ScopedFd::new(),
             supports_file_data_cloning_: false,
+            frame_index_interval: DEFAULT_FRAME_INDEX_INTERVAL,
+            frame_index: Vec::new(),
+            mmap_checksums: MmapChecksums::new(),
         };
 
         tw.bind_to_cpu = bind_to_cpu;
+        // See `close()` for the other predefined metadata keys
+        tw.set_metadata("rd.exe", &file_name.to_string_lossy());
+        let record_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        tw.set_metadata("rd.record_time", &record_time.to_string());
 
         for &s in Substream::iter() {
             tw.writers.insert(
                 s,
-                CompressedWriter::new(&tw.path(s), substream(s).block_size, substream(s).threads),
+                CompressedWriter::with_compression(
+                    &tw.path(s),
+                    substream(s).block_size,
+                    substream(s).threads,
+                    compression,
+                    substream(s).checksum_enabled,
+                ),
             );
         }
 
@@ -616,7 +705,10 @@ impl TraceWriter {
             Ok(_) => (),
         }
 
-        let buf = format!("{}\n", TRACE_VERSION);
+        let buf = match compression {
+            Compression::Brotli => format!("{}\n", TRACE_VERSION),
+            Compression::Zstd(_) => format!("{} {}\n", TRACE_VERSION, ZSTD_VERSION_MARKER),
+        };
         write_all(tw.version_fd.as_raw(), buf.as_bytes());
 
         // Test if file data cloning is supported
@@ -684,10 +776,34 @@ impl TraceWriter {
     ///  call this before a crash that won't call the destructor, to ensure
     ///  buffered data is flushed.
     /// If `uuid` is `None` then a uuid will be generated for you.
+    ///
+    /// DIFF NOTE: Also writes the trace metadata, adding the predefined keys
+    /// "rd.version" and "rd.arch". "rd.exe" and "rd.record_time" (seconds since
+    /// the epoch at which recording started) are set by `new()`.
     pub fn close(&mut self, status: CloseStatus, maybe_uuid: Option<TraceUuid>) {
+        let mut closed_writers: HashMap<Substream, CompressedWriter> = HashMap::new();
         for s in &SUBSTREAMS {
             let mut w = self.writers.remove(s).unwrap();
             w.close(None);
+            closed_writers.insert(*s, w);
+        }
+        self.write_frame_index(&closed_writers);
+
+        self.set_metadata("rd.version", env!("CARGO_PKG_VERSION"));
+        let arch_name = match RD_NATIVE_ARCH {
+            SupportedArch::X86 => "x86",
+            SupportedArch::X64 => "x86_64",
+        };
+        self.set_metadata("rd.arch", arch_name);
+        match self.flush_metadata() {
+            Err(e) => log!(LogWarn, "Unable to write trace metadata: {:?}", e),
+            Ok(_) => (),
+        }
+        if !self.mmap_checksums.is_empty() {
+            match self.write_mmap_checksums(&self.mmap_checksums) {
+                Err(e) => log!(LogWarn, "Unable to write mmap checksums: {:?}", e),
+                Ok(_) => (),
+            }
         }
 
         let mut header_msg = message::Builder::new_default();
@@ -867,6 +983,34 @@ impl TraceWriter {
         copy_file(dest.as_raw(), src.as_raw())
     }
 
+    /// The frame index is only an optimization for replay so failing to write
+    /// it is not fatal.
+    fn write_frame_index(&self, closed_writers: &HashMap<Substream, CompressedWriter>) {
+        if self.frame_index.is_empty() {
+            return;
+        }
+
+        let entries: Vec<FrameIndexEntry> = self
+            .frame_index
+            .iter()
+            .map(|&(time, uncompressed_positions)| {
+                let mut entry = FrameIndexEntry {
+                    time,
+                    ..Default::default()
+                };
+                for &s in SUBSTREAMS.iter() {
+                    entry.positions[s as usize] =
+                        closed_writers[&s].stream_position(uncompressed_positions[s as usize]);
+                }
+                entry
+            })
+            .collect();
+        match self.trace_stream.write_frame_index(&entries) {
+            Err(e) => log!(LogWarn, "Unable to write frame index: {:?}", e),
+            Ok(_) => (),
+        }
+    }
+
     fn writer(&self, s: Substream) -> &CompressedWriter {
         self.writers.get(&s).unwrap()
     }